@@ -0,0 +1,5 @@
+fn main() {
+	#[cfg(feature = "grpc")]
+	tonic_build::compile_protos("proto/mah_ts_identity.proto")
+		.expect("Failed to compile the protobuf definitions");
+}
@@ -0,0 +1,112 @@
+//! A json HTTP API to run searches.
+//!
+//! - `POST /jobs` starts a job, the body is either
+//!   `{"type": "find", "patterns": ["abc"], "max_matches": 1}` or
+//!   `{"type": "level", "identity": "...", "level": 30}`
+//! - `GET /jobs` lists all jobs
+//! - `GET /jobs/<id>` returns the progress and results of a job
+//! - `DELETE /jobs/<id>` stops a job
+//!
+//! Find jobs stop after 1000 identities, finished jobs are removed after an hour.
+//!
+//! Every request needs the printed token in an 'Authorization: Bearer <token>' header. Set
+//! 'MAHTS_API_TOKEN' to choose it.
+
+use std::net::TcpListener;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::control::is_interrupted;
+use crate::jobs::{JobStatus, Jobs};
+use crate::token::Token;
+use crate::{Outcome, Result};
+
+/// Serves on `listener` if it was bound already, otherwise binds `addr`.
+pub fn tool_api(addr: &str, listener: Option<TcpListener>) -> Result {
+	let server = match listener {
+		Some(listener) => Server::from_listener(listener, None),
+		None => Server::http(addr),
+	}
+	.map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+	info!("Listening on http://{}", addr);
+	let token = Token::from_env("MAHTS_API_TOKEN");
+	// Not logged, the log file and syslog may be readable by others
+	eprintln!("API token: {}", token.as_str());
+	let jobs = Jobs::default();
+
+	while !is_interrupted() {
+		let mut request = match server.recv_timeout(Duration::from_millis(100)) {
+			Ok(Some(request)) => request,
+			Ok(None) => continue,
+			Err(e) => {
+				warn!("Failed to receive request: {}", e);
+				continue;
+			}
+		};
+		debug!("{} {}", request.method(), request.url());
+		if !token.accepts(&request) {
+			respond(request, 401, &json!({ "error": "Requires the token" }));
+			continue;
+		}
+		let path = request
+			.url()
+			.trim_end_matches('/')
+			.split('/')
+			.skip(1)
+			.map(str::to_string)
+			.collect::<Vec<_>>();
+		let path = path.iter().map(String::as_str).collect::<Vec<_>>();
+		let method = request.method().clone();
+		let (status, body) = match (&method, path.as_slice()) {
+			(Method::Post, ["jobs"]) => {
+				let mut body = String::new();
+				let id = std::io::Read::read_to_string(request.as_reader(), &mut body)
+					.map_err(|e| e.to_string())
+					.and_then(|_| serde_json::from_str(&body).map_err(|e| e.to_string()))
+					.and_then(|job| jobs.start(job));
+				match id {
+					Ok(id) => {
+						info!("Started job {}", id);
+						(201, json!({ "id": id }))
+					}
+					Err(e) => (400, json!({ "error": e })),
+				}
+			}
+			(Method::Get, ["jobs"]) => (200, json!(jobs.list())),
+			(Method::Get, ["jobs", id]) => {
+				job_response(id.parse().ok().and_then(|id| jobs.get(id)))
+			}
+			(Method::Delete, ["jobs", id]) => {
+				info!("Stopping job {}", id);
+				job_response(id.parse().ok().and_then(|id| jobs.stop(id)))
+			}
+			(_, ["jobs", _]) => (405, json!({ "error": "Method not allowed" })),
+			_ => (404, json!({ "error": "Not found" })),
+		};
+		respond(request, status, &body);
+	}
+	jobs.stop_all();
+	Ok(Outcome::Success)
+}
+
+fn job_response(status: Option<JobStatus>) -> (u16, Value) {
+	match status {
+		Some(status) => (200, json!(status)),
+		None => (404, json!({ "error": "Unknown job" })),
+	}
+}
+
+fn respond(request: tiny_http::Request, status: u16, body: &Value) {
+	let response = Response::from_string(body.to_string())
+		.with_status_code(status)
+		.with_header(
+			Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..])
+				.expect("Header is valid"),
+		);
+	if let Err(e) = request.respond(response) {
+		warn!("Failed to send response: {}", e);
+	}
+}
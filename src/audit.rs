@@ -0,0 +1,95 @@
+//! An append-only log of where and how identities were generated, without their keys.
+//!
+//! Every line is a json object with the time, tool, uid, level, host, version and the
+//! command line. Secret options in the command line are replaced by `***`.
+
+use std::ffi::OsString;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use log::error;
+use serde_json::json;
+
+/// Options whose values are never written to the log.
+const SECRET_OPTIONS: &[&str] = &["identity", "mnemonic", "raw-key", "seed", "telegram-token"];
+const SECRET_SHORTS: &[char] = &['i'];
+
+#[derive(Debug)]
+pub struct AuditLog {
+	file: Mutex<File>,
+	host: String,
+	args: Vec<String>,
+}
+
+impl AuditLog {
+	pub fn open(path: &Path) -> Result<Self, String> {
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.map_err(|e| format!("Failed to open audit log {}: {}", path.display(), e))?;
+		Ok(Self {
+			file: Mutex::new(file),
+			host: gethostname::gethostname().to_string_lossy().into_owned(),
+			args: redact(std::env::args_os().skip(1)),
+		})
+	}
+
+	/// Records a generated identity, `offset` is the hashcash offset of the level.
+	pub fn record(&self, tool: &str, uid: &str, level: u8, offset: u64) {
+		let line = json!({
+			"timestamp": humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+			"tool": tool,
+			"uid": uid,
+			"level": level,
+			"offset": offset,
+			"host": self.host,
+			"version": env!("CARGO_PKG_VERSION"),
+			"args": self.args,
+		});
+		let mut file = self.file.lock().unwrap();
+		if let Err(e) = writeln!(file, "{}", line).and_then(|_| file.sync_data()) {
+			error!("Failed to write audit log: {}", e);
+		}
+	}
+}
+
+/// Replaces the values of secret options.
+fn redact(args: impl Iterator<Item = OsString>) -> Vec<String> {
+	let mut redacted = Vec::new();
+	let mut hide_next = false;
+	for arg in args.map(|a| a.to_string_lossy().into_owned()) {
+		if hide_next {
+			hide_next = false;
+			redacted.push("***".to_string());
+			continue;
+		}
+		if let Some(long) = arg.strip_prefix("--") {
+			let (name, value) = match long.split_once('=') {
+				Some((name, _)) => (name, true),
+				None => (long, false),
+			};
+			if SECRET_OPTIONS.contains(&name) {
+				if value {
+					redacted.push(format!("--{}=***", name));
+					continue;
+				}
+				hide_next = true;
+			}
+		} else if let Some(shorts) = arg.strip_prefix('-') {
+			// The value is the rest of the argument or the next argument
+			if let Some(pos) = shorts.find(SECRET_SHORTS) {
+				if pos + 1 < shorts.len() {
+					redacted.push(format!("-{}***", &shorts[..=pos]));
+					continue;
+				}
+				hide_next = true;
+			}
+		}
+		redacted.push(arg);
+	}
+	redacted
+}
@@ -0,0 +1,98 @@
+//! Keeps the system from going to sleep while searching with '--keep-awake'.
+//!
+//! Linux uses 'systemd-inhibit', macOS 'caffeinate' and Windows `SetThreadExecutionState`.
+//! The lock is released when the search ends, also if the process is killed.
+
+use log::{info, warn};
+
+/// Holds the sleep inhibition until it is dropped.
+#[derive(Debug)]
+pub struct KeepAwake {
+	#[cfg(any(target_os = "linux", target_os = "macos"))]
+	child: std::process::Child,
+}
+
+impl KeepAwake {
+	/// Returns `None` and warns if sleep can not be prevented on this system.
+	pub fn start() -> Option<Self> {
+		match Self::inhibit() {
+			Ok(awake) => {
+				info!("Preventing system sleep while searching");
+				Some(awake)
+			}
+			Err(e) => {
+				warn!("Can not prevent system sleep: {}", e);
+				None
+			}
+		}
+	}
+
+	#[cfg(target_os = "linux")]
+	fn inhibit() -> Result<Self, String> {
+		use std::process::{Command, Stdio};
+
+		// 'cat' waits for its stdin, which is closed when this process exits in any way
+		let child = Command::new("systemd-inhibit")
+			.args([
+				"--what=sleep:idle",
+				"--who=mahtsidentity",
+				"--why=Searching identities",
+				"--mode=block",
+				"cat",
+			])
+			.stdin(Stdio::piped())
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+			.map_err(|e| format!("Failed to run systemd-inhibit: {}", e))?;
+		Ok(Self { child })
+	}
+
+	#[cfg(target_os = "macos")]
+	fn inhibit() -> Result<Self, String> {
+		use std::process::{Command, Stdio};
+
+		// caffeinate holds an IOKit power assertion until this process exits
+		let child = Command::new("caffeinate")
+			.args(["-i", "-w", &std::process::id().to_string()])
+			.stdout(Stdio::null())
+			.stderr(Stdio::null())
+			.spawn()
+			.map_err(|e| format!("Failed to run caffeinate: {}", e))?;
+		Ok(Self { child })
+	}
+
+	/// The state belongs to the main thread, which lives as long as the search.
+	#[cfg(windows)]
+	fn inhibit() -> Result<Self, String> {
+		use windows_sys::Win32::System::Power::{
+			SetThreadExecutionState, ES_CONTINUOUS, ES_SYSTEM_REQUIRED,
+		};
+
+		if unsafe { SetThreadExecutionState(ES_CONTINUOUS | ES_SYSTEM_REQUIRED) } == 0 {
+			return Err("SetThreadExecutionState failed".to_string());
+		}
+		Ok(Self {})
+	}
+
+	#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+	fn inhibit() -> Result<Self, String> {
+		Err("Not supported on this system".to_string())
+	}
+}
+
+impl Drop for KeepAwake {
+	fn drop(&mut self) {
+		#[cfg(any(target_os = "linux", target_os = "macos"))]
+		{
+			let _ = self.child.kill();
+			let _ = self.child.wait();
+		}
+		#[cfg(windows)]
+		unsafe {
+			windows_sys::Win32::System::Power::SetThreadExecutionState(
+				windows_sys::Win32::System::Power::ES_CONTINUOUS,
+			);
+		}
+	}
+}
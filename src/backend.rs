@@ -0,0 +1,129 @@
+//! The implementations which generate key pairs.
+//!
+//! ring is the fastest, but does not build on every target. The pure Rust p256 crate builds
+//! everywhere, build with `--no-default-features` to leave out ring.
+
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::SecretKey;
+use tsproto_types::crypto::{EccKeyPrivP256, EccKeyPubP256};
+use zeroize::Zeroizing;
+
+use crate::rng;
+
+static SELECTED: AtomicU8 = AtomicU8::new(BackendKind::DEFAULT as u8);
+
+/// A key pair, the private scalar and the uncompressed public point.
+pub type KeyPair = (Zeroizing<Vec<u8>>, Vec<u8>);
+
+pub trait Backend: Sync {
+	fn generate(&self) -> KeyPair;
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum BackendKind {
+	#[cfg(feature = "ring")]
+	/// ring, the fastest.
+	Ring,
+	/// The pure Rust p256 crate.
+	P256,
+}
+
+impl BackendKind {
+	#[cfg(feature = "ring")]
+	const DEFAULT: Self = Self::Ring;
+	#[cfg(not(feature = "ring"))]
+	const DEFAULT: Self = Self::P256;
+
+	fn backend(self) -> &'static dyn Backend {
+		match self {
+			#[cfg(feature = "ring")]
+			Self::Ring => &Ring,
+			Self::P256 => &P256,
+		}
+	}
+}
+
+#[cfg(feature = "ring")]
+struct Ring;
+
+#[cfg(feature = "ring")]
+impl Backend for Ring {
+	fn generate(&self) -> KeyPair {
+		use flakebi_ring::signature;
+		let (priv_key, pub_key) = signature::EcdsaKeyPair::generate_key_pair(
+			&signature::ECDSA_P256_SHA256_ASN1_SIGNING,
+			&flakebi_ring::rand::SystemRandom::new(),
+		)
+		.unwrap();
+		(Zeroizing::new(priv_key), pub_key)
+	}
+}
+
+struct P256;
+
+impl Backend for P256 {
+	fn generate(&self) -> KeyPair {
+		key_pair(&rng::secret_key())
+	}
+}
+
+fn key_pair(secret: &SecretKey) -> KeyPair {
+	let pub_key = secret.public_key().to_encoded_point(false);
+	(
+		Zeroizing::new(secret.to_be_bytes().to_vec()),
+		pub_key.as_bytes().to_vec(),
+	)
+}
+
+/// Sets the backend for all following key generations.
+pub fn select(kind: BackendKind) {
+	SELECTED.store(kind as u8, Ordering::Relaxed);
+}
+
+pub fn selected() -> BackendKind {
+	let selected = SELECTED.load(Ordering::Relaxed);
+	<BackendKind as clap::ValueEnum>::value_variants()
+		.iter()
+		.copied()
+		.find(|k| *k as u8 == selected)
+		.unwrap_or(BackendKind::DEFAULT)
+}
+
+/// Generates a key pair with the selected backend.
+fn generate() -> KeyPair {
+	selected().backend().generate()
+}
+
+/// Generates a key pair with the given backend, for benchmarks.
+pub fn generate_with(kind: BackendKind) -> KeyPair {
+	kind.backend().generate()
+}
+
+/// Generates the key with this number with '--seed', else a random one.
+fn generate_at(index: u64) -> KeyPair {
+	match rng::seeded_key(index) {
+		Some(secret) => key_pair(&secret),
+		None => generate(),
+	}
+}
+
+/// Generates the key with this number with '--seed', else a random one.
+pub fn generate_key_at(index: u64) -> EccKeyPrivP256 {
+	let (priv_key, _) = generate_at(index);
+	EccKeyPrivP256::from_short(&priv_key).unwrap()
+}
+
+/// Generates a key pair and decodes the public key.
+pub fn generate_pub() -> (Zeroizing<Vec<u8>>, EccKeyPubP256) {
+	let (priv_key, pub_key) = generate();
+	(priv_key, EccKeyPubP256::from_short(&pub_key).unwrap())
+}
+
+/// Like `generate_pub`, but derives the key with this number with '--seed'.
+pub fn generate_pub_at(index: u64) -> (Zeroizing<Vec<u8>>, EccKeyPubP256) {
+	let (priv_key, pub_key) = generate_at(index);
+	(priv_key, EccKeyPubP256::from_short(&pub_key).unwrap())
+}
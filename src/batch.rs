@@ -0,0 +1,65 @@
+use std::time::{Duration, Instant};
+
+use log::debug;
+
+/// The size of the first batch when the size is adapted, small enough to finish quickly on slow
+/// machines.
+const INITIAL_SIZE: u64 = 10_000;
+/// A batch grows at most by this factor at once, so a single fast batch cannot overshoot.
+const MAX_GROWTH: f64 = 8.0;
+
+/// Decides how many keys or offsets are processed in one batch.
+///
+/// With a fixed size, every batch has the same size. Otherwise the size is adapted after each
+/// batch, so a batch takes roughly `target` wall time, independent of the hardware.
+#[derive(Clone, Copy, Debug)]
+pub struct BatchSizer {
+	size: u64,
+	/// `None` if the size is fixed.
+	target: Option<Duration>,
+	start: Option<Instant>,
+}
+
+impl BatchSizer {
+	pub fn fixed(size: u64) -> Self {
+		Self {
+			size,
+			target: None,
+			start: None,
+		}
+	}
+
+	pub fn adaptive(target: Duration) -> Self {
+		Self {
+			size: INITIAL_SIZE,
+			target: Some(target),
+			start: None,
+		}
+	}
+
+	/// Starts a new batch and returns its size.
+	pub fn start(&mut self) -> u64 {
+		if self.target.is_some() {
+			self.start = Some(Instant::now());
+		}
+		self.size
+	}
+
+	/// Finishes the current batch and adapts the size of the next one.
+	pub fn finish(&mut self) {
+		let (target, start) = match (self.target, self.start.take()) {
+			(Some(target), Some(start)) => (target, start),
+			_ => return,
+		};
+		let elapsed = start.elapsed().as_secs_f64().max(0.001);
+		let factor = (target.as_secs_f64() / elapsed).min(MAX_GROWTH);
+		let size = ((self.size as f64 * factor) as u64).max(1);
+		if size != self.size {
+			debug!(
+				"Batch of {} took {:.2}s, next batch has {}",
+				self.size, elapsed, size
+			);
+			self.size = size;
+		}
+	}
+}
@@ -0,0 +1,227 @@
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use log::info;
+use rayon::prelude::*;
+use sha1::{Digest, Sha1};
+use tsproto_types::crypto::{EccKeyPrivP256, EccKeyPubP256};
+
+use crate::backend::{self, BackendKind};
+use crate::calibration;
+use crate::progress::human_count;
+use crate::sha1x;
+use crate::{Outcome, Result};
+
+/// Operations between two checks of the elapsed time.
+const CHUNK: u64 = 64;
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+	#[clap(short, long, value_parser = humantime::parse_duration, default_value = "3s")]
+	/// How long each primitive is measured.
+	duration: Duration,
+
+	#[clap(short, long)]
+	/// Number of threads to use, defaults to the global thread count.
+	threads: Option<usize>,
+
+	#[clap(long)]
+	/// Also measures key generation with the backends which are not selected.
+	compare_backends: bool,
+
+	#[clap(long)]
+	/// Measures the throughput with 1, 2, 4, … up to the thread count instead.
+	scaling: bool,
+}
+
+pub fn tool_bench(args: BenchArgs) -> Result {
+	let threads = args.threads.unwrap_or_else(rayon::current_num_threads);
+	if args.scaling {
+		return bench_scaling(args.duration, threads);
+	}
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.build()
+		.map_err(|e| format!("Failed to create thread pool: {}", e))?;
+	info!(
+		"Measuring each primitive for {} on {} threads",
+		humantime::format_duration(args.duration),
+		threads
+	);
+	info!("Hashing uids with {}", sha1x::implementation());
+
+	let selected = backend::selected();
+	let (_, pub_key) = backend::generate_with(selected);
+	let omega = EccKeyPubP256::from_short(&pub_key).unwrap().to_ts();
+
+	let mut results = vec![
+		(
+			generation_name(selected),
+			measure(&pool, args.duration, |_| {
+				black_box(backend::generate_with(selected));
+			}),
+		),
+		(
+			"public key encoding".to_string(),
+			measure(&pool, args.duration, |_| {
+				black_box(EccKeyPubP256::from_short(&pub_key).unwrap().to_ts());
+			}),
+		),
+		(
+			"uid hashing (sha1)".to_string(),
+			measure(&pool, args.duration, |_| {
+				black_box(Sha1::digest(omega.as_bytes()));
+			}),
+		),
+		(
+			"pattern candidates".to_string(),
+			measure(&pool, args.duration, |_| candidate()),
+		),
+		(
+			"hashcash level".to_string(),
+			hashcash_rate(&pool, args.duration, &omega),
+		),
+	];
+	if args.compare_backends {
+		for &kind in BackendKind::value_variants()
+			.iter()
+			.filter(|k| **k != selected)
+		{
+			results.insert(
+				1,
+				(
+					generation_name(kind),
+					measure(&pool, args.duration, |_| {
+						black_box(backend::generate_with(kind));
+					}),
+				),
+			);
+		}
+	}
+
+	let rate_of = |name: &str| results.iter().find(|(n, _)| *n == name).map(|(_, r)| *r);
+	calibration::store(
+		threads,
+		rate_of("pattern candidates"),
+		rate_of("hashcash level"),
+	);
+
+	println!("{:<24} {:>12} {:>12}", "Primitive", "ops/s", "per thread");
+	for (name, rate) in results {
+		println!(
+			"{:<24} {:>12} {:>12}",
+			name,
+			human_count(rate),
+			human_count(rate / threads as f64)
+		);
+	}
+	Ok(Outcome::Success)
+}
+
+pub fn generation_name(kind: BackendKind) -> String {
+	let name = kind.to_possible_value().map(|v| v.get_name().to_string());
+	format!("key generation ({})", name.unwrap_or_default())
+}
+
+/// Generates and hashes a key like the pattern search.
+pub fn candidate() {
+	let (_, pub_key) = backend::generate_pub();
+	black_box(Sha1::digest(pub_key.to_ts().as_bytes()));
+}
+
+/// Prints how the pattern search and the hashcash throughput scale with the number of threads.
+fn bench_scaling(duration: Duration, max_threads: usize) -> Result {
+	let mut counts = Vec::new();
+	let mut n = 1;
+	while n < max_threads {
+		counts.push(n);
+		n *= 2;
+	}
+	counts.push(max_threads);
+
+	let omega = EccKeyPrivP256::create().to_pub().to_ts();
+	println!(
+		"{:>7} {:>12} {:>10} {:>12} {:>10}",
+		"threads", "keys/s", "efficiency", "hashcash/s", "efficiency"
+	);
+	let mut base = None;
+	for threads in counts {
+		let pool = rayon::ThreadPoolBuilder::new()
+			.num_threads(threads)
+			.build()
+			.map_err(|e| format!("Failed to create thread pool: {}", e))?;
+		info!("Measuring {} threads", threads);
+		let keys = measure(&pool, duration, |_| candidate());
+		let hashes = hashcash_rate(&pool, duration, &omega);
+		// Efficiency compares to a perfect linear scaling of the single thread throughput
+		let (base_keys, base_hashes) = *base.get_or_insert((keys, hashes));
+		println!(
+			"{:>7} {:>12} {:>9.0}% {:>12} {:>9.0}%",
+			threads,
+			human_count(keys),
+			keys / (base_keys * threads as f64) * 100.0,
+			human_count(hashes),
+			hashes / (base_hashes * threads as f64) * 100.0
+		);
+	}
+	Ok(Outcome::Success)
+}
+
+/// Checked offsets per second, like the level search checks them.
+pub fn hashcash_rate(pool: &rayon::ThreadPool, duration: Duration, omega: &str) -> f64 {
+	let midstate = sha1x::Midstate::new(omega);
+	let lanes = sha1x::LANES as u64;
+	measure(pool, duration, |i| {
+		black_box(midstate.levels(&std::array::from_fn(|l| i * lanes + l as u64)));
+	}) * lanes as f64
+}
+
+/// Measures the hashcash rate on all threads and stores it for later runs.
+pub fn measure_hashcash(duration: Duration) -> std::result::Result<f64, String> {
+	let threads = rayon::current_num_threads();
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.build()
+		.map_err(|e| format!("Failed to create thread pool: {}", e))?;
+	let omega = EccKeyPrivP256::create().to_pub().to_ts();
+	let rate = hashcash_rate(&pool, duration, &omega);
+	calibration::store(threads, None, Some(rate));
+	Ok(rate)
+}
+
+/// Measures the key rate of the pattern search on all threads and stores it for later runs.
+pub fn measure_keys(duration: Duration) -> std::result::Result<f64, String> {
+	let threads = rayon::current_num_threads();
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.build()
+		.map_err(|e| format!("Failed to create thread pool: {}", e))?;
+	let rate = measure(&pool, duration, |_| candidate());
+	calibration::store(threads, Some(rate), None);
+	Ok(rate)
+}
+
+/// Runs `f` on every thread of the pool for `duration` and returns the operations per second.
+///
+/// `f` gets a number which is unique for every call.
+pub fn measure<F: Fn(u64) + Sync>(pool: &rayon::ThreadPool, duration: Duration, f: F) -> f64 {
+	let threads = pool.current_num_threads() as u64;
+	let start = Instant::now();
+	let ops: u64 = pool.install(|| {
+		(0..threads)
+			.into_par_iter()
+			.map(|t| {
+				let mut n = 0;
+				while start.elapsed() < duration {
+					for _ in 0..CHUNK {
+						f((t << 40) + n);
+						n += 1;
+					}
+				}
+				n
+			})
+			.sum()
+	});
+	ops as f64 / start.elapsed().as_secs_f64()
+}
@@ -0,0 +1,117 @@
+use std::path::PathBuf;
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+/// Benchmark results of one machine configuration.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct Calibration {
+	pub cpu: String,
+	pub threads: usize,
+	/// Pattern search candidates per second.
+	pub keys_per_sec: Option<f64>,
+	/// Checked hashcash offsets per second.
+	pub hashcash_per_sec: Option<f64>,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct CacheFile {
+	entries: Vec<Calibration>,
+}
+
+/// The model name of the cpu, this identifies the calibration together with the thread count.
+pub fn cpu_model() -> String {
+	read_cpu_model().unwrap_or_else(|| format!("unknown {}", std::env::consts::ARCH))
+}
+
+#[cfg(target_os = "linux")]
+fn read_cpu_model() -> Option<String> {
+	let info = std::fs::read_to_string("/proc/cpuinfo").ok()?;
+	info.lines()
+		.find(|l| l.starts_with("model name"))
+		.and_then(|l| l.split_once(':'))
+		.map(|(_, m)| m.trim().to_string())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn read_cpu_model() -> Option<String> {
+	None
+}
+
+fn cache_path() -> Option<PathBuf> {
+	Some(
+		dirs::cache_dir()?
+			.join("mah_ts_identity")
+			.join("bench.json"),
+	)
+}
+
+fn read_cache() -> CacheFile {
+	let path = match cache_path() {
+		Some(p) => p,
+		None => return CacheFile::default(),
+	};
+	match std::fs::read_to_string(&path) {
+		Ok(s) => serde_json::from_str(&s).unwrap_or_else(|e| {
+			warn!("Ignoring broken benchmark cache {}: {}", path.display(), e);
+			CacheFile::default()
+		}),
+		Err(_) => CacheFile::default(),
+	}
+}
+
+/// Returns the stored benchmark results for this cpu and thread count.
+pub fn load(threads: usize) -> Option<Calibration> {
+	let cpu = cpu_model();
+	read_cache()
+		.entries
+		.into_iter()
+		.find(|c| c.cpu == cpu && c.threads == threads)
+}
+
+/// Stores benchmark results for this cpu and thread count, results which are `None` keep their
+/// previous value.
+pub fn store(threads: usize, keys_per_sec: Option<f64>, hashcash_per_sec: Option<f64>) {
+	let path = match cache_path() {
+		Some(p) => p,
+		None => return,
+	};
+	let cpu = cpu_model();
+	let mut cache = read_cache();
+	let entry = match cache
+		.entries
+		.iter_mut()
+		.position(|c| c.cpu == cpu && c.threads == threads)
+	{
+		Some(i) => &mut cache.entries[i],
+		None => {
+			cache.entries.push(Calibration {
+				cpu,
+				threads,
+				keys_per_sec: None,
+				hashcash_per_sec: None,
+			});
+			cache.entries.last_mut().unwrap()
+		}
+	};
+	entry.keys_per_sec = keys_per_sec.or(entry.keys_per_sec);
+	entry.hashcash_per_sec = hashcash_per_sec.or(entry.hashcash_per_sec);
+
+	let res = path
+		.parent()
+		.map_or(Ok(()), std::fs::create_dir_all)
+		.and_then(|_| {
+			std::fs::write(
+				&path,
+				serde_json::to_string_pretty(&cache).expect("Cache is always serializable"),
+			)
+		});
+	match res {
+		Ok(()) => debug!("Stored benchmark results in {}", path.display()),
+		Err(e) => warn!(
+			"Failed to store benchmark results in {}: {}",
+			path.display(),
+			e
+		),
+	}
+}
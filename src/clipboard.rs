@@ -0,0 +1,24 @@
+//! Copies results to the system clipboard, for pasting them into the TeamSpeak import.
+
+use log::{info, warn};
+
+/// Puts the text on the clipboard, failures are only logged.
+///
+/// The clipboard holds a private key afterwards, so a warning is printed.
+pub fn copy(text: &str) {
+	let result = arboard::Clipboard::new().and_then(|mut c| c.set_text(text.to_string()));
+	match result {
+		Ok(()) => {
+			info!("Copied the key to the clipboard");
+			warn!(
+				"The clipboard contains a private key, other programs and clipboard managers can \
+				 read it"
+			);
+			// On X11 and Wayland the content is served by this process, so without a
+			// clipboard manager it is gone when we exit
+			#[cfg(all(unix, not(target_os = "macos")))]
+			info!("Without a clipboard manager, the clipboard is cleared when this program exits");
+		}
+		Err(e) => warn!("Failed to copy to the clipboard: {}", e),
+	}
+}
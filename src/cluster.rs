@@ -0,0 +1,677 @@
+//! Distributes a search over several machines.
+//!
+//! The coordinator (`serve`) hands out work to workers (`worker`) over TCP. Messages are json
+//! objects, one per line. For leveling, the work is a range of offsets, for the pattern search,
+//! a number of keys to generate. Keys are random, so workers never duplicate pattern work.
+//! With '--seed' or '--mnemonic', the pattern search hands out ranges of key numbers instead,
+//! the workers have to be started with the same seed.
+//!
+//! Workers authenticate with the secret from 'MAHTS_CLUSTER_SECRET', which the coordinator
+//! requires when it listens on other addresses than loopback. The connection is not encrypted,
+//! use a VPN or an SSH tunnel between machines.
+
+use std::collections::VecDeque;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::backend;
+use crate::control::{self, is_interrupted};
+use crate::output::{Found, Output};
+use crate::token::constant_time_eq;
+use crate::{
+	best_level, output_from_opts, sha1x, uid_bits, FindPattern, Level, Opts, Outcome, Result,
+};
+
+/// Wait time before a worker reconnects to the coordinator.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+const SECRET_VAR: &str = "MAHTS_CLUSTER_SECRET";
+/// The longest hello, which is read before the worker is authenticated.
+const MAX_HELLO_LINE: u64 = 4096;
+/// The longest other message, a pattern result with many keys.
+const MAX_LINE: u64 = 64 << 20;
+/// Connections which did not send their hello yet, more are closed right away.
+const MAX_CONNECTING: usize = 16;
+/// Time a new connection has to send its hello.
+const HELLO_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(clap::Args, Debug)]
+pub struct ServeArgs {
+	#[clap(long, default_value = "127.0.0.1:9654")]
+	/// The address to listen on for workers. Other addresses than loopback require a secret in
+	/// 'MAHTS_CLUSTER_SECRET'.
+	listen: String,
+
+	#[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+	/// Offsets or keys per work unit. Defaults to 100M offsets or 1M keys.
+	chunk: Option<u64>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WorkerArgs {
+	#[clap(long)]
+	/// The address of the coordinator, like 'host:9654'. Sends the secret from
+	/// 'MAHTS_CLUSTER_SECRET'.
+	connect: String,
+}
+
+/// Sent from a worker to the coordinator.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+	Hello {
+		threads: usize,
+		#[serde(default)]
+		secret: Option<String>,
+	},
+	/// The result of a `Work::Level`.
+	Level {
+		start: u64,
+		count: u64,
+		level: u8,
+		offset: u64,
+	},
+	/// The result of a `Work::Pattern`, contains the keys of all matching identities.
+	Pattern { attempts: u64, keys: Vec<String> },
+}
+
+impl Request {
+	/// A summary for the log, without the keys and the secret.
+	fn describe(&self) -> String {
+		match self {
+			Request::Hello { threads, .. } => format!("hello with {} threads", threads),
+			Request::Level {
+				start,
+				count,
+				level,
+				offset,
+			} => format!(
+				"level {} at offset {} for {} offsets from {}",
+				level, offset, count, start
+			),
+			Request::Pattern { attempts, keys } => {
+				format!(
+					"pattern result of {} keys for {} attempts",
+					keys.len(),
+					attempts
+				)
+			}
+		}
+	}
+}
+
+/// Sent from the coordinator to a worker.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Work {
+	/// Finds the best level for the public key `omega` in the range.
+	Level {
+		omega: String,
+		start: u64,
+		count: u64,
+	},
+	/// Generates `count` keys and reports the ones matching any pattern.
+	Pattern {
+		patterns: Vec<String>,
+		count: u64,
+		/// The first key number with a seed, keys are random otherwise.
+		#[serde(default)]
+		start: Option<u64>,
+		/// The uid of key number 0, to check that the worker has the same seed.
+		#[serde(default)]
+		seed_check: Option<String>,
+	},
+	/// The search is finished.
+	Done,
+}
+
+enum Job {
+	Level {
+		omega: String,
+		want_level: u8,
+		state: Mutex<LevelState>,
+	},
+	Pattern {
+		patterns: Vec<FindPattern>,
+		max_matches: Option<u64>,
+		matches: AtomicU64,
+		attempts: AtomicU64,
+		output: Output,
+		seeded: Option<Seeded>,
+	},
+}
+
+/// Hands out ranges of offsets or key numbers.
+struct Ranges {
+	next: u64,
+	/// Ranges which were handed out to a worker that disconnected.
+	pending: VecDeque<(u64, u64)>,
+}
+
+impl Ranges {
+	fn new(start: u64) -> Self {
+		Self {
+			next: start,
+			pending: VecDeque::new(),
+		}
+	}
+
+	fn take(&mut self, chunk: u64) -> (u64, u64) {
+		self.pending.pop_front().unwrap_or_else(|| {
+			let start = self.next;
+			self.next += chunk;
+			(start, chunk)
+		})
+	}
+}
+
+struct LevelState {
+	ranges: Ranges,
+	best: Level,
+}
+
+/// The key numbers of a pattern search with a seed.
+struct Seeded {
+	ranges: Mutex<Ranges>,
+	/// The uid of key number 0.
+	check: String,
+}
+
+struct Coordinator {
+	job: Job,
+	chunk: u64,
+	done: AtomicBool,
+	secret: Option<String>,
+	/// The worker connections, to end them when the search is done.
+	connections: Mutex<Vec<TcpStream>>,
+	/// Connections which did not send their hello yet.
+	connecting: AtomicUsize,
+}
+
+pub fn tool_serve(args: ServeArgs, opts: Opts) -> Result {
+	let secret = std::env::var(SECRET_VAR).ok().filter(|s| !s.is_empty());
+	let loopback = args
+		.listen
+		.to_socket_addrs()
+		.map_err(|e| format!("Failed to resolve {}: {}", args.listen, e))?
+		.all(|a| a.ip().is_loopback());
+	if !loopback && secret.is_none() {
+		return Err(format!(
+			"Listening on {} requires a secret for the workers in '{}'",
+			args.listen, SECRET_VAR
+		));
+	}
+	let job = if !opts.patterns.is_empty() {
+		let patterns = opts
+			.patterns
+			.iter()
+			.map(|p| FindPattern::parse(p))
+			.collect::<std::result::Result<Vec<_>, _>>()?;
+		Job::Pattern {
+			patterns,
+			max_matches: if opts.exit_when_found {
+				Some(1)
+			} else {
+				opts.max_matches
+			},
+			matches: AtomicU64::new(0),
+			attempts: AtomicU64::new(0),
+			output: output_from_opts(&opts)?,
+			seeded: (opts.seed.is_some() || opts.mnemonic.is_some()).then(|| Seeded {
+				ranges: Mutex::new(Ranges::new(0)),
+				check: backend::generate_key_at(0).to_pub().get_uid().to_string(),
+			}),
+		}
+	} else if let Some(level) = opts.level {
+		let identity = opts
+			.identity
+			.as_ref()
+			.ok_or("Requires an identity (-i) to level")?;
+		let tp_priv =
+			EccKeyPrivP256::import_str(identity).map_err(|_| "Failed to read identity")?;
+		let omega = tp_priv.to_pub().to_ts();
+		// Continue from the counter of the identity, like the local level search
+		let start = opts.counter.unwrap_or(0);
+		let best = Level {
+			level: get_hash_cash_level(&omega, start),
+			offset: start,
+		};
+		Job::Level {
+			omega,
+			want_level: level as u8,
+			state: Mutex::new(LevelState {
+				ranges: Ranges::new(start),
+				best,
+			}),
+		}
+	} else {
+		return Err("Requires patterns or an identity and a level to distribute".to_string());
+	};
+	let chunk = args.chunk.unwrap_or(match job {
+		Job::Level { .. } => 100_000_000,
+		Job::Pattern { .. } => 1_000_000,
+	});
+	let coordinator = Coordinator {
+		job,
+		chunk,
+		done: AtomicBool::new(false),
+		secret,
+		connections: Mutex::new(Vec::new()),
+		connecting: AtomicUsize::new(0),
+	};
+	if let Job::Pattern { output, .. } = &coordinator.job {
+		output.print_header();
+	}
+
+	let listener = TcpListener::bind(&args.listen)
+		.map_err(|e| format!("Failed to listen on {}: {}", args.listen, e))?;
+	listener
+		.set_nonblocking(true)
+		.map_err(|e| format!("Failed to listen on {}: {}", args.listen, e))?;
+	info!("Waiting for workers on {}", args.listen);
+
+	std::thread::scope(|s| {
+		while !coordinator.done.load(Ordering::Relaxed) && !is_interrupted() {
+			match listener.accept() {
+				Ok((stream, addr)) => {
+					if coordinator.connecting.load(Ordering::Relaxed) >= MAX_CONNECTING {
+						warn!("Too many workers are connecting, closing {}", addr);
+						continue;
+					}
+					info!("Worker {} connected", addr);
+					match stream.try_clone() {
+						Ok(s) => coordinator.connections.lock().unwrap().push(s),
+						Err(e) => {
+							warn!("Failed to accept worker {}: {}", addr, e);
+							continue;
+						}
+					}
+					coordinator.connecting.fetch_add(1, Ordering::Relaxed);
+					let coordinator = &coordinator;
+					s.spawn(move || {
+						if let Err(e) = coordinator.handle(stream) {
+							info!("Worker {} disconnected: {}", addr, e);
+						}
+					});
+				}
+				Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+					std::thread::sleep(Duration::from_millis(100));
+				}
+				Err(e) => warn!("Failed to accept a worker: {}", e),
+			}
+		}
+		if let Job::Pattern { output, .. } = &coordinator.job {
+			output.finish();
+		}
+		// Connections block on reading, closing them ends the threads
+		for stream in coordinator.connections.lock().unwrap().drain(..) {
+			let _ = stream.shutdown(Shutdown::Both);
+		}
+	});
+	Ok(coordinator.outcome())
+}
+
+impl Coordinator {
+	fn handle(&self, stream: TcpStream) -> std::io::Result<()> {
+		let hello = Self::hello(stream);
+		self.connecting.fetch_sub(1, Ordering::Relaxed);
+		let (hello, mut reader, mut writer) = hello?;
+		if !self.authorize(&hello) {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::PermissionDenied,
+				"Wrong or missing secret",
+			));
+		}
+		reader.get_ref().set_read_timeout(None)?;
+
+		let mut assigned = None;
+		let res = (|| -> std::io::Result<()> {
+			let mut request = hello;
+			loop {
+				assigned = None;
+				self.process(request);
+				let work = self.next_work();
+				assigned = match &work {
+					Work::Level { start, count, .. }
+					| Work::Pattern {
+						start: Some(start),
+						count,
+						..
+					} => Some((*start, *count)),
+					_ => None,
+				};
+				let done = matches!(work, Work::Done);
+				send(&mut writer, &work)?;
+				if done {
+					return Ok(());
+				}
+				request = match read_message(&mut reader, MAX_LINE)? {
+					Some(request) => request,
+					None => return Ok(()),
+				};
+				debug!("Received {}", request.describe());
+			}
+		})();
+		// Hand out the unfinished range again
+		if let Some(range) = assigned {
+			match &self.job {
+				Job::Level { state, .. } => state.lock().unwrap().ranges.pending.push_back(range),
+				Job::Pattern {
+					seeded: Some(seeded),
+					..
+				} => seeded.ranges.lock().unwrap().pending.push_back(range),
+				Job::Pattern { .. } => {}
+			}
+		}
+		res
+	}
+
+	/// Reads the first request of a connection, which has to come soon and be short.
+	fn hello(stream: TcpStream) -> std::io::Result<(Request, BufReader<TcpStream>, TcpStream)> {
+		stream.set_nonblocking(false)?;
+		stream.set_read_timeout(Some(HELLO_TIMEOUT))?;
+		let writer = stream.try_clone()?;
+		let mut reader = BufReader::new(stream);
+		let hello = read_message(&mut reader, MAX_HELLO_LINE)?.ok_or_else(|| {
+			std::io::Error::new(std::io::ErrorKind::UnexpectedEof, "Connection closed")
+		})?;
+		Ok((hello, reader, writer))
+	}
+
+	fn outcome(&self) -> Outcome {
+		let found = match &self.job {
+			Job::Level {
+				want_level, state, ..
+			} => state.lock().unwrap().best.level >= *want_level,
+			Job::Pattern { matches, .. } => matches.load(Ordering::Relaxed) > 0,
+		};
+		if found {
+			Outcome::Success
+		} else {
+			Outcome::NotFound
+		}
+	}
+
+	/// Checks that the first request is a hello with the secret.
+	fn authorize(&self, request: &Request) -> bool {
+		match (request, &self.secret) {
+			(Request::Hello { .. }, None) => true,
+			(
+				Request::Hello {
+					secret: Some(s), ..
+				},
+				Some(secret),
+			) => constant_time_eq(s.as_bytes(), secret.as_bytes()),
+			_ => false,
+		}
+	}
+
+	fn process(&self, request: Request) {
+		match (request, &self.job) {
+			(Request::Hello { threads, .. }, _) => debug!("Worker has {} threads", threads),
+			(
+				Request::Level { level, offset, .. },
+				Job::Level {
+					omega,
+					want_level,
+					state,
+				},
+			) => {
+				// Do not trust the worker, the level is cheap to check
+				if get_hash_cash_level(omega, offset) != level {
+					warn!("Worker reported a wrong level for offset {}", offset);
+					return;
+				}
+				let mut state = state.lock().unwrap();
+				if level > state.best.level {
+					state.best = Level { level, offset };
+					println!("LEVEL: {} OFFSET: {}", level, offset);
+					if level >= *want_level {
+						self.done.store(true, Ordering::Relaxed);
+					}
+				}
+			}
+			(
+				Request::Pattern { attempts, keys },
+				Job::Pattern {
+					patterns,
+					max_matches,
+					matches,
+					attempts: total,
+					output,
+				},
+			) => {
+				let total = total.fetch_add(attempts, Ordering::Relaxed) + attempts;
+				for key in keys {
+					let tp_priv = match EccKeyPrivP256::import_str(&key) {
+						Ok(k) => k,
+						Err(_) => {
+							warn!("Worker reported an invalid key");
+							continue;
+						}
+					};
+					let bits = uid_bits(&tp_priv.to_pub());
+					let pattern = match patterns.iter().find(|p| p.matches(bits)) {
+						Some(p) => p,
+						None => {
+							warn!("Worker reported a key which matches no pattern");
+							continue;
+						}
+					};
+					let found = Found::new(&tp_priv, pattern.input.clone(), total);
+					if !output.claim(&found.uid) {
+						continue;
+					}
+					output.print_found(&found);
+					let n = matches.fetch_add(1, Ordering::Relaxed) + 1;
+					if max_matches.map_or(false, |max| n >= max) {
+						self.done.store(true, Ordering::Relaxed);
+					}
+				}
+			}
+			(request, _) => warn!("Worker sent an unexpected {}", request.describe()),
+		}
+	}
+
+	fn next_work(&self) -> Work {
+		if self.done.load(Ordering::Relaxed) {
+			return Work::Done;
+		}
+		match &self.job {
+			Job::Level { omega, state, .. } => {
+				let (start, count) = state.lock().unwrap().ranges.take(self.chunk);
+				Work::Level {
+					omega: omega.clone(),
+					start,
+					count,
+				}
+			}
+			Job::Pattern {
+				patterns, seeded, ..
+			} => {
+				let patterns = patterns.iter().map(|p| p.input.clone()).collect();
+				match seeded {
+					Some(seeded) => {
+						let (start, count) = seeded.ranges.lock().unwrap().take(self.chunk);
+						Work::Pattern {
+							patterns,
+							count,
+							start: Some(start),
+							seed_check: Some(seeded.check.clone()),
+						}
+					}
+					None => Work::Pattern {
+						patterns,
+						count: self.chunk,
+						start: None,
+						seed_check: None,
+					},
+				}
+			}
+		}
+	}
+}
+
+/// Reads a json line of at most `max` bytes, `None` at the end of the stream.
+fn read_message<T: DeserializeOwned>(
+	reader: &mut BufReader<TcpStream>,
+	max: u64,
+) -> std::io::Result<Option<T>> {
+	let mut line = String::new();
+	if reader.by_ref().take(max).read_line(&mut line)? == 0 {
+		return Ok(None);
+	}
+	if !line.ends_with('\n') && line.len() as u64 >= max {
+		return Err(std::io::Error::new(
+			std::io::ErrorKind::InvalidData,
+			"Message too long",
+		));
+	}
+	serde_json::from_str(&line)
+		.map(Some)
+		.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))
+}
+
+fn send<T: Serialize>(writer: &mut TcpStream, msg: &T) -> std::io::Result<()> {
+	let mut line = serde_json::to_string(msg).expect("Messages are always serializable");
+	line.push('\n');
+	writer.write_all(line.as_bytes())
+}
+
+pub fn tool_worker(args: WorkerArgs) -> Result {
+	let secret = std::env::var(SECRET_VAR).ok().filter(|s| !s.is_empty());
+	loop {
+		match TcpStream::connect(&args.connect) {
+			Ok(stream) => {
+				info!("Connected to {}", args.connect);
+				match work(stream, secret.clone()) {
+					Ok(()) => {
+						info!("The coordinator finished the search");
+						return Ok(Outcome::Success);
+					}
+					// Reconnecting does not help with another seed
+					Err(e) if e.kind() == std::io::ErrorKind::InvalidInput => {
+						return Err(e.to_string())
+					}
+					Err(e) => warn!("Lost connection to {}: {}", args.connect, e),
+				}
+			}
+			Err(e) => warn!("Failed to connect to {}: {}", args.connect, e),
+		}
+		let mut waited = Duration::ZERO;
+		while waited < RECONNECT_DELAY {
+			if is_interrupted() {
+				return Ok(Outcome::Success);
+			}
+			std::thread::sleep(Duration::from_millis(100));
+			waited += Duration::from_millis(100);
+		}
+	}
+}
+
+/// Processes work until the coordinator is done.
+fn work(stream: TcpStream, secret: Option<String>) -> std::io::Result<()> {
+	let mut writer = stream.try_clone()?;
+	send(
+		&mut writer,
+		&Request::Hello {
+			threads: rayon::current_num_threads(),
+			secret,
+		},
+	)?;
+	let mut reader = BufReader::new(stream);
+	while let Some(work) = read_message(&mut reader, MAX_LINE)? {
+		if is_interrupted() {
+			return Err(std::io::Error::new(
+				std::io::ErrorKind::Interrupted,
+				"Interrupted",
+			));
+		}
+		let result = match work {
+			Work::Done => return Ok(()),
+			Work::Level {
+				omega,
+				start,
+				count,
+			} => {
+				debug!("Checking offsets {} to {}", start, start + count);
+				let best = best_level(&sha1x::Midstate::new(&omega), start, start + count);
+				Request::Level {
+					start,
+					count,
+					level: best.level,
+					offset: best.offset,
+				}
+			}
+			Work::Pattern {
+				patterns,
+				count,
+				start,
+				seed_check,
+			} => {
+				let patterns = patterns
+					.iter()
+					.map(|p| FindPattern::parse(p))
+					.collect::<std::result::Result<Vec<_>, _>>()
+					.map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+				let keys = match start {
+					Some(start) => {
+						let uid = backend::generate_key_at(0).to_pub().get_uid().to_string();
+						if seed_check.as_deref() != Some(uid.as_str()) {
+							return Err(std::io::Error::new(
+								std::io::ErrorKind::InvalidInput,
+								"The coordinator derives the keys from a seed, start the worker \
+								 with the same '--seed' or '--mnemonic' and '--seed-start'",
+							));
+						}
+						debug!("Generating keys {} to {}", start, start + count);
+						(start..start + count)
+							.into_par_iter()
+							.filter_map(|i| candidate(&patterns, Some(i)))
+							.collect()
+					}
+					None => {
+						debug!("Generating {} keys", count);
+						(0..count)
+							.into_par_iter()
+							.filter_map(|_| candidate(&patterns, None))
+							.collect()
+					}
+				};
+				Request::Pattern {
+					attempts: count,
+					keys,
+				}
+			}
+		};
+		send(&mut writer, &result)?;
+	}
+	Err(std::io::Error::new(
+		std::io::ErrorKind::UnexpectedEof,
+		"Connection closed",
+	))
+}
+
+/// Generates a key, with this number with a seed, and returns it if it matches any pattern.
+fn candidate(patterns: &[FindPattern], index: Option<u64>) -> Option<String> {
+	control::wait_while_paused();
+	let (priv_key, pub_key) = match index {
+		Some(index) => backend::generate_pub_at(index),
+		None => backend::generate_pub(),
+	};
+	let bits = uid_bits(&pub_key);
+	if patterns.iter().any(|p| p.matches(bits)) {
+		Some(EccKeyPrivP256::from_short(&priv_key).unwrap().to_ts())
+	} else {
+		None
+	}
+}
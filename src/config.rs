@@ -0,0 +1,208 @@
+//! Default options from a TOML file.
+//!
+//! Every key is the name of a command line option, e.g. `threads = 4` or
+//! `output = "found.txt"`. Tables prefix their keys, so `[telegram] token = "..."` sets
+//! `--telegram-token`. `patterns` and `patterns-file` are only used if no other search is
+//! selected on the command line. Options on the command line override the file.
+
+use std::collections::HashSet;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+use clap::{CommandFactory, Parser};
+use toml::value::{Table, Value};
+
+use crate::Opts;
+
+const FILE_NAME: &str = "mahtsidentity.toml";
+
+/// The options from a config file.
+#[derive(Debug, Default)]
+struct Config {
+	/// Command line arguments for all options which are not given on the command line.
+	args: Vec<OsString>,
+	patterns: Vec<String>,
+	patterns_file: Option<PathBuf>,
+}
+
+/// Parses the command line with the defaults from the config file.
+///
+/// Exits like clap if the config file is invalid.
+pub fn parse() -> Opts {
+	let cli = std::env::args_os().collect::<Vec<_>>();
+	let explicit = config_arg(&cli);
+	let path = explicit.clone().or_else(default_path);
+	let mut config = Config::default();
+	let mut used = None;
+	if let Some(path) = path {
+		if explicit.is_some() || path.exists() {
+			config = load(&path, &cli).unwrap_or_else(|e| {
+				eprintln!("error: {}", e);
+				std::process::exit(2);
+			});
+			used = Some(path);
+		}
+	}
+
+	let mut args = cli.iter().take(1).cloned().collect::<Vec<_>>();
+	args.extend(config.args);
+	args.extend(cli.iter().skip(1).cloned());
+	let mut opts = Opts::parse_from(args);
+	#[allow(unused_mut)]
+	let mut other_tool = opts.command.is_some()
+		|| opts.export
+		|| opts.deobfuscate
+		|| opts.check
+		|| opts.level.is_some();
+	#[cfg(feature = "test-connect")]
+	{
+		other_tool |= opts.for_server.is_some();
+	}
+	if !other_tool && opts.patterns.is_empty() && opts.patterns_file.is_none() {
+		opts.patterns = config.patterns;
+		opts.patterns_file = config.patterns_file;
+	}
+	opts.config = used;
+	opts
+}
+
+fn default_path() -> Option<PathBuf> {
+	Some(dirs::config_dir()?.join(FILE_NAME))
+}
+
+/// The value of `--config` on the command line.
+fn config_arg(cli: &[OsString]) -> Option<PathBuf> {
+	let mut args = cli.iter().skip(1).map(|a| a.to_string_lossy());
+	while let Some(arg) = args.next() {
+		if arg == "--" {
+			break;
+		} else if arg == "--config" {
+			return args.next().map(|p| PathBuf::from(p.as_ref()));
+		} else if let Some(path) = arg.strip_prefix("--config=") {
+			return Some(PathBuf::from(path));
+		}
+	}
+	None
+}
+
+/// The long names of all options on the command line.
+fn given_options(cli: &[OsString]) -> HashSet<String> {
+	let command = Opts::command();
+	let mut given = HashSet::new();
+	for arg in cli.iter().skip(1).map(|a| a.to_string_lossy()) {
+		if arg == "--" {
+			break;
+		} else if let Some(long) = arg.strip_prefix("--") {
+			let long = long.split('=').next().unwrap_or_default();
+			given.insert(long.to_string());
+		} else if let Some(shorts) = arg.strip_prefix('-') {
+			for short in shorts.chars() {
+				let option = command
+					.get_arguments()
+					.find(|a| a.get_short() == Some(short));
+				let option = match option {
+					Some(o) => o,
+					None => break,
+				};
+				if let Some(long) = option.get_long() {
+					given.insert(long.to_string());
+				}
+				// The rest of the argument is the value
+				if option.is_takes_value_set() {
+					break;
+				}
+			}
+		}
+	}
+	given
+}
+
+fn load(path: &Path, cli: &[OsString]) -> Result<Config, String> {
+	let content = std::fs::read_to_string(path)
+		.map_err(|e| format!("Failed to read config file {}: {}", path.display(), e))?;
+	let table = content
+		.parse::<Value>()
+		.map_err(|e| format!("Invalid config file {}: {}", path.display(), e))?;
+	let table = match table {
+		Value::Table(t) => t,
+		_ => return Err(format!("Invalid config file {}", path.display())),
+	};
+
+	let command = Opts::command();
+	let longs = command
+		.get_arguments()
+		.filter_map(|a| a.get_long())
+		.collect::<HashSet<_>>();
+	let given = given_options(cli);
+	let mut config = Config::default();
+	let mut options = Vec::new();
+	flatten("", table, &mut options);
+	for (name, value) in options {
+		match name.as_str() {
+			"patterns" => {
+				config.patterns = strings(&value)
+					.ok_or_else(|| format!("'patterns' in {} must be a list", path.display()))?;
+				continue;
+			}
+			"patterns-file" => {
+				config.patterns_file = value.as_str().map(PathBuf::from);
+				continue;
+			}
+			"config" => {
+				return Err(format!("'config' can not be set in {}", path.display()));
+			}
+			_ => {}
+		}
+		if !longs.contains(name.as_str()) {
+			return Err(format!("Unknown option '{}' in {}", name, path.display()));
+		}
+		if given.contains(&name) {
+			continue;
+		}
+		match value {
+			Value::Boolean(true) => config.args.push(format!("--{}", name).into()),
+			Value::Boolean(false) => {}
+			Value::Array(_) => {
+				let values = strings(&value).ok_or_else(|| {
+					format!("'{}' in {} must be a list of values", name, path.display())
+				})?;
+				for v in values {
+					config.args.push(format!("--{}={}", name, v).into());
+				}
+			}
+			value => config
+				.args
+				.push(format!("--{}={}", name, scalar(&value).unwrap_or_default()).into()),
+		}
+	}
+	Ok(config)
+}
+
+/// Collects all keys with their values, keys in tables are prefixed with the table name.
+fn flatten(prefix: &str, table: Table, options: &mut Vec<(String, Value)>) {
+	for (key, value) in table {
+		let name = format!("{}{}", prefix, key.replace('_', "-"));
+		match value {
+			Value::Table(t) => flatten(&format!("{}-", name), t, options),
+			value => options.push((name, value)),
+		}
+	}
+}
+
+fn scalar(value: &Value) -> Option<String> {
+	match value {
+		Value::String(s) => Some(s.clone()),
+		Value::Integer(i) => Some(i.to_string()),
+		Value::Float(f) => Some(f.to_string()),
+		Value::Datetime(d) => Some(d.to_string()),
+		_ => None,
+	}
+}
+
+/// A single value or a list of values.
+fn strings(value: &Value) -> Option<Vec<String>> {
+	match value {
+		Value::Array(values) => values.iter().map(scalar).collect(),
+		value => scalar(value).map(|v| vec![v]),
+	}
+}
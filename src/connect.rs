@@ -0,0 +1,102 @@
+//! Connects to a TeamSpeak server to check if it accepts an identity.
+//!
+//! The connection is made with tsclientlib, which runs the tsproto handshake. If the server
+//! demands a higher security level, tsclientlib would start leveling the identity. We stop
+//! there and report the demanded level instead.
+
+use std::time::Duration;
+
+use futures::prelude::*;
+use log::info;
+use tsclientlib::{Connection, DisconnectOptions, Identity, StreamItem};
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::{Outcome, Result};
+
+const TIMEOUT: Duration = Duration::from_secs(15);
+
+#[derive(clap::Args, Debug)]
+pub struct TestConnectArgs {
+	#[clap(long, value_name = "HOST:PORT")]
+	/// The address of the server.
+	address: String,
+}
+
+enum Answer {
+	Accepted,
+	/// The server demands this security level.
+	Level(u8),
+}
+
+/// Connects with the identity and returns how the server answered.
+fn connect(
+	address: &str,
+	key: EccKeyPrivP256,
+	counter: u64,
+) -> std::result::Result<Answer, String> {
+	let runtime = tokio::runtime::Builder::new_current_thread()
+		.enable_all()
+		.build()
+		.map_err(|e| format!("Failed to start the async runtime: {}", e))?;
+	runtime.block_on(async {
+		let mut con = Connection::build(address.to_string())
+			.identity(Identity::new(key, counter))
+			.connect()
+			.map_err(|e| format!("Failed to connect to {}: {}", address, e))?;
+		let answer = tokio::time::timeout(TIMEOUT, async {
+			let mut events = con.events();
+			while let Some(event) = events.next().await {
+				match event.map_err(|e| format!("Connection failed: {}", e))? {
+					StreamItem::BookEvents(_) => return Ok(Answer::Accepted),
+					StreamItem::IdentityLevelIncreasing(level) => return Ok(Answer::Level(level)),
+					_ => {}
+				}
+			}
+			Err("The server closed the connection".to_string())
+		})
+		.await
+		.map_err(|_| format!("The server did not answer within {:?}", TIMEOUT))??;
+		// Leave the server cleanly, errors do not change the result
+		if con.disconnect(DisconnectOptions::new()).is_ok() {
+			let _ = tokio::time::timeout(
+				Duration::from_secs(2),
+				con.events().for_each(|_| future::ready(())),
+			)
+			.await;
+		}
+		Ok(answer)
+	})
+}
+
+/// Asks the server for the security level it requires, with a fresh identity.
+pub fn required_level(address: &str) -> std::result::Result<u8, String> {
+	let key = EccKeyPrivP256::create();
+	let level = get_hash_cash_level(&key.to_pub().to_ts(), 0);
+	info!("Asking {} for its required security level", address);
+	match connect(address, key, 0)? {
+		Answer::Level(required) => Ok(required),
+		// Accepting the throwaway identity means its level is enough
+		Answer::Accepted => Ok(level),
+	}
+}
+
+pub fn tool_test_connect(args: TestConnectArgs, identity: Option<&str>, counter: u64) -> Result {
+	let identity = identity.ok_or("Requires an identity (-i) to connect")?;
+	let key = EccKeyPrivP256::import_str(identity).map_err(|_| "Failed to read identity")?;
+	let level = get_hash_cash_level(&key.to_pub().to_ts(), counter);
+	info!("Connecting to {} with level {}", args.address, level);
+	match connect(&args.address, key, counter)? {
+		Answer::Accepted => {
+			println!("ACCEPTED: {} LEVEL: {}", args.address, level);
+			Ok(Outcome::Success)
+		}
+		Answer::Level(required) => {
+			println!(
+				"REJECTED: {} LEVEL: {} REQUIRED: {}",
+				args.address, level, required
+			);
+			Ok(Outcome::NotFound)
+		}
+	}
+}
@@ -0,0 +1,155 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+
+use crate::EXIT_INTERRUPTED;
+
+/// Set by the Ctrl+C handler, the running tool stops as soon as it sees this.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+/// Workers wait while this is set.
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// Set by `SIGQUIT` (Ctrl+\) to print the best candidates so far.
+static DUMP_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// Set by `SIGHUP` to reload the patterns file.
+static RELOAD_REQUESTED: AtomicBool = AtomicBool::new(false);
+/// The percentage of each throttle period in which workers are allowed to run.
+static CPU_LIMIT: AtomicU32 = AtomicU32::new(100);
+
+/// Workers run for `CPU_LIMIT` percent of each period and sleep for the rest.
+const THROTTLE_PERIOD_US: u64 = 100_000;
+
+pub fn is_interrupted() -> bool {
+	INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Stops the running tool like Ctrl+C.
+pub fn interrupt() {
+	INTERRUPTED.store(true, Ordering::Relaxed);
+}
+
+pub fn is_paused() -> bool {
+	PAUSED.load(Ordering::Relaxed)
+}
+
+/// Pauses or resumes the workers, returns `false` if they already were in that state.
+pub fn set_paused(paused: bool) -> bool {
+	PAUSED.swap(paused, Ordering::Relaxed) != paused
+}
+
+/// Returns `true` once after the user asked to print the current best candidates.
+pub fn take_dump_request() -> bool {
+	DUMP_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Returns `true` once after the user asked to reload the patterns file.
+pub fn take_reload_request() -> bool {
+	RELOAD_REQUESTED.swap(false, Ordering::Relaxed)
+}
+
+/// Blocks the calling worker until the search is resumed or interrupted.
+///
+/// Also sleeps if the workers are throttled.
+pub fn wait_while_paused() {
+	while is_paused() && !is_interrupted() {
+		std::thread::sleep(Duration::from_millis(100));
+	}
+	throttle();
+}
+
+pub fn cpu_limit() -> u32 {
+	CPU_LIMIT.load(Ordering::Relaxed)
+}
+
+/// Limits the workers to roughly the given percentage of the cpu time.
+pub fn set_cpu_limit(percent: u32) {
+	CPU_LIMIT.store(percent.clamp(1, 100), Ordering::Relaxed);
+}
+
+/// Sleeps until the end of the current period if the workers already ran for their share of it.
+///
+/// All workers sleep at the same time, so this duty cycle limits the total cpu usage.
+pub fn throttle() {
+	let limit = cpu_limit() as u64;
+	if limit >= 100 {
+		return;
+	}
+	let now = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.unwrap_or_default()
+		.as_micros() as u64
+		% THROTTLE_PERIOD_US;
+	let running = THROTTLE_PERIOD_US * limit / 100;
+	if now >= running && !is_interrupted() {
+		std::thread::sleep(Duration::from_micros(THROTTLE_PERIOD_US - now));
+	}
+}
+
+/// Installs the Ctrl+C handler and, on unix, `SIGUSR1`/`SIGUSR2` to pause and resume and
+/// `SIGQUIT` to print the best candidates.
+pub fn install_handlers() {
+	if let Err(e) = ctrlc::set_handler(|| {
+		if INTERRUPTED.swap(true, Ordering::Relaxed) {
+			// Second Ctrl+C, do not wait for the workers
+			std::process::exit(EXIT_INTERRUPTED);
+		}
+	}) {
+		warn!("Failed to install Ctrl+C handler: {}", e);
+	}
+
+	#[cfg(unix)]
+	install_signal_handlers();
+}
+
+#[cfg(unix)]
+fn install_signal_handlers() {
+	use signal_hook::consts::{SIGQUIT, SIGUSR1, SIGUSR2};
+	use signal_hook::iterator::Signals;
+
+	let mut signals = match Signals::new(&[SIGUSR1, SIGUSR2, SIGQUIT]) {
+		Ok(s) => s,
+		Err(e) => {
+			warn!("Failed to install signal handlers: {}", e);
+			return;
+		}
+	};
+	std::thread::spawn(move || {
+		for signal in signals.forever() {
+			if signal == SIGUSR1 && !PAUSED.swap(true, Ordering::Relaxed) {
+				info!(
+					"Paused, send SIGUSR2 to resume (pid {})",
+					std::process::id()
+				);
+			} else if signal == SIGUSR2 && PAUSED.swap(false, Ordering::Relaxed) {
+				info!("Resumed");
+			} else if signal == SIGQUIT {
+				DUMP_REQUESTED.store(true, Ordering::Relaxed);
+			}
+		}
+	});
+}
+
+/// Reloads the patterns file on `SIGHUP`.
+///
+/// This is separate from the other handlers, as `SIGHUP` should still end the process when
+/// nothing can be reloaded.
+pub fn install_reload_handler() {
+	#[cfg(unix)]
+	{
+		use signal_hook::consts::SIGHUP;
+		use signal_hook::iterator::Signals;
+
+		let mut signals = match Signals::new(&[SIGHUP]) {
+			Ok(s) => s,
+			Err(e) => {
+				warn!("Failed to install SIGHUP handler: {}", e);
+				return;
+			}
+		};
+		std::thread::spawn(move || {
+			for _ in signals.forever() {
+				RELOAD_REQUESTED.store(true, Ordering::Relaxed);
+			}
+		});
+	}
+}
@@ -0,0 +1,155 @@
+//! Converts many identities between formats at once, e.g. to migrate the keys of bots.
+
+use std::path::Path;
+
+use log::info;
+use serde_json::json;
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+use zeroize::Zeroizing;
+
+use crate::{identity_from_str, keys, output, ts3ini, Opts, Outcome, Result};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+	/// The plain key which '--identity' expects.
+	Plain,
+	/// '<counter>V<obfuscated key>' like the TeamSpeak client stores it.
+	Obfuscated,
+	/// The ini file of the TeamSpeak 3 identity import.
+	Ini,
+	/// A PKCS#8 PEM file.
+	Pem,
+	/// A json object with the uid, both keys, the counter and the level.
+	Json,
+	/// The identity section of a TS3AudioBot bot config.
+	Ts3audiobot,
+	/// The identity of the SinusBot instance settings.
+	Sinusbot,
+}
+
+impl ExportFormat {
+	fn extension(self) -> &'static str {
+		match self {
+			Self::Plain | Self::Obfuscated => "txt",
+			Self::Ini => "ini",
+			Self::Pem => "pem",
+			Self::Json => "json",
+			Self::Ts3audiobot => "toml",
+			Self::Sinusbot => "json",
+		}
+	}
+
+	/// Formats an identity, `counter` is the hashcash offset of the security level.
+	pub fn format(
+		self,
+		key: &EccKeyPrivP256,
+		counter: Option<u64>,
+		nickname: &str,
+	) -> std::result::Result<Zeroizing<String>, String> {
+		let obfuscated = Zeroizing::new(format!(
+			"{}V{}",
+			counter.unwrap_or(0),
+			key.to_ts_obfuscated()
+		));
+		Ok(Zeroizing::new(match self {
+			Self::Plain => format!("{}\n", key.to_ts()),
+			Self::Obfuscated => format!("{}\n", *obfuscated),
+			Self::Ini => ts3ini::to_ini(key, nickname, counter.unwrap_or(0)),
+			Self::Ts3audiobot => ts3audiobot_config(key, counter.unwrap_or(0)),
+			Self::Sinusbot => sinusbot_settings(key, counter.unwrap_or(0)),
+			Self::Pem => keys::to_pem(key)?.to_string(),
+			Self::Json => {
+				let pub_key = key.to_pub();
+				let counter = counter.unwrap_or(0);
+				let mut json = json!({
+					"uid": pub_key.get_uid().to_string(),
+					"key": key.to_ts(),
+					"key_obfuscated": *obfuscated,
+					"counter": counter,
+					"level": get_hash_cash_level(&pub_key.to_ts(), counter),
+				})
+				.to_string();
+				json.push('\n');
+				json
+			}
+		}))
+	}
+}
+
+/// A file name for a uid, uids can contain '/' which is not allowed in file names.
+pub fn uid_file_name(uid: &str) -> String {
+	uid.replace('/', "_").replace('+', "-")
+}
+
+/// The `[connect.identity]` table of a TS3AudioBot `bot.toml`.
+///
+/// A level of -1 lets the bot raise the level itself if a server demands more.
+pub fn ts3audiobot_config(key: &EccKeyPrivP256, counter: u64) -> String {
+	format!(
+		"[connect.identity]\nkey = \"{}\"\noffset = {}\nlevel = -1\n",
+		key.to_ts(),
+		counter
+	)
+}
+
+/// The SinusBot instance settings with the identity, as json like its web interface sends
+/// them.
+///
+/// SinusBot expects the obfuscated key with the level offset like the TeamSpeak client
+/// stores it.
+pub fn sinusbot_settings(key: &EccKeyPrivP256, counter: u64) -> String {
+	let identity = Zeroizing::new(format!("{}V{}", counter, key.to_ts_obfuscated()));
+	let mut json = json!({ "identity": *identity }).to_string();
+	json.push('\n');
+	json
+}
+
+/// Converts every identity in the file given by `--batch`.
+///
+/// Each line holds an identity in any format of `--identity`, empty lines and lines starting
+/// with `#` are skipped. Writes one file per identity to `--batch-dir` or all of them to
+/// stdout.
+pub fn tool_export_batch(opts: &Opts, path: &Path) -> Result {
+	let content = Zeroizing::new(
+		std::fs::read_to_string(path)
+			.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+	);
+	if let Some(dir) = &opts.batch_dir {
+		std::fs::create_dir_all(dir)
+			.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+	}
+	let mut combined = Zeroizing::new(String::new());
+	let lines = content
+		.lines()
+		.enumerate()
+		.map(|(i, l)| (i + 1, l.trim()))
+		.filter(|(_, l)| !l.is_empty() && !l.starts_with('#'));
+	let mut count = 0;
+	for (line, identity) in lines {
+		let (identity, counter) = identity_from_str(identity)
+			.map_err(|e| format!("Line {} of {}: {}", line, path.display(), e))?;
+		let identity = Zeroizing::new(identity);
+		let key = EccKeyPrivP256::import_str(&identity)
+			.map_err(|_| format!("Failed to read the identity in line {}", line))?;
+		let formatted = opts.batch_format.format(&key, counter, &opts.nickname)?;
+		match &opts.batch_dir {
+			Some(dir) => {
+				let name = uid_file_name(&key.to_pub().get_uid().to_string());
+				let file = dir.join(format!("{}.{}", name, opts.batch_format.extension()));
+				output::write_private(&file, formatted.as_bytes())
+					.map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+			}
+			None => {
+				if opts.batch_format == ExportFormat::Ini && count > 0 {
+					combined.push('\n');
+				}
+				combined.push_str(&formatted);
+			}
+		}
+		count += 1;
+	}
+	print!("{}", *combined);
+	info!("Converted {} identities", count);
+	Ok(Outcome::Success)
+}
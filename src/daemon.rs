@@ -0,0 +1,91 @@
+use std::time::Duration;
+
+use log::{debug, warn};
+
+/// Detaches from the terminal and continues in the background.
+///
+/// Must be called before any thread is started. The working directory is kept, so relative
+/// paths still work, stdin, stdout and stderr are redirected to `/dev/null`.
+#[cfg(unix)]
+pub fn detach() -> Result<(), String> {
+	let fork = || match unsafe { libc::fork() } {
+		-1 => Err(format!(
+			"Failed to fork: {}",
+			std::io::Error::last_os_error()
+		)),
+		0 => Ok(()),
+		// The parent exits, the child continues
+		_ => std::process::exit(0),
+	};
+
+	fork()?;
+	if unsafe { libc::setsid() } == -1 {
+		return Err(format!(
+			"Failed to create a new session: {}",
+			std::io::Error::last_os_error()
+		));
+	}
+	// Fork again, so the daemon can never acquire a controlling terminal
+	fork()?;
+
+	let null = std::fs::OpenOptions::new()
+		.read(true)
+		.write(true)
+		.open("/dev/null")
+		.map_err(|e| format!("Failed to open /dev/null: {}", e))?;
+	let null = std::os::unix::io::AsRawFd::as_raw_fd(&null);
+	for fd in [libc::STDIN_FILENO, libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+		if unsafe { libc::dup2(null, fd) } == -1 {
+			return Err(format!(
+				"Failed to redirect standard streams: {}",
+				std::io::Error::last_os_error()
+			));
+		}
+	}
+	Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn detach() -> Result<(), String> {
+	Err("Daemon mode is only supported on unix".to_string())
+}
+
+/// Sends a state like `READY=1` to systemd, if running as a `Type=notify` service.
+///
+/// With `--daemon`, the service needs `NotifyAccess=all` because the notifications come from
+/// the forked process.
+#[cfg(unix)]
+pub fn notify(state: &str) {
+	let socket = match std::env::var_os("NOTIFY_SOCKET") {
+		Some(socket) => socket,
+		None => return,
+	};
+	let res = std::os::unix::net::UnixDatagram::unbound()
+		.and_then(|s| s.send_to(state.as_bytes(), &socket));
+	if let Err(e) = res {
+		warn!("Failed to notify systemd: {}", e);
+	}
+}
+
+#[cfg(not(unix))]
+pub fn notify(_: &str) {}
+
+/// Notifies systemd that startup finished and pings its watchdog, if enabled for the service.
+///
+/// Includes the pid, so systemd tracks the forked process with '--daemon'.
+pub fn notify_ready() {
+	notify(&format!("READY=1\nMAINPID={}", std::process::id()));
+
+	let interval = std::env::var("WATCHDOG_USEC")
+		.ok()
+		.and_then(|usec| usec.parse::<u64>().ok());
+	if let Some(usec) = interval {
+		// Ping twice per interval, as recommended by sd_watchdog_enabled(3)
+		let interval = Duration::from_micros(usec / 2);
+		debug!("Pinging the systemd watchdog every {:?}", interval);
+		std::thread::spawn(move || loop {
+			notify("WATCHDOG=1");
+			std::thread::sleep(interval);
+		});
+	}
+}
@@ -0,0 +1,86 @@
+//! A web dashboard for the pattern search.
+//!
+//! Shows the throughput, the expected time for each pattern and the found identities, and can
+//! pause, resume and stop the search.
+//!
+//! Everything but the page itself needs the token, which is part of the printed address. Set
+//! 'MAHTS_DASHBOARD_TOKEN' to choose it.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use tiny_http::{Header, Method, Response, Server};
+
+use crate::token::Token;
+use crate::{control, state, RunData};
+
+const PAGE: &str = include_str!("dashboard.html");
+
+pub struct Dashboard {
+	server: Server,
+	token: Token,
+}
+
+pub fn bind(addr: &str) -> Result<Dashboard, String> {
+	let server = Server::http(addr).map_err(|e| format!("Failed to listen on {}: {}", addr, e))?;
+	let token = Token::from_env("MAHTS_DASHBOARD_TOKEN");
+	// Not logged, the log file and syslog may be readable by others
+	eprintln!("Dashboard on http://{}/#{}", addr, token.as_str());
+	Ok(Dashboard { server, token })
+}
+
+/// Answers requests until `done` is set.
+pub fn serve(dashboard: &Dashboard, data: &RunData, start: Instant, done: &AtomicBool) {
+	let server = &dashboard.server;
+	while !done.load(Ordering::Relaxed) {
+		let request = match server.recv_timeout(Duration::from_millis(100)) {
+			Ok(Some(request)) => request,
+			Ok(None) => continue,
+			Err(e) => {
+				warn!("Failed to receive request: {}", e);
+				continue;
+			}
+		};
+		let page = (request.method(), request.url()) == (&Method::Get, "/");
+		if !page && !dashboard.token.accepts(&request) {
+			let _ = request.respond(Response::empty(401));
+			continue;
+		}
+		let (content_type, body) = match (request.method(), request.url()) {
+			(Method::Get, "/") => ("text/html; charset=utf-8", PAGE.to_string()),
+			(Method::Get, "/state") => (
+				"application/json",
+				state::find_state(data, start.elapsed()).to_string(),
+			),
+			(Method::Post, "/pause") => {
+				if control::set_paused(true) {
+					info!("Paused from the dashboard");
+				}
+				("application/json", "{}".to_string())
+			}
+			(Method::Post, "/resume") => {
+				if control::set_paused(false) {
+					info!("Resumed from the dashboard");
+				}
+				("application/json", "{}".to_string())
+			}
+			(Method::Post, "/stop") => {
+				info!("Stopped from the dashboard");
+				data.stop.store(true, Ordering::Relaxed);
+				("application/json", "{}".to_string())
+			}
+			_ => {
+				let _ = request.respond(Response::empty(404));
+				continue;
+			}
+		};
+		let response = Response::from_string(body).with_header(
+			Header::from_bytes(&b"Content-Type"[..], content_type.as_bytes())
+				.expect("Header is valid"),
+		);
+		if let Err(e) = request.respond(response) {
+			warn!("Failed to send response: {}", e);
+		}
+	}
+}
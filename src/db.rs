@@ -0,0 +1,66 @@
+use std::path::Path;
+use std::sync::Mutex;
+
+use rusqlite::{params, Connection};
+
+use crate::output::{self, Found};
+
+/// A SQLite database which records all found identities.
+#[derive(Debug)]
+pub struct Database {
+	conn: Mutex<Connection>,
+}
+
+impl Database {
+	pub fn open(path: &Path) -> Result<Self, String> {
+		// The keys are stored in plain text, so only the owner may read a new database
+		output::open_append(path)
+			.map_err(|e| format!("Failed to create database {}: {}", path.display(), e))?;
+		let conn = Connection::open(path)
+			.map_err(|e| format!("Failed to open database {}: {}", path.display(), e))?;
+		conn.execute_batch(
+			"CREATE TABLE IF NOT EXISTS found (
+				uid TEXT PRIMARY KEY NOT NULL,
+				key TEXT NOT NULL,
+				key_obfuscated TEXT NOT NULL,
+				level INTEGER NOT NULL,
+				pattern TEXT NOT NULL,
+				timestamp TEXT NOT NULL,
+				attempts INTEGER NOT NULL
+			);",
+		)
+		.map_err(|e| format!("Failed to initialize database {}: {}", path.display(), e))?;
+		Ok(Self {
+			conn: Mutex::new(conn),
+		})
+	}
+
+	/// Checks if an identity with the uid is stored, through the index of the primary key.
+	pub fn contains(&self, uid: &str) -> rusqlite::Result<bool> {
+		let conn = self.conn.lock().unwrap();
+		let mut stmt = conn.prepare_cached("SELECT 1 FROM found WHERE uid = ?1")?;
+		stmt.exists([uid])
+	}
+
+	/// Stores a found identity.
+	///
+	/// Returns `false` if an identity with the same uid is already stored.
+	pub fn insert(&self, found: &Found) -> rusqlite::Result<bool> {
+		let conn = self.conn.lock().unwrap();
+		let changed = conn.execute(
+			"INSERT OR IGNORE INTO found
+				(uid, key, key_obfuscated, level, pattern, timestamp, attempts)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+			params![
+				found.uid,
+				found.key,
+				found.key_obfuscated,
+				found.level,
+				found.pattern,
+				found.timestamp,
+				found.attempts as i64,
+			],
+		)?;
+		Ok(changed != 0)
+	}
+}
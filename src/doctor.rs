@@ -0,0 +1,155 @@
+//! Checks the machine and the configuration for things which slow down the search.
+
+use std::hint::black_box;
+use std::time::Duration;
+
+use clap::ValueEnum;
+use sha1::{Digest, Sha1};
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::backend::{self, BackendKind};
+use crate::bench;
+use crate::calibration;
+use crate::pool;
+use crate::progress::human_count;
+use crate::sha1x;
+use crate::{Opts, Outcome, Result};
+
+#[derive(clap::Args, Debug)]
+pub struct DoctorArgs {
+	#[clap(short, long, value_parser = humantime::parse_duration, default_value = "1s")]
+	/// How long each primitive is measured on a single thread.
+	duration: Duration,
+}
+
+/// The cpu features which matter for the search and if the cpu has them.
+fn cpu_features() -> Vec<(&'static str, bool)> {
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	{
+		vec![
+			("SHA-NI", is_x86_feature_detected!("sha")),
+			("AVX2", is_x86_feature_detected!("avx2")),
+			("AVX-512", is_x86_feature_detected!("avx512f")),
+		]
+	}
+	#[cfg(target_arch = "aarch64")]
+	{
+		vec![
+			("NEON", std::arch::is_aarch64_feature_detected!("neon")),
+			("SHA-1", std::arch::is_aarch64_feature_detected!("sha2")),
+		]
+	}
+	#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+	{
+		Vec::new()
+	}
+}
+
+fn has_feature(features: &[(&str, bool)], name: &str) -> bool {
+	features.iter().any(|(n, has)| *n == name && *has)
+}
+
+pub fn tool_doctor(args: DoctorArgs, opts: &Opts) -> Result {
+	let mut problems = Vec::new();
+
+	println!(
+		"CPU: {} ({})",
+		calibration::cpu_model(),
+		std::env::consts::ARCH
+	);
+	let features = cpu_features();
+	for (name, has) in &features {
+		println!("  {:<10} {}", name, if *has { "yes" } else { "no" });
+	}
+	if cfg!(debug_assertions) {
+		problems.push("This is a debug build, build with '--release' to search faster".to_string());
+	}
+
+	let selected = backend::selected();
+	println!("Key backend: {:?}", selected);
+	if BackendKind::value_variants().len() > 1 && selected == BackendKind::P256 {
+		problems
+			.push("The p256 backend is slower than ring, which this build includes".to_string());
+	}
+	println!("SHA-1: {}", sha1x::implementation());
+
+	let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+	let threads = rayon::current_num_threads();
+	println!("Threads: {} of {} logical cpus", threads, cores);
+	if threads > cores {
+		problems.push(format!(
+			"{} threads share {} cpus, more threads than cpus only add switching",
+			threads, cores
+		));
+	}
+	let nodes = pool::numa_nodes()?;
+	println!("NUMA nodes: {}", nodes.len().max(1));
+	for (node, cpus) in nodes.iter().enumerate() {
+		println!("  node {:<5} {} cpus", node, cpus.0.len());
+	}
+	if nodes.len() > 1 && !opts.numa {
+		problems.push(format!(
+			"The system has {} NUMA nodes, '--numa' keeps the workers on their node",
+			nodes.len()
+		));
+	}
+	if let Some(perf) = pool::perf_cores() {
+		println!("Performance cores: {}", perf.0.len());
+		if !opts.perf_cores_only && opts.pin_threads.is_none() {
+			problems.push(
+				"The cpu has efficiency cores, '--perf-cores-only' keeps them from slowing down \
+				 every batch"
+					.to_string(),
+			);
+		}
+	}
+
+	// The micro benchmark runs on one thread, so it shows the speed of one core
+	let pool = rayon::ThreadPoolBuilder::new()
+		.num_threads(1)
+		.build()
+		.map_err(|e| format!("Failed to create thread pool: {}", e))?;
+	let omega = EccKeyPrivP256::create().to_pub().to_ts();
+	let mut results = BackendKind::value_variants()
+		.iter()
+		.map(|&kind| {
+			(
+				bench::generation_name(kind),
+				bench::measure(&pool, args.duration, |_| {
+					black_box(backend::generate_with(kind));
+				}),
+			)
+		})
+		.collect::<Vec<_>>();
+	let single = bench::measure(&pool, args.duration, |_| {
+		black_box(Sha1::digest(omega.as_bytes()));
+	});
+	let messages = [omega.as_bytes(); sha1x::LANES];
+	let lanes = bench::measure(&pool, args.duration, |_| {
+		black_box(sha1x::digest(&messages));
+	}) * sha1x::LANES as f64;
+	results.push(("uid hashing (sha1)".to_string(), single));
+	results.push(("uid hashing (multi-buffer)".to_string(), lanes));
+	results.push((
+		"hashcash level".to_string(),
+		bench::hashcash_rate(&pool, args.duration, &omega),
+	));
+	println!("{:<24} {:>12}", "Primitive", "ops/s");
+	for (name, rate) in &results {
+		println!("{:<24} {:>12}", name, human_count(*rate));
+	}
+	if has_feature(&features, "SHA-NI") && single > lanes {
+		problems.push(format!(
+			"The cpu has SHA-NI, but uids are hashed with the slower {}",
+			sha1x::implementation()
+		));
+	}
+
+	if problems.is_empty() {
+		println!("No problems found");
+	}
+	for problem in problems {
+		println!("WARNING: {}", problem);
+	}
+	Ok(Outcome::Success)
+}
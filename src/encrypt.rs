@@ -0,0 +1,150 @@
+//! Private keys encrypted with a passphrase, for backups of valuable identities.
+//!
+//! The key is derived with Argon2id and encrypts the identity with ChaCha20-Poly1305. An
+//! encrypted identity looks like `MAHTS-ENCRYPTED:<base64 of salt, nonce and ciphertext>`.
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use zeroize::Zeroizing;
+
+const PREFIX: &str = "MAHTS-ENCRYPTED:";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+/// The passphrase for scripts, which can not answer the prompt.
+const PASSPHRASE_VAR: &str = "MAHTS_PASSPHRASE";
+
+pub fn is_encrypted(data: &str) -> bool {
+	data.trim_start().starts_with(PREFIX)
+}
+
+/// Derives the key, the parameters are fixed so old backups stay readable.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>, String> {
+	// 19 MiB memory, 2 iterations, 1 thread, the recommendation of OWASP
+	let params = Params::new(19 * 1024, 2, 1, Some(32)).map_err(|e| e.to_string())?;
+	let mut key = Zeroizing::new([0; 32]);
+	Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+		.hash_password_into(passphrase.as_bytes(), salt, &mut *key)
+		.map_err(|e| format!("Failed to derive key: {}", e))?;
+	Ok(key)
+}
+
+/// Encrypts an identity string, asks for the passphrase twice.
+pub fn encrypt(identity: &str) -> Result<String, String> {
+	let passphrase = passphrase(true)?;
+	let mut salt = [0; SALT_LEN];
+	OsRng.fill_bytes(&mut salt);
+	seal(
+		identity,
+		&passphrase,
+		salt,
+		ChaCha20Poly1305::generate_nonce(&mut OsRng),
+	)
+}
+
+fn seal(
+	identity: &str,
+	passphrase: &str,
+	salt: [u8; SALT_LEN],
+	nonce: Nonce,
+) -> Result<String, String> {
+	let key = derive_key(passphrase, &salt)?;
+	let ciphertext = ChaCha20Poly1305::new(Key::from_slice(&*key))
+		.encrypt(&nonce, identity.as_bytes())
+		.map_err(|_| "Failed to encrypt identity")?;
+	let mut data = salt.to_vec();
+	data.extend_from_slice(&nonce);
+	data.extend_from_slice(&ciphertext);
+	Ok(format!("{}{}", PREFIX, base64::encode(data)))
+}
+
+/// Decrypts an identity which was encrypted with `encrypt`.
+pub fn decrypt(data: &str) -> Result<Zeroizing<String>, String> {
+	open(data, &passphrase(false)?)
+}
+
+fn open(data: &str, passphrase: &str) -> Result<Zeroizing<String>, String> {
+	let data = data
+		.trim()
+		.strip_prefix(PREFIX)
+		.and_then(|d| base64::decode(d).ok())
+		.filter(|d| d.len() > SALT_LEN + NONCE_LEN)
+		.ok_or("Invalid encrypted identity")?;
+	let (salt, rest) = data.split_at(SALT_LEN);
+	let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+	let key = derive_key(passphrase, salt)?;
+	let identity = ChaCha20Poly1305::new(Key::from_slice(&*key))
+		.decrypt(Nonce::from_slice(nonce), ciphertext)
+		.map(Zeroizing::new)
+		.map_err(|_| "Wrong passphrase or damaged identity")?;
+	let identity = std::str::from_utf8(&identity).map_err(|_| "Invalid encrypted identity")?;
+	Ok(Zeroizing::new(identity.to_string()))
+}
+
+/// Reads the passphrase from `MAHTS_PASSPHRASE` or asks for it on the terminal.
+fn passphrase(confirm: bool) -> Result<Zeroizing<String>, String> {
+	if let Ok(passphrase) = std::env::var(PASSPHRASE_VAR) {
+		return Ok(Zeroizing::new(passphrase));
+	}
+	let read = |prompt: &str| {
+		rpassword::prompt_password(prompt)
+			.map(Zeroizing::new)
+			.map_err(|e| format!("Failed to read passphrase: {}", e))
+	};
+	let passphrase = read("Passphrase: ")?;
+	if confirm {
+		if passphrase.is_empty() {
+			return Err("The passphrase must not be empty".to_string());
+		}
+		if read("Repeat passphrase: ")? != passphrase {
+			return Err("The passphrases do not match".to_string());
+		}
+	}
+	Ok(passphrase)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const IDENTITY: &str = "1234VsecretKey";
+	/// Salt 0..16, nonce 16..28 and the passphrase `correct horse`, pins the format.
+	const KNOWN: &str = "MAHTS-ENCRYPTED:AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaG35geEuiseSLHAdGkwQ4wc2MDx5pq3sQ5pxgrEMIFQ==";
+
+	#[test]
+	fn roundtrip_with_passphrase_from_env() {
+		std::env::set_var(PASSPHRASE_VAR, "correct horse");
+		let encrypted = encrypt(IDENTITY).unwrap();
+		assert!(is_encrypted(&encrypted));
+		assert_eq!(*decrypt(&encrypted).unwrap(), IDENTITY);
+		std::env::remove_var(PASSPHRASE_VAR);
+	}
+
+	#[test]
+	fn known_answer() {
+		let salt: [u8; SALT_LEN] = core::array::from_fn(|i| i as u8);
+		let nonce: [u8; NONCE_LEN] = core::array::from_fn(|i| (SALT_LEN + i) as u8);
+		let sealed = seal(
+			IDENTITY,
+			"correct horse",
+			salt,
+			Nonce::clone_from_slice(&nonce),
+		)
+		.unwrap();
+		assert_eq!(sealed, KNOWN);
+		assert_eq!(*open(KNOWN, "correct horse").unwrap(), IDENTITY);
+	}
+
+	#[test]
+	fn wrong_passphrase_is_rejected() {
+		assert!(open(KNOWN, "wrong horse").is_err());
+	}
+
+	#[test]
+	fn damaged_data_is_rejected() {
+		assert!(open(&KNOWN[..KNOWN.len() - 8], "correct horse").is_err());
+		assert!(open(&format!("{}AAEC", PREFIX), "correct horse").is_err());
+		assert!(open("AAECAwQF", "correct horse").is_err());
+	}
+}
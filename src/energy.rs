@@ -0,0 +1,212 @@
+//! Measures the energy the cpu uses during a search, with RAPL on Linux and powermetrics on
+//! macOS.
+//!
+//! Both need elevated rights: the RAPL counters are only readable by root on current kernels
+//! and powermetrics must run with sudo.
+
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+#[cfg(target_os = "linux")]
+use std::time::Duration;
+
+use log::{info, warn};
+
+/// How often the RAPL counters are read. They wrap around after a few minutes, and the energy
+/// since the last read is missing from the summary.
+#[cfg(target_os = "linux")]
+const INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default)]
+struct Counter {
+	millijoules: AtomicU64,
+	/// Set after the first measurement.
+	measured: AtomicBool,
+}
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+impl Counter {
+	fn add(&self, joules: f64) {
+		self.millijoules
+			.fetch_add((joules * 1000.0) as u64, Ordering::Relaxed);
+		self.measured.store(true, Ordering::Relaxed);
+	}
+}
+
+/// Sums up the energy in a background thread from its start until it is dropped.
+#[derive(Debug)]
+pub struct EnergyMeter {
+	counter: Arc<Counter>,
+	#[cfg(target_os = "macos")]
+	child: std::process::Child,
+}
+
+impl EnergyMeter {
+	/// Returns `None` and warns if the energy can not be measured on this system.
+	pub fn start() -> Option<Self> {
+		let meter = Self::spawn();
+		if meter.is_none() {
+			warn!(
+				"Can not measure the energy usage, this needs root for RAPL on Linux or \
+				 powermetrics on macOS"
+			);
+		}
+		meter
+	}
+
+	/// The used energy in joules, `None` if nothing was measured.
+	pub fn joules(&self) -> Option<f64> {
+		self.counter
+			.measured
+			.load(Ordering::Relaxed)
+			.then(|| self.counter.millijoules.load(Ordering::Relaxed) as f64 / 1000.0)
+	}
+
+	/// Logs the used energy and the energy per match.
+	pub fn print_summary(&self, matches: Option<u64>) {
+		let joules = match self.joules() {
+			Some(joules) => joules,
+			None => {
+				warn!("No energy usage was measured");
+				return;
+			}
+		};
+		let mut line = format!(
+			"Used {:.1} kJ ({:.3} kWh) of cpu energy",
+			joules / 1000.0,
+			joules / 3.6e6
+		);
+		if let Some(matches) = matches.filter(|m| *m > 0) {
+			line.push_str(&format!(", {:.0} J per match", joules / matches as f64));
+		}
+		info!("{}", line);
+	}
+
+	#[cfg(target_os = "linux")]
+	fn spawn() -> Option<Self> {
+		let mut zones = rapl_zones();
+		if zones.is_empty() {
+			return None;
+		}
+		let counter = Arc::new(Counter::default());
+		let thread_counter = counter.clone();
+		std::thread::spawn(move || loop {
+			std::thread::sleep(INTERVAL);
+			let mut joules = 0.0;
+			for zone in &mut zones {
+				joules += zone.advance();
+			}
+			thread_counter.add(joules);
+		});
+		Some(Self { counter })
+	}
+
+	#[cfg(target_os = "macos")]
+	fn spawn() -> Option<Self> {
+		use std::io::{BufRead, BufReader};
+		use std::process::{Command, Stdio};
+
+		let mut child = Command::new("powermetrics")
+			.args(["--samplers", "cpu_power", "-i", "1000"])
+			.stdout(Stdio::piped())
+			.stderr(Stdio::null())
+			.spawn()
+			.ok()?;
+		let stdout = child.stdout.take()?;
+		let counter = Arc::new(Counter::default());
+		let thread_counter = counter.clone();
+		std::thread::spawn(move || {
+			// Every sample covers one second, so its power in watts is its energy in joules
+			for line in BufReader::new(stdout).lines().flatten() {
+				if let Some(watts) = parse_power(&line) {
+					thread_counter.add(watts);
+				}
+			}
+		});
+		Some(Self { counter, child })
+	}
+
+	#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+	fn spawn() -> Option<Self> {
+		None
+	}
+}
+
+#[cfg(target_os = "macos")]
+impl Drop for EnergyMeter {
+	fn drop(&mut self) {
+		let _ = self.child.kill();
+		let _ = self.child.wait();
+	}
+}
+
+/// Reads the power of Apple Silicon ('CPU Power: 1234 mW') or Intel Macs ('... package power
+/// (CPUs+GT+SA): 8.27W') in watts.
+#[cfg(target_os = "macos")]
+fn parse_power(line: &str) -> Option<f64> {
+	let (name, value) = line.split_once(':')?;
+	if name != "CPU Power" && !name.contains("package power") {
+		return None;
+	}
+	let value = value.trim();
+	if let Some(mw) = value.strip_suffix("mW") {
+		return mw.trim().parse::<f64>().ok().map(|mw| mw / 1000.0);
+	}
+	value.strip_suffix('W')?.trim().parse().ok()
+}
+
+/// A RAPL package domain.
+#[cfg(target_os = "linux")]
+#[derive(Debug)]
+struct RaplZone {
+	path: std::path::PathBuf,
+	/// The counter wraps around at this value.
+	max: u64,
+	last: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl RaplZone {
+	/// Returns the joules since the last call.
+	fn advance(&mut self) -> f64 {
+		let now = match read_u64(&self.path.join("energy_uj")) {
+			Some(now) => now,
+			None => return 0.0,
+		};
+		let delta = if now >= self.last {
+			now - self.last
+		} else {
+			self.max - self.last + now
+		};
+		self.last = now;
+		delta as f64 / 1e6
+	}
+}
+
+/// The package domains, their subdomains like 'intel-rapl:0:0' are part of them.
+#[cfg(target_os = "linux")]
+fn rapl_zones() -> Vec<RaplZone> {
+	let dir = match std::fs::read_dir("/sys/class/powercap") {
+		Ok(dir) => dir,
+		Err(_) => return Vec::new(),
+	};
+	dir.flatten()
+		.filter(|e| {
+			let name = e.file_name();
+			let name = name.to_string_lossy();
+			name.starts_with("intel-rapl:") && name.matches(':').count() == 1
+		})
+		.filter_map(|e| {
+			let path = e.path();
+			Some(RaplZone {
+				max: read_u64(&path.join("max_energy_range_uj"))?,
+				last: read_u64(&path.join("energy_uj"))?,
+				path,
+			})
+		})
+		.collect()
+}
+
+#[cfg(target_os = "linux")]
+fn read_u64(path: &std::path::Path) -> Option<u64> {
+	std::fs::read_to_string(path).ok()?.trim().parse().ok()
+}
@@ -0,0 +1,85 @@
+//! Mass produces fresh identities with a minimum security level and no pattern.
+
+use std::io::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use log::info;
+use rayon::prelude::*;
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::audit::AuditLog;
+use crate::backend;
+use crate::control::{self, is_interrupted};
+use crate::convert::ExportFormat;
+use crate::{Opts, Outcome, Result};
+
+/// Number of offsets between checks for an interruption.
+const CHECK_INTERVAL: u64 = 1 << 16;
+
+#[derive(clap::Args, Debug)]
+pub struct GenerateArgs {
+	#[clap(short, long, default_value = "1")]
+	/// Number of identities to generate.
+	count: u64,
+
+	#[clap(long, default_value = "8")]
+	/// Every identity gets at least this security level.
+	min_level: u8,
+
+	#[clap(long, value_enum, default_value = "obfuscated")]
+	/// How the identities are printed, the nickname of ini files is set with '--nickname'.
+	format: ExportFormat,
+}
+
+pub fn tool_generate(args: GenerateArgs, opts: &Opts) -> Result {
+	let audit = opts.audit_log.as_deref().map(AuditLog::open).transpose()?;
+	let done = AtomicU64::new(0);
+	// Every identity is leveled on its own thread, that is faster than splitting the offsets
+	// of one identity when many are needed
+	(0..args.count)
+		.into_par_iter()
+		.try_for_each(|i| -> std::result::Result<(), String> {
+			let key = backend::generate_key_at(i);
+			let counter = match min_offset(&key, args.min_level) {
+				Some(counter) => counter,
+				None => return Ok(()),
+			};
+			let formatted = args.format.format(&key, Some(counter), &opts.nickname)?;
+			if let Some(audit) = &audit {
+				let pub_key = key.to_pub();
+				let level = get_hash_cash_level(&pub_key.to_ts(), counter);
+				audit.record("generate", &pub_key.get_uid().to_string(), level, counter);
+			}
+			let stdout = std::io::stdout();
+			let mut lock = stdout.lock();
+			let _ = write!(lock, "{}", *formatted);
+			let _ = lock.flush();
+			let done = done.fetch_add(1, Ordering::Relaxed) + 1;
+			info!("Generated {}/{}", done, args.count);
+			Ok(())
+		})?;
+	if is_interrupted() {
+		info!("Interrupted");
+	}
+	Ok(Outcome::Success)
+}
+
+/// The first offset with at least the given level, `None` if interrupted.
+fn min_offset(key: &EccKeyPrivP256, min_level: u8) -> Option<u64> {
+	let omega = key.to_pub().to_ts();
+	let mut offset = 0;
+	loop {
+		if offset % CHECK_INTERVAL == 0 {
+			control::wait_while_paused();
+			if is_interrupted() {
+				return None;
+			}
+		}
+		control::throttle();
+		if get_hash_cash_level(&omega, offset) >= min_level {
+			return Some(offset);
+		}
+		offset += 1;
+	}
+}
@@ -0,0 +1,198 @@
+//! A gRPC interface to run searches, defined in `proto/mah_ts_identity.proto`.
+//!
+//! Every call needs the printed token in the 'authorization' metadata as 'Bearer <token>'. Set
+//! 'MAHTS_GRPC_TOKEN' to choose it.
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+use log::info;
+use tokio_stream::wrappers::ReceiverStream;
+use tokio_stream::Stream;
+use tonic::{Request, Response, Status};
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::control::is_interrupted;
+use crate::jobs::{JobRequest, JobStatus, Jobs};
+use crate::token::Token;
+use crate::{Outcome, Result};
+
+pub mod proto {
+	tonic::include_proto!("mah_ts_identity");
+}
+
+use proto::search_server::{Search, SearchServer};
+
+struct Service {
+	jobs: Arc<Jobs>,
+}
+
+pub fn tool_grpc(addr: &str) -> Result {
+	let addr: SocketAddr = addr
+		.parse()
+		.map_err(|e| format!("Invalid address {}: {}", addr, e))?;
+	let runtime = tokio::runtime::Builder::new_multi_thread()
+		.worker_threads(1)
+		.enable_all()
+		.build()
+		.map_err(|e| format!("Failed to start the async runtime: {}", e))?;
+	let jobs = Arc::new(Jobs::default());
+	let service = Service { jobs: jobs.clone() };
+	let token = Arc::new(Token::from_env("MAHTS_GRPC_TOKEN"));
+	info!("Serving gRPC on {}", addr);
+	// Not logged, the log file and syslog may be readable by others
+	eprintln!("gRPC token: {}", token.as_str());
+	let authorize = move |request: Request<()>| {
+		let authorized = request
+			.metadata()
+			.get("authorization")
+			.and_then(|v| v.to_str().ok())
+			.map_or(false, |v| token.accepts_header(v));
+		if authorized {
+			Ok(request)
+		} else {
+			Err(Status::unauthenticated("Requires the token"))
+		}
+	};
+	runtime
+		.block_on(
+			tonic::transport::Server::builder()
+				.add_service(SearchServer::with_interceptor(service, authorize))
+				.serve_with_shutdown(addr, async {
+					while !is_interrupted() {
+						tokio::time::sleep(Duration::from_millis(100)).await;
+					}
+				}),
+		)
+		.map_err(|e| format!("Failed to serve gRPC on {}: {}", addr, e))?;
+	jobs.stop_all();
+	Ok(Outcome::Success)
+}
+
+fn not_found() -> Status {
+	Status::not_found("Unknown job")
+}
+
+impl From<JobStatus> for proto::JobStatus {
+	fn from(status: JobStatus) -> Self {
+		Self {
+			id: status.id,
+			r#type: status.kind.to_string(),
+			state: status.state.to_string(),
+			elapsed_secs: status.elapsed_secs,
+			attempts: status.attempts,
+			rate: status.rate,
+			patterns: status.patterns.unwrap_or_default(),
+			matches: status.matches.unwrap_or_default(),
+			want_level: status.want_level.unwrap_or_default().into(),
+			level: status.level.unwrap_or_default().into(),
+			offset: status.offset.unwrap_or_default(),
+			results: status
+				.results
+				.unwrap_or_default()
+				.into_iter()
+				// Found wipes its keys on drop, so the fields can not be moved out
+				.map(|f| proto::Identity {
+					uid: f.uid.clone(),
+					key: f.key.clone(),
+					key_obfuscated: f.key_obfuscated.clone(),
+					level: f.level.into(),
+					pattern: f.pattern.clone(),
+					timestamp: f.timestamp.clone(),
+					attempts: f.attempts,
+				})
+				.collect(),
+		}
+	}
+}
+
+#[tonic::async_trait]
+impl Search for Service {
+	type WatchJobStream =
+		Pin<Box<dyn Stream<Item = std::result::Result<proto::JobStatus, Status>> + Send>>;
+
+	async fn start_job(
+		&self,
+		request: Request<proto::StartJobRequest>,
+	) -> std::result::Result<Response<proto::JobId>, Status> {
+		let job = match request.into_inner().job {
+			Some(proto::start_job_request::Job::Find(find)) => JobRequest::Find {
+				patterns: find.patterns,
+				max_matches: (find.max_matches != 0).then(|| find.max_matches),
+			},
+			Some(proto::start_job_request::Job::Level(level)) => JobRequest::Level {
+				identity: level.identity,
+				level: u8::try_from(level.level)
+					.map_err(|_| Status::invalid_argument("The level is too high"))?,
+			},
+			None => return Err(Status::invalid_argument("Requires a job")),
+		};
+		let id = self.jobs.start(job).map_err(Status::invalid_argument)?;
+		info!("Started job {}", id);
+		Ok(Response::new(proto::JobId { id }))
+	}
+
+	async fn list_jobs(
+		&self,
+		_: Request<proto::ListJobsRequest>,
+	) -> std::result::Result<Response<proto::JobList>, Status> {
+		let jobs = self.jobs.list().into_iter().map(Into::into).collect();
+		Ok(Response::new(proto::JobList { jobs }))
+	}
+
+	async fn get_job(
+		&self,
+		request: Request<proto::JobId>,
+	) -> std::result::Result<Response<proto::JobStatus>, Status> {
+		let status = self
+			.jobs
+			.get(request.into_inner().id)
+			.ok_or_else(not_found)?;
+		Ok(Response::new(status.into()))
+	}
+
+	async fn watch_job(
+		&self,
+		request: Request<proto::JobId>,
+	) -> std::result::Result<Response<Self::WatchJobStream>, Status> {
+		let id = request.into_inner().id;
+		self.jobs.get(id).ok_or_else(not_found)?;
+		let jobs = self.jobs.clone();
+		let (send, recv) = tokio::sync::mpsc::channel(4);
+		tokio::spawn(async move {
+			while let Some(mut status) = jobs.get(id) {
+				// The results are only for GetJob, they contain the keys
+				status.results = None;
+				let finished = status.state == "finished";
+				if send.send(Ok(status.into())).await.is_err() || finished {
+					break;
+				}
+				tokio::time::sleep(Duration::from_secs(1)).await;
+			}
+		});
+		Ok(Response::new(Box::pin(ReceiverStream::new(recv))))
+	}
+
+	async fn stop_job(
+		&self,
+		request: Request<proto::JobId>,
+	) -> std::result::Result<Response<proto::JobStatus>, Status> {
+		let id = request.into_inner().id;
+		let status = self.jobs.stop(id).ok_or_else(not_found)?;
+		info!("Stopped job {}", id);
+		Ok(Response::new(status.into()))
+	}
+
+	async fn export(
+		&self,
+		request: Request<proto::ExportRequest>,
+	) -> std::result::Result<Response<proto::ExportResponse>, Status> {
+		let tp_priv = EccKeyPrivP256::import_str(&request.into_inner().identity)
+			.map_err(|_| Status::invalid_argument("Failed to read identity"))?;
+		Ok(Response::new(proto::ExportResponse {
+			key: tp_priv.to_ts_obfuscated(),
+		}))
+	}
+}
@@ -0,0 +1,304 @@
+//! Searches which run in the background, started through the HTTP or gRPC API.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::batch::BatchSizer;
+use crate::control::{self, is_interrupted};
+use crate::output::{Found, Output, OutputFormat};
+use crate::patterns::PatternSet;
+use crate::progress::Progress;
+use crate::stats::ThreadCounters;
+use crate::{best_level, find_pattern_parallel, sha1x, ts3ini, FindPattern, Level, RunData};
+
+/// The most identities a find job collects, it finishes when it has them.
+const MAX_RESULTS: u64 = 1000;
+/// Finished jobs and their results are removed after this time.
+const FINISHED_TTL: Duration = Duration::from_secs(60 * 60);
+/// The most finished jobs which are kept, older ones are removed first.
+const MAX_FINISHED: usize = 100;
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JobRequest {
+	Find {
+		patterns: Vec<String>,
+		#[serde(default)]
+		max_matches: Option<u64>,
+	},
+	/// The identity is a key or `<counter>V<obfuscated key>`, the search continues from the
+	/// counter.
+	Level { identity: String, level: u8 },
+}
+
+/// The progress of a job.
+#[derive(Debug, Serialize)]
+pub struct JobStatus {
+	pub id: u64,
+	#[serde(rename = "type")]
+	pub kind: &'static str,
+	/// `running` or `finished`.
+	pub state: &'static str,
+	pub elapsed_secs: u64,
+	/// Generated keys or checked offsets.
+	pub attempts: u64,
+	pub rate: f64,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub patterns: Option<Vec<String>>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub matches: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub want_level: Option<u8>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub level: Option<u8>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub offset: Option<u64>,
+	/// The found identities, only included when asking for a single job.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub results: Option<Vec<Found>>,
+}
+
+/// All jobs which were started.
+#[derive(Default)]
+pub struct Jobs {
+	jobs: Mutex<BTreeMap<u64, Job>>,
+	next_id: AtomicU64,
+}
+
+struct Job {
+	start: Instant,
+	/// When the job finished.
+	finished: Arc<Mutex<Option<Instant>>>,
+	kind: JobKind,
+}
+
+enum JobKind {
+	Find {
+		data: Arc<RunData>,
+		results: Arc<Mutex<Vec<Found>>>,
+	},
+	Level(Arc<LevelJob>),
+}
+
+struct LevelJob {
+	want_level: u8,
+	attempts: AtomicU64,
+	best: Mutex<Level>,
+	stop: AtomicBool,
+}
+
+impl Jobs {
+	/// Starts a job and returns its id.
+	pub fn start(&self, request: JobRequest) -> Result<u64, String> {
+		let job = start_job(request)?;
+		let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+		let mut jobs = self.jobs.lock().unwrap();
+		prune(&mut jobs);
+		jobs.insert(id, job);
+		Ok(id)
+	}
+
+	pub fn list(&self) -> Vec<JobStatus> {
+		let mut jobs = self.jobs.lock().unwrap();
+		prune(&mut jobs);
+		jobs.iter()
+			.map(|(id, job)| job.status(*id, false))
+			.collect()
+	}
+
+	/// Returns the status of a job including its results.
+	pub fn get(&self, id: u64) -> Option<JobStatus> {
+		let jobs = self.jobs.lock().unwrap();
+		jobs.get(&id).map(|job| job.status(id, true))
+	}
+
+	pub fn stop(&self, id: u64) -> Option<JobStatus> {
+		let jobs = self.jobs.lock().unwrap();
+		jobs.get(&id).map(|job| {
+			job.stop();
+			job.status(id, false)
+		})
+	}
+
+	pub fn stop_all(&self) {
+		for job in self.jobs.lock().unwrap().values() {
+			job.stop();
+		}
+	}
+}
+
+/// Removes jobs which finished more than `FINISHED_TTL` ago, and the oldest finished jobs
+/// beyond `MAX_FINISHED`.
+fn prune(jobs: &mut BTreeMap<u64, Job>) {
+	let mut finished = jobs
+		.iter()
+		.filter_map(|(id, job)| Some((*job.finished.lock().unwrap().as_ref()?, *id)))
+		.collect::<Vec<_>>();
+	finished.sort_unstable();
+	let excess = finished.len().saturating_sub(MAX_FINISHED);
+	for (i, (time, id)) in finished.into_iter().enumerate() {
+		if i < excess || time.elapsed() > FINISHED_TTL {
+			jobs.remove(&id);
+		}
+	}
+}
+
+fn start_job(request: JobRequest) -> Result<Job, String> {
+	let finished = Arc::new(Mutex::new(None));
+	let kind = match request {
+		JobRequest::Find {
+			patterns,
+			max_matches,
+		} => {
+			if patterns.is_empty() {
+				return Err("Requires at least one pattern".to_string());
+			}
+			let patterns = patterns
+				.iter()
+				.map(|p| FindPattern::parse(p))
+				.collect::<Result<Vec<_>, _>>()?;
+			let mut output = Output::new(OutputFormat::Json, None);
+			let results = output.collect();
+			let data = Arc::new(RunData {
+				patterns: PatternSet::new(patterns),
+				max_matches: Some(max_matches.map_or(MAX_RESULTS, |m| m.min(MAX_RESULTS))),
+				matches: AtomicU64::new(0),
+				max_attempts: None,
+				batch: BatchSizer::adaptive(Duration::from_secs(2)),
+				required_per_pattern: None,
+				near_misses: false,
+				predicates: Vec::new(),
+				top: None,
+				output,
+				attempts: AtomicU64::new(0),
+				thread_attempts: ThreadCounters::new(),
+				progress: Progress::new(true),
+				stop: AtomicBool::new(false),
+			});
+			let thread_data = data.clone();
+			let thread_finished = finished.clone();
+			std::thread::spawn(move || {
+				find_pattern_parallel::<false>(&thread_data);
+				*thread_finished.lock().unwrap() = Some(Instant::now());
+			});
+			JobKind::Find { data, results }
+		}
+		JobRequest::Level { identity, level } => {
+			let (counter, tp_priv) = match EccKeyPrivP256::import_str(&identity) {
+				Ok(key) => (0, key),
+				Err(_) => {
+					ts3ini::parse_identity(&identity).map_err(|_| "Failed to read identity")?
+				}
+			};
+			let omega = tp_priv.to_pub().to_ts();
+			let job = Arc::new(LevelJob {
+				want_level: level,
+				attempts: AtomicU64::new(0),
+				best: Mutex::new(Level {
+					level: get_hash_cash_level(&omega, counter),
+					offset: counter,
+				}),
+				stop: AtomicBool::new(false),
+			});
+			let thread_job = job.clone();
+			let thread_finished = finished.clone();
+			std::thread::spawn(move || {
+				thread_job.run(&omega, counter);
+				*thread_finished.lock().unwrap() = Some(Instant::now());
+			});
+			JobKind::Level(job)
+		}
+	};
+	Ok(Job {
+		start: Instant::now(),
+		finished,
+		kind,
+	})
+}
+
+impl Job {
+	fn stop(&self) {
+		match &self.kind {
+			JobKind::Find { data, .. } => data.stop.store(true, Ordering::Relaxed),
+			JobKind::Level(job) => job.stop.store(true, Ordering::Relaxed),
+		}
+	}
+
+	/// Describes the job, results are only included if `details` is set.
+	fn status(&self, id: u64, details: bool) -> JobStatus {
+		let elapsed = self.start.elapsed().as_secs_f64();
+		let mut status = JobStatus {
+			id,
+			kind: "find",
+			state: if self.finished.lock().unwrap().is_some() {
+				"finished"
+			} else {
+				"running"
+			},
+			elapsed_secs: elapsed as u64,
+			attempts: 0,
+			rate: 0.0,
+			patterns: None,
+			matches: None,
+			want_level: None,
+			level: None,
+			offset: None,
+			results: None,
+		};
+		match &self.kind {
+			JobKind::Find { data, results } => {
+				status.attempts = data.attempts.load(Ordering::Relaxed);
+				status.patterns = Some(
+					data.patterns
+						.read()
+						.iter()
+						.map(|p| p.input.clone())
+						.collect(),
+				);
+				status.matches = Some(data.matches.load(Ordering::Relaxed));
+				if details {
+					status.results = Some(results.lock().unwrap().clone());
+				}
+			}
+			JobKind::Level(job) => {
+				let best = job.best.lock().unwrap();
+				status.kind = "level";
+				status.attempts = job.attempts.load(Ordering::Relaxed);
+				status.want_level = Some(job.want_level);
+				status.level = Some(best.level);
+				status.offset = Some(best.offset);
+			}
+		}
+		status.rate = status.attempts as f64 / elapsed.max(0.001);
+		status
+	}
+}
+
+impl LevelJob {
+	fn run(&self, omega: &str, mut start_off: u64) {
+		let midstate = sha1x::Midstate::new(omega);
+		let mut batch = BatchSizer::adaptive(Duration::from_secs(2));
+		while !self.stop.load(Ordering::Relaxed) && !is_interrupted() {
+			control::wait_while_paused();
+			let batch_size = batch.start();
+			let max_res = best_level(&midstate, start_off, start_off + batch_size);
+			batch.finish();
+			start_off += batch_size;
+			self.attempts.fetch_add(batch_size, Ordering::Relaxed);
+
+			let mut best = self.best.lock().unwrap();
+			if max_res.level > best.level {
+				*best = max_res;
+				if best.level >= self.want_level {
+					break;
+				}
+			}
+		}
+	}
+}
@@ -0,0 +1,78 @@
+//! Private keys in standard formats, to exchange them with OpenSSL and other ECDSA tools.
+
+use p256::pkcs8::{DecodePrivateKey, EncodePrivateKey, LineEnding};
+use p256::SecretKey;
+use tsproto_types::crypto::EccKeyPrivP256;
+use zeroize::Zeroizing;
+
+/// `SecretKey` wipes itself on drop.
+fn to_secret(key: &EccKeyPrivP256) -> Result<SecretKey, String> {
+	SecretKey::from_be_bytes(&Zeroizing::new(key.to_short()))
+		.map_err(|_| "Invalid private key".to_string())
+}
+
+fn from_secret(secret: &SecretKey) -> Result<EccKeyPrivP256, String> {
+	EccKeyPrivP256::from_short(&Zeroizing::new(secret.to_be_bytes()))
+		.map_err(|_| "Invalid private key".to_string())
+}
+
+/// A PKCS#8 PEM file like `openssl pkey` writes it.
+pub fn to_pem(key: &EccKeyPrivP256) -> Result<Zeroizing<String>, String> {
+	let pem = to_secret(key)?
+		.to_pkcs8_pem(LineEnding::LF)
+		.map_err(|e| format!("Failed to encode key: {}", e))?;
+	Ok(Zeroizing::new(pem.to_string()))
+}
+
+/// A PKCS#8 DER file.
+pub fn to_der(key: &EccKeyPrivP256) -> Result<Zeroizing<Vec<u8>>, String> {
+	let der = to_secret(key)?
+		.to_pkcs8_der()
+		.map_err(|e| format!("Failed to encode key: {}", e))?;
+	Ok(Zeroizing::new(der.as_bytes().to_vec()))
+}
+
+pub fn is_pem(data: &str) -> bool {
+	data.trim_start().starts_with("-----BEGIN")
+}
+
+/// Reads a PKCS#8 or SEC1 (`EC PRIVATE KEY`) PEM file.
+pub fn from_pem(data: &str) -> Result<EccKeyPrivP256, String> {
+	let secret = SecretKey::from_pkcs8_pem(data)
+		.map_err(|e| e.to_string())
+		.or_else(|_| SecretKey::from_sec1_pem(data).map_err(|e| e.to_string()))
+		.map_err(|e| format!("Failed to read PEM key: {}", e))?;
+	from_secret(&secret)
+}
+
+/// Reads a PKCS#8 or SEC1 DER file.
+pub fn from_der(data: &[u8]) -> Result<EccKeyPrivP256, String> {
+	let secret = SecretKey::from_pkcs8_der(data)
+		.map_err(|e| e.to_string())
+		.or_else(|_| SecretKey::from_sec1_der(data).map_err(|e| e.to_string()))
+		.map_err(|e| format!("Failed to read DER key: {}", e))?;
+	from_secret(&secret)
+}
+
+/// Reads a bare 32 byte private scalar in hex or base64.
+pub fn from_raw(data: &str) -> Result<EccKeyPrivP256, String> {
+	let data = data.trim();
+	let bytes = Zeroizing::new(
+		if data.len() == 64 && data.bytes().all(|b| b.is_ascii_hexdigit()) {
+			(0..data.len())
+				.step_by(2)
+				.map(|i| u8::from_str_radix(&data[i..i + 2], 16))
+				.collect::<Result<Vec<_>, _>>()
+				.map_err(|e| e.to_string())?
+		} else {
+			base64::decode(data).map_err(|_| "The raw key is neither hex nor base64")?
+		},
+	);
+	if bytes.len() != 32 {
+		return Err(format!(
+			"A raw key has 32 bytes, but this one has {}",
+			bytes.len()
+		));
+	}
+	EccKeyPrivP256::from_short(&bytes).map_err(|_| "Invalid private key".to_string())
+}
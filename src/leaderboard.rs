@@ -0,0 +1,76 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+
+use log::info;
+use zeroize::Zeroize;
+
+/// The best candidates of a search, ordered by a score.
+#[derive(Debug)]
+pub struct Leaderboard {
+	size: usize,
+	/// The score a candidate has to beat to get on the board, 0 while it is not full.
+	min_score: AtomicU32,
+	entries: Mutex<Vec<Entry>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Entry {
+	pub score: u32,
+	pub pattern: String,
+	pub uid: String,
+	pub key: String,
+}
+
+impl Drop for Entry {
+	fn drop(&mut self) {
+		self.key.zeroize();
+	}
+}
+
+impl Leaderboard {
+	pub fn new(size: usize) -> Self {
+		Self {
+			size,
+			min_score: AtomicU32::new(0),
+			entries: Mutex::new(Vec::with_capacity(size + 1)),
+		}
+	}
+
+	/// A cheap check if a candidate with this score would get on the board.
+	pub fn qualifies(&self, score: u32) -> bool {
+		score > self.min_score.load(Ordering::Relaxed)
+	}
+
+	pub fn insert(&self, entry: Entry) {
+		let mut entries = self.entries.lock().unwrap();
+		if !self.qualifies(entry.score) {
+			return;
+		}
+		let pos = entries.partition_point(|e| e.score >= entry.score);
+		entries.insert(pos, entry);
+		entries.truncate(self.size);
+		if entries.len() == self.size {
+			self.min_score
+				.store(entries[self.size - 1].score, Ordering::Relaxed);
+		}
+	}
+
+	pub fn entries(&self) -> Vec<Entry> {
+		self.entries.lock().unwrap().clone()
+	}
+
+	pub fn print(&self) {
+		let entries = self.entries.lock().unwrap();
+		info!("Best {} candidates:", entries.len());
+		for (i, e) in entries.iter().enumerate() {
+			info!(
+				"{:>3}. {} bits of {} UID: {} KEY: {}",
+				i + 1,
+				e.score,
+				e.pattern,
+				e.uid,
+				e.key
+			);
+		}
+	}
+}
@@ -0,0 +1,226 @@
+//! Mirrors the status messages into a file with '--log-file' and to syslog, independent of
+//! what is printed.
+//!
+//! The file is rotated when it becomes too large or too old: 'search.log' is renamed to
+//! 'search.log.1', the older files move up by one and the oldest one is deleted.
+
+use std::borrow::Cow;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+#[cfg(unix)]
+use crate::syslog::Syslog;
+
+static LOG_FILE: Mutex<Option<RotatingFile>> = Mutex::new(None);
+
+#[derive(Debug)]
+pub struct RotatingFile {
+	path: PathBuf,
+	file: File,
+	size: u64,
+	opened: SystemTime,
+	max_size: u64,
+	max_age: Option<Duration>,
+	/// Number of old files which are kept.
+	keep: u32,
+}
+
+impl RotatingFile {
+	pub fn open(
+		path: &Path,
+		max_size: u64,
+		max_age: Option<Duration>,
+		keep: u32,
+	) -> Result<Self, String> {
+		let file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(path)
+			.map_err(|e| format!("Failed to open log file {}: {}", path.display(), e))?;
+		let metadata = file
+			.metadata()
+			.map_err(|e| format!("Failed to read log file {}: {}", path.display(), e))?;
+		Ok(Self {
+			path: path.to_path_buf(),
+			file,
+			size: metadata.len(),
+			// A file from an earlier run counts from its start
+			opened: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+			max_size,
+			max_age,
+			keep,
+		})
+	}
+
+	fn rotated_path(&self, n: u32) -> PathBuf {
+		let mut path = self.path.clone().into_os_string();
+		path.push(format!(".{}", n));
+		PathBuf::from(path)
+	}
+
+	fn is_due(&self) -> bool {
+		self.size >= self.max_size
+			|| self.max_age.map_or(false, |age| {
+				self.opened
+					.elapsed()
+					.map_or(false, |elapsed| elapsed >= age)
+			})
+	}
+
+	fn rotate(&mut self) -> std::io::Result<()> {
+		if self.keep == 0 {
+			std::fs::remove_file(&self.path)?;
+		} else {
+			let _ = std::fs::remove_file(self.rotated_path(self.keep));
+			for n in (1..self.keep).rev() {
+				let _ = std::fs::rename(self.rotated_path(n), self.rotated_path(n + 1));
+			}
+			std::fs::rename(&self.path, self.rotated_path(1))?;
+		}
+		self.file = OpenOptions::new()
+			.create(true)
+			.append(true)
+			.open(&self.path)?;
+		self.size = 0;
+		self.opened = SystemTime::now();
+		Ok(())
+	}
+
+	fn write_line(&mut self, line: &str) {
+		if self.size > 0 && self.is_due() {
+			if let Err(e) = self.rotate() {
+				eprintln!("Failed to rotate log file {}: {}", self.path.display(), e);
+			}
+		}
+		let line = format!(
+			"{} {}\n",
+			humantime::format_rfc3339_seconds(SystemTime::now()),
+			line
+		);
+		// Logging must not stop the search, e.g. on a full disk
+		if self.file.write_all(line.as_bytes()).is_ok() {
+			self.size += line.len() as u64;
+		}
+	}
+}
+
+/// Writes a line to the log file, if there is one.
+pub fn mirror(line: &str) {
+	if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+		file.write_line(line);
+	}
+}
+
+/// Logs to stderr like `stderr` and to the log file and syslog up to `file_level`.
+struct TeeLogger {
+	stderr: env_logger::Logger,
+	file_level: LevelFilter,
+	#[cfg(unix)]
+	syslog: Option<Syslog>,
+}
+
+impl Log for TeeLogger {
+	fn enabled(&self, metadata: &Metadata) -> bool {
+		self.stderr.enabled(metadata) || metadata.level() <= self.file_level
+	}
+
+	fn log(&self, record: &Record) {
+		if self.stderr.matches(record) {
+			self.stderr.log(record);
+		}
+		if record.level() <= self.file_level {
+			let message = record.args().to_string();
+			mirror(&format!("{:<5} {}", record.level(), redact_keys(&message)));
+			#[cfg(unix)]
+			if let Some(syslog) = &self.syslog {
+				syslog.send_log(record.level(), &message);
+			}
+		}
+	}
+
+	fn flush(&self) {
+		self.stderr.flush();
+		if let Some(file) = LOG_FILE.lock().unwrap().as_mut() {
+			let _ = file.file.flush();
+		}
+	}
+}
+
+/// Where messages go besides stderr.
+#[derive(Default)]
+pub struct Targets {
+	pub file: Option<RotatingFile>,
+	#[cfg(unix)]
+	pub syslog: Option<Syslog>,
+}
+
+impl Targets {
+	fn is_empty(&self) -> bool {
+		#[cfg(unix)]
+		{
+			if self.syslog.is_some() {
+				return false;
+			}
+		}
+		self.file.is_none()
+	}
+}
+
+/// Installs the logger, messages up to `level` also go to the other targets.
+pub fn init(stderr: env_logger::Logger, targets: Targets, level: LevelFilter) {
+	let file_level = if targets.is_empty() {
+		LevelFilter::Off
+	} else {
+		level
+	};
+	*LOG_FILE.lock().unwrap() = targets.file;
+	log::set_max_level(stderr.filter().max(file_level));
+	log::set_boxed_logger(Box::new(TeeLogger {
+		stderr,
+		file_level,
+		#[cfg(unix)]
+		syslog: targets.syslog,
+	}))
+	.expect("The logger is only installed once");
+}
+
+/// Replaces the private keys in messages like 'UID: ... KEY: <key>', such as near misses and
+/// the top list. Only stderr gets them, the file and syslog are often readable by others.
+pub fn redact_keys(message: &str) -> Cow<'_, str> {
+	const MARKER: &str = "KEY: ";
+	if !message.contains(MARKER) {
+		return Cow::Borrowed(message);
+	}
+	let mut redacted = String::with_capacity(message.len());
+	let mut rest = message;
+	while let Some(pos) = rest.find(MARKER) {
+		let (before, after) = rest.split_at(pos + MARKER.len());
+		redacted.push_str(before);
+		redacted.push_str("<redacted>");
+		let end = after.find(char::is_whitespace).unwrap_or(after.len());
+		rest = &after[end..];
+	}
+	redacted.push_str(rest);
+	Cow::Owned(redacted)
+}
+
+/// Reads a size like '10M', with the binary suffixes K, M and G.
+pub fn parse_size(s: &str) -> Result<u64, String> {
+	let invalid = || format!("Invalid size '{}', use e.g. '512K' or '10M'", s);
+	let (number, factor) = match s.trim().to_ascii_uppercase() {
+		n if n.ends_with('K') => (n[..n.len() - 1].to_string(), 1 << 10),
+		n if n.ends_with('M') => (n[..n.len() - 1].to_string(), 1 << 20),
+		n if n.ends_with('G') => (n[..n.len() - 1].to_string(), 1 << 30),
+		n => (n, 1),
+	};
+	let size = number.trim().parse::<u64>().map_err(|_| invalid())?;
+	match size.checked_mul(factor) {
+		Some(size) if size > 0 => Ok(size),
+		_ => Err(invalid()),
+	}
+}
@@ -1,56 +1,920 @@
+use std::io::IsTerminal;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 
 use byteorder::{BigEndian, ByteOrder};
-use clap::Parser;
-use flakebi_ring::signature;
+use clap::{CommandFactory, Parser};
+use log::{debug, error, info, warn, LevelFilter};
 use rayon::prelude::*;
 use sha1::{Digest, Sha1};
 use tsproto::algorithms::get_hash_cash_level;
 use tsproto_types::crypto::{EccKeyPrivP256, EccKeyPubP256};
+use zeroize::{Zeroize, Zeroizing};
 
-type Result = std::result::Result<(), String>;
+mod api;
+mod audit;
+mod awake;
+mod backend;
+mod batch;
+mod bench;
+mod calibration;
+mod clipboard;
+mod cluster;
+mod config;
+#[cfg(feature = "test-connect")]
+mod connect;
+mod control;
+mod convert;
+mod daemon;
+#[cfg(feature = "dashboard")]
+mod dashboard;
+mod db;
+mod doctor;
+mod encrypt;
+mod energy;
+mod generate;
+#[cfg(feature = "grpc")]
+mod grpc;
+mod jobs;
+mod keys;
+mod leaderboard;
+mod logfile;
+mod mnemonic;
+mod notify;
+mod output;
+mod patterns;
+mod pipeline;
+mod pool;
+mod predicate;
+mod priority;
+mod progress;
+mod randomart;
+mod recipient;
+mod report;
+mod rng;
+#[cfg(windows)]
+mod service;
+mod sha1x;
+mod shard;
+mod sign;
+mod state;
+mod stats;
+#[cfg(unix)]
+mod status;
+mod store;
+#[cfg(unix)]
+mod syslog;
+mod thermal;
+mod token;
+mod ts3client;
+mod ts3ini;
+mod tui;
+mod tune;
+
+use batch::BatchSizer;
+use control::is_interrupted;
+use leaderboard::{Entry, Leaderboard};
+use output::{Found, Output, OutputFormat, Template};
+use patterns::{PatternFile, PatternSet};
+use predicate::Predicate;
+use progress::Progress;
+use stats::{Stats, StatsTimer, ThreadCounters, ThreadMonitor};
+
+type Result = std::result::Result<Outcome, String>;
+
+/// How a tool finished, this decides the exit code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Outcome {
+	Success,
+	/// A search limit was reached before anything was found.
+	NotFound,
+}
+
+/// Exit code for errors while running, e.g. a file which can not be written.
+const EXIT_ERROR: i32 = 1;
+/// Exit code for invalid options, identities or patterns, like clap uses for usage errors.
+const EXIT_INVALID_INPUT: i32 = 2;
+/// Exit code when a search limit was reached without finding anything.
+const EXIT_NOT_FOUND: i32 = 3;
+/// Exit code after the search was stopped with Ctrl+C, like a shell uses for SIGINT.
+const EXIT_INTERRUPTED: i32 = 130;
+
+const EXIT_CODES: &str = "EXIT CODES:
+    0    Success, e.g. a match was found
+    1    Error while running
+    2    Invalid options, identity or patterns
+    3    A search limit was reached without a match
+    130  Interrupted";
 
 #[derive(Parser, Debug)]
-#[clap(about, author)]
+#[clap(about, author, after_help = EXIT_CODES)]
 struct Opts {
 	#[clap(short, long)]
 	/// Stops searching after the first match.
 	exit_when_found: bool,
 
+	#[clap(long, conflicts_with = "exit-when-found", value_parser = clap::value_parser!(u64).range(1..))]
+	/// Stops searching after this many matches for all patterns together.
+	max_matches: Option<u64>,
+
+	#[clap(long, conflicts_with = "exit-when-found")]
+	/// Finds one identity per pattern and stops when every pattern has a match.
+	find_all: bool,
+
+	#[clap(
+		long,
+		conflicts_with_all = &["exit-when-found", "find-all"],
+		value_parser = clap::value_parser!(u64).range(1..)
+	)]
+	/// Finds this many identities per pattern and stops when every pattern has enough matches.
+	count_per_pattern: Option<u64>,
+
+	#[clap(long)]
+	/// Reports identities which match the most leading characters of a pattern so far.
+	near_misses: bool,
+
+	#[clap(long, value_parser = humantime::parse_duration, default_value = "30days")]
+	/// Flags patterns which are expected to take longer than this (requires '--bench').
+	eta_warn: Duration,
+
+	#[clap(long, value_parser = humantime::parse_duration, default_value = "1year")]
+	/// Refuses to search patterns which are expected to take longer than this, at the stored
+	/// or a briefly measured key rate. On a terminal, the search can be confirmed instead.
+	max_eta: Duration,
+
+	#[clap(long)]
+	/// Searches patterns even if they are expected to take longer than '--max-eta'.
+	force: bool,
+
+	#[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+	/// Keeps the given number of best candidates by matching bits and prints them at the end.
+	/// Send SIGQUIT (Ctrl+\) to print them while searching.
+	top: Option<u64>,
+
+	#[clap(long, value_parser = humantime::parse_duration)]
+	/// Stops searching after this time, e.g. '6h' or '1d 12h'. Exits with code 3 if nothing was
+	/// found.
+	max_time: Option<Duration>,
+
+	#[clap(long)]
+	/// Stops searching after generating this many keys. Exits with code 3 if nothing was found.
+	max_attempts: Option<u64>,
+
 	#[clap(short, long)]
 	/// Specifies how many threads should be used for the task. By default this will match the cpu cores/hyperthreads
 	threads: Option<usize>,
 
+	#[clap(long, value_name = "CORES", require_equals = true, value_parser = pool::CoreList::parse)]
+	/// Pins every worker thread to a cpu core. Optionally takes a list of cores like
+	/// '--pin-threads=0-3,8', the thread count then defaults to the number of listed cores.
+	pin_threads: Option<Option<pool::CoreList>>,
+
+	#[clap(long, conflicts_with_all = &["threads", "pin-threads", "perf-cores-only", "numa"])]
+	/// Tries a few thread counts and, for the level search, batch sizes at startup and uses the
+	/// fastest.
+	autotune: bool,
+
+	#[clap(long, conflicts_with = "pin-threads")]
+	/// Only uses the performance cores of hybrid cpus, efficiency cores slow down every batch.
+	/// Threads are pinned to the performance cores.
+	perf_cores_only: bool,
+
+	#[clap(long, conflicts_with_all = &["threads", "pin-threads", "perf-cores-only"])]
+	/// Uses one thread pool per NUMA node with threads pinned to the node. Only affects the
+	/// pattern search.
+	numa: bool,
+
+	#[clap(long)]
+	/// Detaches from the terminal and runs in the background. Results are only written to
+	/// '--output' or '--db'. Supports systemd readiness and watchdog notifications.
+	daemon: bool,
+
+	#[cfg(windows)]
+	#[clap(long, hide = true)]
+	/// Set when started by the Windows service manager.
+	windows_service: bool,
+
+	#[clap(long, conflicts_with = "priority")]
+	/// Only uses cpu time which nothing else wants, a shortcut for '--priority idle'.
+	background: bool,
+
+	#[clap(long, value_enum)]
+	/// The scheduling priority of the search, maps to nice on unix and the priority class on
+	/// Windows.
+	priority: Option<priority::Priority>,
+
+	#[clap(long, value_name = "PERCENT", value_parser = clap::value_parser!(u32).range(1..=100))]
+	/// Limits the cpu usage to roughly this percentage by letting all threads pause regularly.
+	cpu_limit: Option<u32>,
+
+	#[clap(long, value_enum, global = true)]
+	/// The implementation which generates keys, defaults to ring if it was built in.
+	backend: Option<backend::BackendKind>,
+
+	#[clap(long, value_enum, global = true)]
+	/// Where the private keys come from. Anything but 'system' uses the p256 backend.
+	rng: Option<rng::RngKind>,
+
+	#[clap(long, value_name = "PATH", global = true, requires = "rng")]
+	/// Seeds '--rng chacha-seeded' with 32 bytes from this file or device, e.g. /dev/hwrng.
+	rng_seed_file: Option<PathBuf>,
+
+	#[clap(long, value_name = "HEX", global = true, conflicts_with = "rng")]
+	/// Derives every key from this seed of 64 hex characters and its number, so runs can be
+	/// repeated and their results verified. Uses the p256 backend. Only for testing, the keys
+	/// are only as secret as the seed.
+	seed: Option<String>,
+
+	#[clap(
+		long,
+		value_name = "WORDS",
+		global = true,
+		conflicts_with_all = &["rng", "seed"]
+	)]
+	/// Derives every key from this BIP39 phrase like '--seed', so found identities can be
+	/// recreated from the phrase. '-' reads it from stdin. The 'mnemonic' subcommand creates
+	/// a phrase, 'MAHTS_MNEMONIC_PASSPHRASE' sets an optional passphrase.
+	mnemonic: Option<String>,
+
+	#[clap(long, value_name = "N", global = true)]
+	/// The number of the first key with '--seed' or '--mnemonic', to split the keys between
+	/// machines. The number of a found key is its '{attempts}' minus one, plus this start.
+	seed_start: Option<u64>,
+
+	#[clap(
+		long,
+		value_name = "N",
+		global = true,
+		conflicts_with_all = &["identity", "identity-file", "raw-key", "seed-start"]
+	)]
+	/// Uses the key with this number of '--seed' or '--mnemonic' as the identity.
+	key_number: Option<u64>,
+
+	#[clap(long, value_name = "CELSIUS")]
+	/// Lowers the cpu usage while the cpu is hotter than this temperature and raises it again
+	/// once it cooled down. Only supported on Linux.
+	max_temp: Option<f64>,
+
+	#[clap(long)]
+	/// Measures the energy the cpu used and adds it to the summary at the end, for the pattern
+	/// search also per match. Needs root, uses RAPL on Linux and powermetrics on macOS.
+	energy: bool,
+
+	#[clap(long, global = true)]
+	/// Keeps the system from going to sleep while searching, with systemd-inhibit on Linux,
+	/// caffeinate on macOS and SetThreadExecutionState on Windows. The screen may still turn
+	/// off.
+	keep_awake: bool,
+
 	#[clap()]
-	/// All patterns to search for. Use an '_' as a wildcard.
+	/// All patterns to search for. Use an '_' as a wildcard. A '*' starts a suffix which the
+	/// end of the uid must match, like 'Cat*xyz'.
 	patterns: Vec<String>,
 
+	#[clap(long, alias = "patterns", value_name = "PATH")]
+	/// Reads further patterns from this file, one per line. Lines starting with '#' are
+	/// ignored. While searching, the file is reloaded when it changes or on SIGHUP.
+	///
+	/// With '-', patterns are read from stdin while searching and every pattern is
+	/// acknowledged with a 'SATISFIED: <pattern>' line on stdout once it has enough matches
+	/// (one by default). The search ends when stdin is closed and all patterns are satisfied.
+	patterns_file: Option<PathBuf>,
+
+	#[clap(long, value_name = "LIB")]
+	/// Only reports identities which this dynamic library accepts, can be given multiple
+	/// times. The library exports 'mahts_predicate', see src/predicate.rs. Without patterns,
+	/// every identity is checked.
+	predicate: Vec<PathBuf>,
+
 	#[clap(short, long)]
 	/// Run a small bench before starting the real search to add time estimates for all patterns.
 	bench: bool,
 
-	#[clap(short, long)]
+	#[clap(short, long, global = true)]
+	/// The private key of an identity, also accepts TeamSpeak identity strings like
+	/// '<counter>V<obfuscated key>'. '-' reads it from stdin. Defaults to the
+	/// 'MAHTS_IDENTITY' environment variable. Keys on the command line end up in the shell
+	/// history, so prefer one of the other ways.
 	identity: Option<String>,
 
+	#[clap(long, value_name = "PATH", global = true, conflicts_with = "identity")]
+	/// Reads the private key of the identity from this file. Identities exported from the
+	/// TeamSpeak 3 client are read from their [Identity] section, PEM and DER files in the
+	/// PKCS#8 or SEC1 format are also supported.
+	identity_file: Option<PathBuf>,
+
+	#[clap(
+		long,
+		value_name = "KEY",
+		global = true,
+		conflicts_with_all = &["identity", "identity-file"]
+	)]
+	/// A bare 32 byte private scalar in hex or base64, as some bots store keys.
+	raw_key: Option<String>,
+
+	#[clap(skip)]
+	/// The security level counter of a TeamSpeak identity string like
+	/// '<counter>V<obfuscated key>'.
+	counter: Option<u64>,
+
 	#[clap(short = 'x', long)]
 	/// Converts a private key to a ts-like obfucasted key which can be imported in the ts3 ui.
 	export: bool,
 
+	#[clap(long)]
+	/// Copies the exported key or the last found identity to the clipboard, ready to paste in
+	/// the identity import of the TeamSpeak client.
+	clipboard: bool,
+
+	#[clap(long)]
+	/// Also prints the exported or found private keys as 24 words, a backup for paper which
+	/// '--identity' reads again.
+	mnemonic_backup: bool,
+
+	#[clap(
+		long,
+		requires = "export",
+		conflicts_with_all = &["pem", "der", "ts3-ini", "ts3audiobot", "sinusbot"]
+	)]
+	/// Encrypts the exported key with a passphrase, for backups. The passphrase is asked for
+	/// or read from the 'MAHTS_PASSPHRASE' environment variable. '--identity' accepts the
+	/// encrypted key.
+	encrypt: bool,
+
+	#[clap(long, requires = "export")]
+	/// Prints the exported key as PKCS#8 PEM, e.g. for 'openssl pkey'.
+	pem: bool,
+
+	#[clap(long, value_name = "PATH", requires = "export")]
+	/// Writes the exported key to a PKCS#8 DER file.
+	der: Option<PathBuf>,
+
+	#[clap(long, conflicts_with = "export")]
+	/// Converts an obfuscated key, e.g. from a TeamSpeak config, back to the plain key which
+	/// '--identity' expects.
+	deobfuscate: bool,
+
+	#[clap(long, value_name = "PATH", requires = "export")]
+	/// Writes the exported identity to an ini file which the "Import identity" dialog of the
+	/// TeamSpeak 3 client accepts.
+	ts3_ini: Option<PathBuf>,
+
+	#[clap(long, requires = "export")]
+	/// Prints the exported identity as the '[connect.identity]' table of a TS3AudioBot bot
+	/// config, with the offset of '--offset' or the counter of the identity.
+	ts3audiobot: bool,
+
+	#[clap(long, requires = "export")]
+	/// Prints the exported identity as the json instance settings of SinusBot, with the offset
+	/// of '--offset' or the counter of the identity.
+	sinusbot: bool,
+
+	#[clap(long, default_value = "MahTsIdentity")]
+	/// The nickname and name of the identity in the '--ts3-ini' file and the ini files of
+	/// '--batch'.
+	nickname: String,
+
+	#[clap(
+		long,
+		value_name = "PATH",
+		requires = "export",
+		conflicts_with_all = &[
+			"encrypt",
+			"pem",
+			"der",
+			"ts3-ini",
+			"ts3audiobot",
+			"sinusbot",
+			"clipboard"
+		]
+	)]
+	/// Exports every identity in this file, one per line in any format of '--identity'.
+	batch: Option<PathBuf>,
+
+	#[clap(long, value_enum, default_value = "obfuscated")]
+	/// The format of the identities exported with '--batch'.
+	batch_format: convert::ExportFormat,
+
+	#[clap(long, value_name = "DIR", requires = "batch")]
+	/// Writes one file per identity of '--batch' to this directory, named after the uid.
+	/// Without it, all identities are printed to stdout.
+	batch_dir: Option<PathBuf>,
+
+	#[clap(long)]
+	/// The hashcash offset of the security level for '--ts3-ini' and '--check', as printed by
+	/// '--level'. Defaults to the counter of the identity, or 0 for '--ts3-ini'.
+	offset: Option<u64>,
+
+	#[clap(long, conflicts_with_all = &["export", "level"])]
+	/// Prints the security level of a public key at '--offset', e.g. to verify the level a
+	/// client claims. The key is given with '--omega' or taken from the identity.
+	check: bool,
+
+	#[clap(long, value_name = "KEY", requires = "check")]
+	/// The public key for '--check' in the base64 format of TeamSpeak.
+	omega: Option<String>,
+
 	#[clap(short, long)]
 	/// Improves the security level of an identity
 	level: Option<u64>,
+
+	#[clap(
+		long,
+		value_name = "PATH",
+		requires = "level",
+		conflicts_with_all = &["identity", "identity-file", "raw-key"]
+	)]
+	/// Improves the level of every identity in this file, one after another. Each line holds
+	/// an identity in any format of '--identity', empty lines and lines starting with '#' are
+	/// skipped.
+	identities_file: Option<PathBuf>,
+
+	#[clap(long, value_name = "PATH", requires = "level")]
+	/// Appends the identity with its counter to this file whenever the level search reaches a
+	/// new best level, so an aborted search keeps the levels it passed. Each line looks like
+	/// 'LEVEL: <level> IDENTITY: <counter>V<obfuscated key>', '--identity' accepts the
+	/// identity part.
+	milestones: Option<PathBuf>,
+
+	#[cfg(feature = "test-connect")]
+	#[clap(long, value_name = "HOST:PORT", conflicts_with = "level")]
+	/// Improves the security level until this server accepts the identity, this asks the
+	/// server for the level it requires. Replaces '--level'.
+	for_server: Option<String>,
+
+	#[clap(long, value_parser = shard::Shard::parse)]
+	/// Only checks a part of the offsets when leveling, like '3/8' for the third of eight
+	/// independent invocations, so they never check the same offsets.
+	shard: Option<shard::Shard>,
+
+	#[clap(long, value_name = "OFFSET", requires = "level")]
+	/// Stops the level search after this offset and reports the best level found so far.
+	/// Exits with code 3 if the wanted level was not reached. TeamSpeak clients do not accept
+	/// arbitrarily large counters, so offsets far beyond 2^32 are of little use anyway.
+	max_offset: Option<u64>,
+
+	#[clap(long, requires = "level")]
+	/// Only estimates how long reaching '--level' takes, from the stored benchmark results or
+	/// a short measurement. Every level doubles the expected time.
+	estimate: bool,
+
+	#[clap(long, value_enum, default_value = "text")]
+	/// How found identities are printed. 'json' streams one object per line.
+	output_format: OutputFormat,
+
+	#[clap(long, value_enum, default_value = "auto")]
+	/// Highlights the characters of found uids which match the pattern and dims the wildcards.
+	color: output::ColorChoice,
+
+	#[clap(long)]
+	/// A custom line format for found identities, e.g. "{uid};{key_obfuscated};{level}".
+	/// Available placeholders: {uid}, {key}, {key_obfuscated}, {level}, {pattern}, {timestamp},
+	/// {attempts}.
+	format: Option<String>,
+
+	#[clap(short, long)]
+	/// Appends every found identity to this file, each write is synced to disk.
+	output: Option<PathBuf>,
+
+	#[clap(long, value_name = "PER_SEC", default_value = "10")]
+	/// Prints at most this many found identities per second to stdout when they are also
+	/// written to '--output', '--output-dir', '--db' or '--store', and logs how many more were
+	/// found. Easy patterns match too often to read them all. 0 prints every identity.
+	print_limit: u32,
+
+	#[clap(long, value_name = "PATH")]
+	/// Never reports identities with a uid from this file, e.g. the uids of existing or banned
+	/// users of a server. The file has one uid per line.
+	blocklist: Option<PathBuf>,
+
+	#[clap(long, value_name = "PATH", global = true)]
+	/// Appends a record of every generated identity to this file, with the time, host,
+	/// version and command line but without the private key.
+	audit_log: Option<PathBuf>,
+
+	#[clap(long, value_name = "DIR")]
+	/// Writes every found identity to its own file '<uid>.json' or '<uid>.ini' in this
+	/// directory. Files appear complete or not at all.
+	output_dir: Option<PathBuf>,
+
+	#[clap(long, value_enum, default_value = "json")]
+	/// The format of the files in '--output-dir', ini files use '--nickname'.
+	output_dir_format: output::DirFormat,
+
+	#[clap(
+		long,
+		value_name = "RECIPIENT",
+		conflicts_with_all = &["encrypt", "pem", "der", "ts3-ini", "ts3audiobot", "sinusbot"]
+	)]
+	/// Encrypts the private keys in '--output' or the key of '--export' to an age public key
+	/// ('age1...') or a GPG key id, so the file is useless to anyone without the matching
+	/// private key. Age plugin recipients like 'age1yubikey1...' and GPG keys on an OpenPGP
+	/// card keep the key on a hardware token. The keys are base64 encoded, decrypt them with
+	/// 'base64 -d | age -d -i <key file>' or 'base64 -d | gpg -d'.
+	encrypt_to: Option<String>,
+
+	#[clap(long)]
+	/// Records every found identity in this SQLite database. Already stored uids are skipped.
+	db: Option<PathBuf>,
+
+	#[clap(long)]
+	/// Adds found identities and the leveled identity to the keystore of 'store'.
+	store: bool,
+
+	#[clap(long, value_name = "PATH", global = true)]
+	/// The keystore database for '--store' and 'store'. Defaults to 'mahtsidentity/store.db'
+	/// in the data directory of the user.
+	store_path: Option<PathBuf>,
+
+	#[clap(long, value_name = "URL")]
+	/// POSTs a json object with the uid, key, level, pattern and timestamp of every found
+	/// identity to this URL, and when a level is reached or the search finished. The 'event'
+	/// field tells them apart. Failed requests are retried a few times.
+	webhook: Option<String>,
+
+	#[clap(long, requires = "telegram-chat")]
+	/// Sends a Telegram message through this bot for every found identity.
+	telegram_token: Option<String>,
+
+	#[clap(long, requires = "telegram-token")]
+	/// The chat id which receives the Telegram messages.
+	telegram_chat: Option<String>,
+
+	#[clap(long, value_enum, default_value = "uid")]
+	/// What the Telegram messages contain.
+	telegram_details: notify::TelegramDetails,
+
+	#[clap(flatten)]
+	smtp: notify::SmtpArgs,
+
+	#[clap(long, value_name = "COMMAND")]
+	/// Runs this shell command for every found identity. It gets the identity in the
+	/// MATCH_UID, MATCH_KEY, MATCH_KEY_OBFUSCATED, MATCH_LEVEL, MATCH_PATTERN, MATCH_TIMESTAMP
+	/// and MATCH_ATTEMPTS environment variables.
+	on_match: Option<String>,
+
+	#[clap(long, value_parser = clap::value_parser!(u64).range(1..))]
+	/// Number of keys or offsets which are processed in one batch. Smaller batches react faster
+	/// to stop conditions and share the threads with other searches, larger ones have less
+	/// overhead. Defaults to a size adapted to
+	/// '--batch-time'.
+	batch_size: Option<u64>,
+
+	#[clap(long, value_parser = humantime::parse_duration, default_value = "2s")]
+	/// The wall time a batch should roughly take, used unless '--batch-size' is given.
+	batch_time: Duration,
+
+	#[cfg(unix)]
+	#[clap(long, value_name = "PATH")]
+	/// Reports the state of the search on this unix socket, query it with the 'status'
+	/// subcommand.
+	status_socket: Option<PathBuf>,
+
+	#[cfg(unix)]
+	#[clap(skip)]
+	/// The socket of '--status-socket', bound before the process detaches.
+	status_listener: Mutex<Option<std::os::unix::net::UnixListener>>,
+
+	#[cfg(unix)]
+	#[clap(long, value_name = "FD")]
+	/// Writes the progress as one json object per line to this open file descriptor, at most
+	/// once per second: attempts, rate, expected time in seconds and matches.
+	progress_fd: Option<i32>,
+
+	#[cfg(feature = "dashboard")]
+	#[clap(long, value_name = "ADDRESS")]
+	/// Serves a web dashboard for the pattern search, e.g. on '127.0.0.1:8080'. Open the
+	/// printed address, it contains the token which the dashboard requires.
+	dashboard: Option<String>,
+
+	#[clap(long, conflicts_with_all = &["quiet", "stats-interval"])]
+	/// Shows an interactive dashboard in the terminal while searching patterns. Found
+	/// identities are printed when it exits.
+	tui: bool,
+
+	#[clap(long, value_name = "ADDRESS")]
+	/// Serves a json HTTP API to start searches and fetch their results, e.g. on
+	/// '127.0.0.1:8080'. Requests need the printed token, or the one from 'MAHTS_API_TOKEN'.
+	api: Option<String>,
+
+	#[clap(skip)]
+	/// The socket of '--api', bound before the process detaches.
+	api_listener: Option<std::net::TcpListener>,
+
+	#[cfg(feature = "grpc")]
+	#[clap(long, value_name = "ADDRESS")]
+	/// Serves a gRPC interface to start searches and fetch their results, e.g. on
+	/// '127.0.0.1:50051'. Calls need the printed token, or the one from 'MAHTS_GRPC_TOKEN'.
+	grpc: Option<String>,
+
+	#[clap(long, value_name = "PATH")]
+	/// Reads default options from this TOML file, defaults to 'mahtsidentity.toml' in the user
+	/// config directory, e.g. '~/.config/mahtsidentity.toml'. Every key is the name of an option
+	/// like 'threads = 4', options on the command line override the file.
+	config: Option<PathBuf>,
+
+	#[clap(subcommand)]
+	command: Option<Command>,
+
+	#[clap(short, long)]
+	/// Only prints found identities and errors, no status messages.
+	quiet: bool,
+
+	#[clap(short, long, action = clap::ArgAction::Count)]
+	/// Prints more status messages, can be repeated. 'RUST_LOG' can be used for finer control.
+	verbose: u8,
+
+	#[clap(long, global = true, value_name = "PATH")]
+	/// Also writes all status messages and statistics to this file with timestamps, even with
+	/// '--quiet' or '--tui'.
+	log_file: Option<PathBuf>,
+
+	#[clap(long, global = true, value_name = "SIZE", value_parser = logfile::parse_size, default_value = "10M")]
+	/// Rotates the log file when it reaches this size, e.g. '512K' or '1G'.
+	log_max_size: u64,
+
+	#[clap(long, global = true, value_parser = humantime::parse_duration)]
+	/// Also rotates the log file when it is older than this, e.g. '1d'.
+	log_max_age: Option<Duration>,
+
+	#[clap(long, global = true, default_value = "5")]
+	/// Number of rotated log files which are kept, older ones are deleted.
+	log_keep: u32,
+
+	#[cfg(unix)]
+	#[clap(long, global = true)]
+	/// Also sends status messages, found identities and finished searches to syslog, which
+	/// journald receives under systemd. Keys are left out.
+	syslog: bool,
+
+	#[cfg(unix)]
+	#[clap(long, requires = "syslog")]
+	/// Includes the private keys of found identities in the syslog messages.
+	syslog_keys: bool,
+
+	#[clap(long, value_parser = parse_seconds)]
+	/// Prints statistics to stderr every given number of seconds. Uses json with
+	/// '--output-format json'. The pattern search includes the rate of every thread and warns
+	/// about threads which stay slower than the others.
+	stats_interval: Option<Duration>,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+	/// Measures the speed of key generation and hashing.
+	Bench(bench::BenchArgs),
+	/// Reports the cpu features, key backend and thread layout, measures every primitive and
+	/// warns about settings which slow down the search.
+	Doctor(doctor::DoctorArgs),
+	/// Hands out the search given by the other arguments to workers over the network.
+	Serve(cluster::ServeArgs),
+	/// Works on the search of a coordinator started with 'serve'.
+	Worker(cluster::WorkerArgs),
+	/// Prints the state of a running search which was started with '--status-socket'.
+	#[cfg(unix)]
+	Status(status::StatusArgs),
+	/// Manages the Windows service which runs a search in the background.
+	#[cfg(windows)]
+	#[clap(subcommand)]
+	Service(service::ServiceCommand),
+	/// Reads an identity exported from the TeamSpeak 3 client or stored in its settings
+	/// database and prints its key in the format of '--identity'.
+	Import(ts3ini::ImportArgs),
+	/// Prints the uid and its randomart of a public key, e.g. the omega from a server log. No
+	/// private key is needed.
+	Uid {
+		/// The public key in the base64 format of TeamSpeak.
+		key: String,
+	},
+	/// Tells if two identities contain the same private key. They can be in any format which
+	/// '--identity' accepts.
+	Diff {
+		identity1: String,
+		identity2: String,
+	},
+	/// Signs a message with the identity given by '--identity', to prove that you own its uid
+	/// without revealing the private key.
+	Sign(sign::SignArgs),
+	/// Checks a signature created with 'sign'.
+	Verify(sign::VerifyArgs),
+	/// Generates fresh identities with a minimum security level, without a pattern.
+	Generate(generate::GenerateArgs),
+	/// Connects to a TeamSpeak server with the identity given by '--identity' and tells if
+	/// the server accepts its security level.
+	#[cfg(feature = "test-connect")]
+	TestConnect(connect::TestConnectArgs),
+	/// Adds the identity given by '--identity' to the local TeamSpeak client, after backing
+	/// up its settings.
+	Install(ts3client::InstallArgs),
+	/// Prints a new random phrase for '--mnemonic'.
+	Mnemonic,
+	/// Runs the jobs of a TOML file one after the other. Every job finds an identity or starts
+	/// from one, levels it and exports it, running the file again continues where it stopped.
+	Run(pipeline::RunArgs),
+	/// Summarizes the identities of earlier searches in the files of '--db' and '--output':
+	/// the matches per pattern and day, the levels and the time until the first match.
+	Stats(report::StatsArgs),
+	/// Manages the local keystore of identities.
+	#[clap(subcommand)]
+	Store(store::StoreCommand),
+	/// Prints a completion script for the given shell to stdout.
+	Completions {
+		#[clap(value_enum)]
+		shell: clap_complete::Shell,
+	},
 }
 
 #[derive(Debug)]
 struct RunData {
-	patterns: Vec<FindPattern>,
-	exit_when_found: bool,
+	patterns: PatternSet,
+	/// Stop after this many identities were found.
+	max_matches: Option<u64>,
+	/// Number of identities found for all patterns.
+	matches: AtomicU64,
+	/// Stop after generating this many keys.
+	max_attempts: Option<u64>,
+	/// Number of keys generated between checks of the stop conditions.
+	batch: BatchSizer,
+	/// Stop when every pattern has this many matches.
+	required_per_pattern: Option<u64>,
+	/// Report identities which match most of a pattern.
+	near_misses: bool,
+	/// Must all accept an identity before it is reported.
+	predicates: Vec<Predicate>,
+	/// The best candidates by number of matching bits.
+	top: Option<Leaderboard>,
+	output: Output,
+	/// Number of generated keys.
+	attempts: AtomicU64,
+	/// Number of generated keys per worker thread.
+	thread_attempts: ThreadCounters,
+	progress: Progress,
+	/// Set when the search is finished, checked by the workers before every key.
+	stop: AtomicBool,
+}
+
+impl RunData {
+	fn is_stopped(&self) -> bool {
+		self.stop.load(Ordering::Relaxed) || is_interrupted()
+	}
 }
 
 #[derive(Debug)]
 struct FindPattern {
+	input: String,
+	/// A uid matching this pattern with all wildcards replaced by 'A'.
+	uid_template: String,
 	text: u64,
 	mask: u64,
+	/// The constrained bits of the suffix after a '*', compared to the last 64 bits of the
+	/// hash.
+	tail_text: u64,
+	tail_mask: u64,
+	/// Number of identities found for this pattern.
+	matches: AtomicU64,
+	/// Set when this pattern needs no more matches.
+	retired: AtomicBool,
+	/// Number of matching leading characters of the best near miss.
+	near_miss_chars: AtomicU32,
+	near_miss: Mutex<Option<NearMiss>>,
+	/// Number of generated keys when this pattern was added to the search.
+	since: u64,
+}
+
+impl FindPattern {
+	fn parse(inp: &str) -> std::result::Result<Self, String> {
+		let (head, tail) = match inp.split_once('*') {
+			Some((head, tail)) => (head, Some(tail.strip_suffix('=').unwrap_or(tail))),
+			None => (inp, None),
+		};
+		if head.len() > MAX_PATTERN_LEN || tail.map_or(false, |t| t.len() > MAX_SUFFIX_LEN) {
+			return Err("Invalid pattern input".to_string());
+		}
+		let mut mask_builder = [0u8; 28]; // The max number of chars in a UID including the '=' at the end
+		let mut char_builder = String::with_capacity(28);
+		let mut i = 0;
+		for c in head.chars() {
+			if c == '+' || c == '/' || c.is_ascii_alphanumeric() {
+				mask_builder[i] = 0b0011_1111;
+				char_builder.push(c);
+				i += 1;
+			} else if c == '_' || c == '?' {
+				mask_builder[i] = 0x0;
+				char_builder.push('A');
+				i += 1;
+			} else {
+				return Err("Invalid pattern input".to_string());
+			}
+			if i >= mask_builder.len() - 1 {
+				break;
+			}
+		}
+		for _ in i..27 {
+			char_builder.push('A');
+		}
+
+		let mut tail_mask = 0u64;
+		if let Some(tail) = tail {
+			let start = 27 - tail.len();
+			char_builder.truncate(start);
+			for (pos, c) in (start..).zip(tail.chars()) {
+				if c == '_' || c == '?' {
+					char_builder.push('A');
+					continue;
+				}
+				if c != '+' && c != '/' && !c.is_ascii_alphanumeric() {
+					return Err("Invalid pattern input".to_string());
+				}
+				char_builder.push(c);
+				if pos == 26 {
+					// The last character only holds 4 bits of the hash, the rest is padding
+					const LAST_CHARS: &str = "AEIMQUYcgkosw048";
+					if !LAST_CHARS.contains(c) {
+						return Err(format!("A uid always ends with one of {}", LAST_CHARS));
+					}
+					tail_mask |= 0b1111;
+				} else {
+					// The last 64 bits of the hash start at character 16
+					tail_mask |= 0b0011_1111 << (58 - (6 * (pos - 16)));
+				}
+			}
+		}
+		char_builder.push('=');
+
+		let mut mask = 0u64;
+		for (i, mask_b) in mask_builder.iter().copied().enumerate().take(10) {
+			mask |= (mask_b as u64) << (58 - (6 * i));
+		}
+
+		let target_bytes = base64::decode(&char_builder).unwrap();
+		let mut text = BigEndian::read_u64(&target_bytes[0..8]);
+
+		text &= mask;
+		let tail_text = BigEndian::read_u64(&target_bytes[12..20]) & tail_mask;
+		Ok(FindPattern {
+			input: inp.to_string(),
+			uid_template: char_builder,
+			text,
+			mask,
+			tail_text,
+			tail_mask,
+			matches: AtomicU64::new(0),
+			retired: AtomicBool::new(false),
+			near_miss_chars: AtomicU32::new(0),
+			near_miss: Mutex::new(None),
+			since: 0,
+		})
+	}
+
+	/// The number of bits which are constrained by this pattern.
+	fn bits(&self) -> u32 {
+		self.mask.count_ones() + self.tail_mask.count_ones()
+	}
+
+	/// Returns `true` if a uid with these bits matches the pattern.
+	fn matches(&self, uid: UidBits) -> bool {
+		(uid.head ^ self.text) & self.mask == 0 && self.tail_matches(uid.tail)
+	}
+
+	fn tail_matches(&self, tail: u64) -> bool {
+		(tail ^ self.tail_text) & self.tail_mask == 0
+	}
+}
+
+/// The first and the last 64 bits of the hash of a uid, this is what patterns are compared to.
+#[derive(Clone, Copy, Debug)]
+struct UidBits {
+	head: u64,
+	tail: u64,
+}
+
+impl UidBits {
+	fn from_hash(hash: &[u8]) -> Self {
+		Self {
+			head: BigEndian::read_u64(&hash[0..8]),
+			tail: BigEndian::read_u64(&hash[12..20]),
+		}
+	}
+}
+
+fn uid_bits(pub_key: &EccKeyPubP256) -> UidBits {
+	UidBits::from_hash(&Sha1::digest(pub_key.to_ts().as_bytes()))
+}
+
+/// The identity which matched the most leading characters of a pattern without matching it.
+#[derive(Debug)]
+struct NearMiss {
+	chars: u32,
+	uid: String,
+	key: String,
+}
+
+impl Drop for NearMiss {
+	fn drop(&mut self) {
+		self.key.zeroize();
+	}
 }
 
 struct Level {
@@ -59,194 +923,1222 @@ struct Level {
 }
 
 fn main() {
-	let opts: Opts = Opts::from_args();
+	let mut opts = config::parse();
+	init_logger(&opts);
+	if let Some(path) = &opts.config {
+		debug!("Read default options from {}", path.display());
+	}
+
+	if opts.daemon && opts.output.is_none() && opts.output_dir.is_none() && opts.db.is_none() {
+		error!("Daemon mode requires '--output', '--output-dir' or '--db' to store results");
+		std::process::exit(EXIT_INVALID_INPUT);
+	}
+	// Before detaching, errors would not reach the terminal afterwards
+	if let Err(err) = select_backend(&opts).and_then(|_| check_input(&mut opts)) {
+		error!("{}", err);
+		std::process::exit(EXIT_INVALID_INPUT);
+	}
+	if let Err(err) = bind_sockets(&mut opts) {
+		error!("{}", err);
+		std::process::exit(EXIT_ERROR);
+	}
+	if opts.daemon {
+		if let Err(err) = daemon::detach() {
+			error!("{}", err);
+			std::process::exit(EXIT_ERROR);
+		}
+	}
+
+	// Before creating any threads, so they inherit the priority
+	let priority = if opts.background {
+		Some(priority::Priority::Idle)
+	} else {
+		opts.priority
+	};
+	if let Some(priority) = priority {
+		if let Err(err) = priority::set(priority) {
+			warn!("{}", err);
+		}
+	}
+
+	control::install_handlers();
+	#[cfg(unix)]
+	{
+		if let Some(fd) = opts.progress_fd {
+			if let Err(err) = progress::open_events(fd) {
+				error!("{}", err);
+				std::process::exit(EXIT_INVALID_INPUT);
+			}
+		}
+	}
+	if let Some(limit) = opts.cpu_limit {
+		control::set_cpu_limit(limit);
+	}
+	if let Some(max_temp) = opts.max_temp {
+		thermal::spawn(max_temp, control::cpu_limit());
+	}
+
+	if opts.autotune && opts.command.is_none() {
+		let level = opts.patterns.is_empty()
+			&& opts.patterns_file.is_none()
+			&& opts.predicate.is_empty()
+			&& opts.level.is_some();
+		match tune::autotune(level) {
+			Ok(tuned) => {
+				opts.threads = Some(tuned.threads);
+				opts.batch_size = opts.batch_size.or(tuned.batch_size);
+			}
+			Err(err) => warn!("{}", err),
+		}
+	}
+
+	let mut pin = opts.pin_threads.take();
+	if opts.perf_cores_only {
+		match pool::perf_cores() {
+			Some(cores) => {
+				info!("Using the performance cores {:?}", cores.0);
+				pin = Some(Some(cores));
+			}
+			None => warn!("Found no efficiency cores, using all cores"),
+		}
+	}
+	if let Err(err) = pool::build_global(opts.threads, pin) {
+		error!("{}", err);
+		std::process::exit(EXIT_ERROR);
+	}
 
-	if let Some(t) = opts.threads {
-		rayon::ThreadPoolBuilder::new()
-			.num_threads(t)
-			.build_global()
-			.unwrap();
+	#[cfg(windows)]
+	{
+		if opts.windows_service {
+			std::process::exit(service::run(opts));
+		}
 	}
 
+	daemon::notify_ready();
 	let result = {
-		if opts.export {
-			tool_export(opts)
-		} else if !opts.patterns.is_empty() || opts.bench {
+		let _awake = opts.keep_awake.then(awake::KeepAwake::start).flatten();
+		run(opts)
+	};
+	daemon::notify("STOPPING=1");
+	std::process::exit(exit_code(result));
+}
+
+/// Selects how keys are generated.
+fn select_backend(opts: &Opts) -> std::result::Result<(), String> {
+	let mut kind = opts.backend;
+	let seed = match (&opts.seed, &opts.mnemonic) {
+		(Some(seed), _) => Some(Zeroizing::new(rng::parse_seed(seed)?)),
+		(_, Some(phrase)) => Some(mnemonic::seed(phrase)?),
+		_ => None,
+	};
+	if seed.is_none() && (opts.seed_start.is_some() || opts.key_number.is_some()) {
+		return Err("'--seed-start' and '--key-number' require '--seed' or '--mnemonic'".into());
+	}
+	if seed.is_some() || opts.rng.map_or(false, |r| r != rng::RngKind::System) {
+		if kind.map_or(false, |k| k != backend::BackendKind::P256) {
+			return Err("'--seed', '--mnemonic' and '--rng' need the p256 backend".to_string());
+		}
+		kind = Some(backend::BackendKind::P256);
+	}
+	if let Some(kind) = kind {
+		backend::select(kind);
+	}
+	if let Some(seed) = &seed {
+		rng::select_seed(seed, opts.seed_start.unwrap_or(0));
+	}
+	rng::select(
+		opts.rng.unwrap_or(rng::RngKind::System),
+		opts.rng_seed_file.as_deref(),
+	)
+}
+
+/// Reads the identity and checks the patterns and the output format, so invalid input is
+/// reported with `EXIT_INVALID_INPUT` before anything runs.
+fn check_input(opts: &mut Opts) -> std::result::Result<(), String> {
+	read_identity(opts)?;
+	if let Some(identity) = &opts.identity {
+		// '--deobfuscate' also takes an obfuscated key without counter
+		if EccKeyPrivP256::import_str(identity).is_err()
+			&& EccKeyPrivP256::from_ts_obfuscated(identity).is_err()
+		{
+			return Err("Failed to read identity".to_string());
+		}
+	}
+	if let Some(format) = &opts.format {
+		Template::parse(format)?;
+	}
+	if let Some(path) = opts.patterns_file.as_deref() {
+		if path != Path::new("-") {
+			PatternFile::open(path)?;
+		}
+	}
+	if let Some(recipient) = &opts.encrypt_to {
+		if opts.output.is_none() && !opts.export {
+			return Err("'--encrypt-to' needs '--output' or '--export'".to_string());
+		}
+		if opts.export && opts.mnemonic_backup {
+			return Err("'--mnemonic-backup' prints the exported key unencrypted".into());
+		}
+		recipient::Recipient::parse(recipient)?;
+	}
+	for pattern in &opts.patterns {
+		FindPattern::parse(pattern).map_err(|e| format!("Pattern '{}': {}", pattern, e))?;
+	}
+	Ok(())
+}
+
+/// Binds the sockets of '--api' and '--status-socket', the tools take them from `opts`.
+fn bind_sockets(opts: &mut Opts) -> std::result::Result<(), String> {
+	if let Some(addr) = &opts.api {
+		opts.api_listener = Some(
+			std::net::TcpListener::bind(addr)
+				.map_err(|e| format!("Failed to listen on {}: {}", addr, e))?,
+		);
+	}
+	#[cfg(unix)]
+	{
+		if let Some(path) = &opts.status_socket {
+			*opts.status_listener.get_mut().unwrap() = Some(status::listen(path)?);
+		}
+	}
+	Ok(())
+}
+
+/// The socket of '--status-socket', bound by `bind_sockets` for the first search.
+#[cfg(unix)]
+fn status_socket(opts: &Opts) -> std::result::Result<Option<status::StatusSocket>, String> {
+	let path = match &opts.status_socket {
+		Some(path) => path,
+		None => return Ok(None),
+	};
+	match opts.status_listener.lock().unwrap().take() {
+		Some(listener) => Ok(Some(status::StatusSocket::serve(path, listener))),
+		None => status::StatusSocket::bind(path).map(Some),
+	}
+}
+
+/// Runs the tool selected by the options, after `check_input`.
+fn run(mut opts: Opts) -> Result {
+	#[cfg(feature = "test-connect")]
+	{
+		if let Some(address) = &opts.for_server {
+			let level = connect::required_level(address)?;
+			info!("{} requires level {}", address, level);
+			opts.level = Some(level.into());
+		}
+	}
+	match opts.command.take() {
+		Some(Command::Bench(args)) => bench::tool_bench(args),
+		Some(Command::Doctor(args)) => doctor::tool_doctor(args, &opts),
+		Some(Command::Serve(args)) => cluster::tool_serve(args, opts),
+		Some(Command::Worker(args)) => cluster::tool_worker(args),
+		#[cfg(unix)]
+		Some(Command::Status(args)) => status::tool_status(args),
+		#[cfg(windows)]
+		Some(Command::Service(cmd)) => service::tool_service(cmd),
+		Some(Command::Import(args)) => ts3ini::tool_import(args),
+		Some(Command::Uid { key }) => tool_uid(&key),
+		Some(Command::Diff {
+			identity1,
+			identity2,
+		}) => tool_diff(&identity1, &identity2),
+		Some(Command::Sign(args)) => sign::tool_sign(args, opts.identity.as_deref()),
+		Some(Command::Verify(args)) => sign::tool_verify(args),
+		Some(Command::Store(cmd)) => store::tool_store(cmd, &opts),
+		Some(Command::Install(args)) => ts3client::tool_install(args, &opts),
+		#[cfg(feature = "test-connect")]
+		Some(Command::TestConnect(args)) => {
+			connect::tool_test_connect(args, opts.identity.as_deref(), opts.counter.unwrap_or(0))
+		}
+		Some(Command::Generate(args)) => generate::tool_generate(args, &opts),
+		Some(Command::Mnemonic) => mnemonic::tool_new_mnemonic(),
+		Some(Command::Run(args)) => pipeline::tool_run(args, opts),
+		Some(Command::Stats(args)) => report::tool_stats(args),
+		Some(Command::Completions { shell }) => tool_completions(shell),
+		None if opts.api.is_some() => api::tool_api(&opts.api.unwrap(), opts.api_listener.take()),
+		#[cfg(feature = "grpc")]
+		None if opts.grpc.is_some() => grpc::tool_grpc(&opts.grpc.unwrap()),
+		None if opts.export => tool_export(opts),
+		None if opts.deobfuscate => tool_deobfuscate(opts),
+		None if opts.check => tool_check_level(opts),
+		None if !opts.patterns.is_empty()
+			|| opts.patterns_file.is_some()
+			|| !opts.predicate.is_empty()
+			|| opts.bench =>
+		{
 			tool_find_pattern(opts)
-		} else if opts.level.is_some() {
-			tool_improve_sec_level(opts)
-		} else {
-			Err("No patters given, please call with wanted uid strings".to_string())
+		}
+		None if opts.level.is_some() => tool_improve_sec_level(opts),
+		None => Err("No patters given, please call with wanted uid strings".to_string()),
+	}
+}
+
+/// Reads the identity from '--identity', '--identity-file', '--raw-key' or the environment.
+///
+/// Afterwards `opts.identity` is a key that `EccKeyPrivP256::import_str` accepts and
+/// `opts.counter` is set if the identity included one.
+fn read_identity(opts: &mut Opts) -> std::result::Result<(), String> {
+	let identity = if let Some(number) = opts.key_number {
+		(backend::generate_key_at(number).to_ts(), None)
+	} else if let Some(raw) = &opts.raw_key {
+		(keys::from_raw(raw)?.to_ts(), None)
+	} else if let Some(path) = &opts.identity_file {
+		let identity = std::fs::read(path)
+			.map_err(|e| format!("Failed to read identity from {}: {}", path.display(), e))?;
+		match String::from_utf8(identity) {
+			Ok(identity) => identity_from_str(&identity),
+			// Only DER files are binary
+			Err(e) => keys::from_der(e.as_bytes()).map(|key| (key.to_ts(), None)),
+		}
+		.map_err(|e| format!("{}: {}", path.display(), e))?
+	} else {
+		match opts.identity.as_deref() {
+			Some("-") => {
+				if opts.patterns_file.as_deref() == Some(Path::new("-")) {
+					return Err(
+						"Can not read both the identity and patterns from stdin".to_string()
+					);
+				}
+				let mut identity = String::new();
+				std::io::Read::read_to_string(&mut std::io::stdin(), &mut identity)
+					.map_err(|e| format!("Failed to read identity from stdin: {}", e))?;
+				identity_from_str(&identity)?
+			}
+			Some(identity) => identity_from_str(identity)?,
+			None => match std::env::var("MAHTS_IDENTITY") {
+				Ok(identity) => identity_from_str(&identity)?,
+				Err(_) => return Ok(()),
+			},
 		}
 	};
+	opts.identity = Some(identity.0);
+	opts.counter = identity.1;
+	Ok(())
+}
 
-	std::process::exit(match result {
-		Ok(_) => 0,
+/// A key, a TeamSpeak identity string like `<counter>V<obfuscated key>`, a TeamSpeak 3 ini
+/// file, a PEM file or an encrypted identity. Returns the key and the counter. Errors never
+/// contain the identity, it is private.
+fn identity_from_str(identity: &str) -> std::result::Result<(String, Option<u64>), String> {
+	if encrypt::is_encrypted(identity) {
+		return identity_from_str(&encrypt::decrypt(identity)?);
+	}
+	if keys::is_pem(identity) {
+		return Ok((keys::from_pem(identity)?.to_ts(), None));
+	}
+	if mnemonic::is_backup(identity) {
+		return Ok((mnemonic::from_backup(identity)?.to_ts(), None));
+	}
+	if ts3ini::is_ini(identity) {
+		let identity = ts3ini::parse(identity)?;
+		return Ok((identity.key.to_ts(), Some(identity.counter)));
+	}
+	let identity = identity.trim();
+	if EccKeyPrivP256::import_str(identity).is_err() {
+		if let Ok((counter, key)) = ts3ini::parse_identity(identity) {
+			return Ok((key.to_ts(), Some(counter)));
+		}
+	}
+	// Invalid keys are reported by the tools
+	Ok((identity.to_string(), None))
+}
+
+fn tool_uid(key: &str) -> Result {
+	let key = EccKeyPubP256::from_ts(key.trim()).map_err(|_| "Failed to read public key")?;
+	let uid = key.get_uid().to_string();
+	println!("UID: {}", uid);
+	if let Some(art) = randomart::render(&uid) {
+		println!("{}", art);
+	}
+	Ok(Outcome::Success)
+}
+
+fn tool_diff(identity1: &str, identity2: &str) -> Result {
+	let read = |identity: &str, name: &str| {
+		let (key, _) = identity_from_str(identity)?;
+		EccKeyPrivP256::import_str(&key)
+			.map_err(|_| format!("Failed to read the {} identity", name))
+	};
+	let (key1, key2) = (read(identity1, "first")?, read(identity2, "second")?);
+	if key1.to_ts() == key2.to_ts() {
+		println!("SAME KEY UID: {}", key1.to_pub().get_uid());
+	} else {
+		println!("DIFFERENT KEYS");
+		println!("UID 1: {}", key1.to_pub().get_uid());
+		println!("UID 2: {}", key2.to_pub().get_uid());
+	}
+	Ok(Outcome::Success)
+}
+
+fn tool_completions(shell: clap_complete::Shell) -> Result {
+	let mut command = Opts::command();
+	let name = command.get_name().to_string();
+	clap_complete::generate(shell, &mut command, name, &mut std::io::stdout());
+	Ok(Outcome::Success)
+}
+
+/// Logs an error and returns the exit code for it.
+fn exit_code(result: Result) -> i32 {
+	match result {
+		Ok(_) if is_interrupted() => EXIT_INTERRUPTED,
+		Ok(Outcome::Success) => 0,
+		Ok(Outcome::NotFound) => EXIT_NOT_FOUND,
 		Err(err) => {
-			eprintln!("Error: {}", err);
-			1
+			error!("{}", err);
+			EXIT_ERROR
+		}
+	}
+}
+
+fn parse_seconds(s: &str) -> std::result::Result<Duration, String> {
+	let secs: u64 = s
+		.parse()
+		.map_err(|_| format!("Invalid number of seconds '{}'", s))?;
+	if secs == 0 {
+		return Err("The interval must be at least one second".to_string());
+	}
+	Ok(Duration::from_secs(secs))
+}
+
+/// Status messages go to stderr, stdout is reserved for results.
+fn init_logger(opts: &Opts) {
+	let level = if opts.tui {
+		// Log lines would mess up the dashboard
+		LevelFilter::Error
+	} else if opts.quiet {
+		LevelFilter::Warn
+	} else {
+		match opts.verbose {
+			0 => LevelFilter::Info,
+			1 => LevelFilter::Debug,
+			_ => LevelFilter::Trace,
+		}
+	};
+	let stderr = env_logger::Builder::new()
+		.filter_level(level)
+		.format_target(false)
+		.parse_default_env()
+		.build();
+	let file = opts
+		.log_file
+		.as_deref()
+		.map(|path| {
+			logfile::RotatingFile::open(path, opts.log_max_size, opts.log_max_age, opts.log_keep)
+		})
+		.transpose();
+	let file_level = match opts.verbose {
+		0 => LevelFilter::Info,
+		1 => LevelFilter::Debug,
+		_ => LevelFilter::Trace,
+	};
+	let mut targets = logfile::Targets::default();
+	let result = file.map(|file| targets.file = file);
+	#[cfg(unix)]
+	let result = result.and_then(|_| {
+		if opts.syslog {
+			targets.syslog = Some(syslog::Syslog::connect()?);
 		}
+		Ok(())
 	});
+	logfile::init(stderr, targets, file_level);
+	if let Err(err) = result {
+		error!("{}", err);
+		std::process::exit(EXIT_ERROR);
+	}
+}
+
+fn output_from_opts(opts: &Opts) -> std::result::Result<Output, String> {
+	let template = opts.format.as_deref().map(Template::parse).transpose()?;
+	let mut output = Output::new(opts.output_format, template);
+	if let Some(path) = &opts.output {
+		output.open_file(path)?;
+	}
+	if let Some(path) = &opts.blocklist {
+		output.open_blocklist(path)?;
+	}
+	if let Some(path) = &opts.audit_log {
+		output.set_audit_log(Arc::new(audit::AuditLog::open(path)?));
+	}
+	if let Some(path) = &opts.output_dir {
+		output.open_dir(path, opts.output_dir_format, &opts.nickname)?;
+	}
+	if let Some(recipient) = &opts.encrypt_to {
+		output.set_file_recipient(recipient::Recipient::parse(recipient)?);
+	}
+	if let Some(path) = &opts.db {
+		output.open_db(path)?;
+	}
+	if opts.seed.is_some() || opts.mnemonic.is_some() {
+		output.set_dedup();
+	}
+	if opts.store {
+		output.set_store(store::Store::open(opts.store_path.as_deref())?);
+	}
+	if opts.mnemonic_backup {
+		output.set_mnemonic_backup()?;
+	}
+	output.set_color(opts.color.enabled());
+	output.set_print_limit(opts.print_limit);
+	if let Some(notifications) = notifications_from_opts(opts)? {
+		output.set_notifications(notifications);
+	}
+	Ok(output)
+}
+
+/// Returns `None` if no notifications are configured.
+fn notifications_from_opts(
+	opts: &Opts,
+) -> std::result::Result<Option<notify::Notifications>, String> {
+	let mut notifiers = Vec::<Box<dyn notify::Notifier>>::new();
+	if let Some(url) = &opts.webhook {
+		notifiers.push(Box::new(notify::Webhook { url: url.clone() }));
+	}
+	if let (Some(token), Some(chat)) = (&opts.telegram_token, &opts.telegram_chat) {
+		notifiers.push(Box::new(notify::Telegram {
+			token: token.clone(),
+			chat: chat.clone(),
+			details: opts.telegram_details,
+		}));
+	}
+	if let Some(smtp) = notify::Smtp::from_args(&opts.smtp)? {
+		notifiers.push(Box::new(smtp));
+	}
+	if let Some(command) = &opts.on_match {
+		notifiers.push(Box::new(notify::Hook {
+			command: command.clone(),
+		}));
+	}
+	#[cfg(unix)]
+	{
+		if opts.syslog {
+			notifiers.push(Box::new(syslog::SyslogNotifier {
+				syslog: syslog::Syslog::connect()?,
+				keys: opts.syslog_keys,
+			}));
+		}
+	}
+	Ok((!notifiers.is_empty()).then(|| notify::Notifications::start(notifiers)))
+}
+
+fn batch_sizer(opts: &Opts) -> BatchSizer {
+	match opts.batch_size {
+		Some(size) => BatchSizer::fixed(size),
+		None => BatchSizer::adaptive(opts.batch_time),
+	}
 }
 
 // Tool: export
 
+/// The obfuscated key, with the security level counter in front if it is known.
+fn identity_string(key: &EccKeyPrivP256, counter: Option<u64>) -> Zeroizing<String> {
+	match counter {
+		Some(counter) => Zeroizing::new(format!("{}V{}", counter, key.to_ts_obfuscated())),
+		None => Zeroizing::new(key.to_ts_obfuscated()),
+	}
+}
+
 fn tool_export(opts: Opts) -> Result {
-	let identity = opts.identity.ok_or("Requires an identity (-i) to export")?;
+	if let Some(path) = &opts.batch {
+		return convert::tool_export_batch(&opts, path);
+	}
+	let identity = Zeroizing::new(opts.identity.ok_or("Requires an identity (-i) to export")?);
 	let tp_priv = EccKeyPrivP256::import_str(&identity).map_err(|_| "Failed to read identity")?;
-	let export = tp_priv.to_ts_obfuscated();
-	println!("KEY: {}", export);
-	Ok(())
+	let mut export = identity_string(&tp_priv, opts.counter);
+	if opts.encrypt {
+		export = Zeroizing::new(encrypt::encrypt(&export)?);
+	}
+	if let Some(recipient) = &opts.encrypt_to {
+		export = Zeroizing::new(recipient::Recipient::parse(recipient)?.encrypt(&export)?);
+	}
+	println!("KEY: {}", *export);
+	if opts.clipboard {
+		clipboard::copy(&export);
+	}
+	if opts.mnemonic_backup {
+		println!("MNEMONIC: {}", *mnemonic::to_backup(&tp_priv));
+	}
+	if opts.pem {
+		print!("{}", *keys::to_pem(&tp_priv)?);
+	}
+	if opts.ts3audiobot {
+		let offset = opts.offset.or(opts.counter).unwrap_or(0);
+		print!(
+			"{}",
+			*Zeroizing::new(convert::ts3audiobot_config(&tp_priv, offset))
+		);
+	}
+	if opts.sinusbot {
+		let offset = opts.offset.or(opts.counter).unwrap_or(0);
+		print!(
+			"{}",
+			*Zeroizing::new(convert::sinusbot_settings(&tp_priv, offset))
+		);
+	}
+	if let Some(path) = &opts.der {
+		output::write_private(path, &keys::to_der(&tp_priv)?)
+			.map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+		info!("Wrote {}", path.display());
+	}
+	if let Some(path) = &opts.ts3_ini {
+		let offset = opts.offset.or(opts.counter).unwrap_or(0);
+		ts3ini::write(path, &tp_priv, &opts.nickname, offset)?;
+		let level = get_hash_cash_level(&tp_priv.to_pub().to_ts(), offset);
+		info!("Wrote {} with level {}", path.display(), level);
+	}
+	Ok(Outcome::Success)
+}
+
+fn tool_deobfuscate(opts: Opts) -> Result {
+	let identity = Zeroizing::new(
+		opts.identity
+			.ok_or("Requires an identity (-i) to deobfuscate")?,
+	);
+	let tp_priv = EccKeyPrivP256::from_ts_obfuscated(&identity)
+		.or_else(|_| EccKeyPrivP256::import_str(&identity))
+		.map_err(|_| "Failed to read identity")?;
+	println!("KEY: {}", tp_priv.to_ts());
+	Ok(Outcome::Success)
+}
+
+fn tool_check_level(opts: Opts) -> Result {
+	let omega = match (&opts.omega, &opts.identity) {
+		(Some(omega), _) => EccKeyPubP256::from_ts(omega.trim())
+			.map_err(|_| "Failed to read public key")?
+			.to_ts(),
+		(None, Some(identity)) => EccKeyPrivP256::import_str(identity)
+			.map_err(|_| "Failed to read identity")?
+			.to_pub()
+			.to_ts(),
+		(None, None) => return Err("Requires a public key (--omega) or an identity (-i)".into()),
+	};
+	let offset = opts
+		.offset
+		.or(opts.counter)
+		.ok_or("Requires an offset (--offset) to check")?;
+	println!("LEVEL: {}", get_hash_cash_level(&omega, offset));
+	Ok(Outcome::Success)
 }
 
 // Tool: Find pattern
 
 const MAX_PATTERN_LEN: usize = 64 / 6;
+/// Number of items a thread of the pattern search takes at once.
+const PATTERN_CHUNK: usize = 64;
+/// The characters 16 to 26 of a uid are in the last 64 bits of the hash.
+const MAX_SUFFIX_LEN: usize = 11;
 
 fn tool_find_pattern(opts: Opts) -> Result {
+	let mut output = output_from_opts(&opts)?;
+	let collected = opts.tui.then(|| output.collect());
 	let time_per_bit = if opts.bench {
-		println!("Benching...");
-		Some(bench())
+		info!("Benching...");
+		let time_per_run = bench();
+		calibration::store(
+			rayon::current_num_threads(),
+			Some(1.0 / time_per_run.as_secs_f64()),
+			None,
+		);
+		Some(time_per_run)
 	} else {
-		None
+		let cached = calibration::load(rayon::current_num_threads()).and_then(|c| c.keys_per_sec);
+		if cached.is_some() {
+			info!("Using stored benchmark results, run with '--bench' to update them");
+		}
+		cached.map(|rate| Duration::from_secs_f64(1.0 / rate))
 	};
+	if is_interrupted() {
+		return Ok(Outcome::Success);
+	}
 
-	if opts.patterns.is_empty() {
-		println!("No further patterns specified, exiting.");
-		return Ok(());
+	let mut patterns = opts
+		.patterns
+		.iter()
+		.map(|p| FindPattern::parse(p))
+		.collect::<std::result::Result<Vec<_>, _>>()?;
+	let stream = opts.patterns_file.as_deref() == Some(Path::new("-"));
+	if stream && opts.tui {
+		return Err("Reading patterns from stdin does not work with '--tui'".to_string());
+	}
+	let mut pattern_file = None;
+	if let Some(path) = opts.patterns_file.as_deref().filter(|_| !stream) {
+		let (file, file_patterns) = PatternFile::open(path)?;
+		patterns.extend(file_patterns);
+		pattern_file = Some(file);
+		control::install_reload_handler();
+	}
+	let predicates = opts
+		.predicate
+		.iter()
+		.map(|p| Predicate::load(p))
+		.collect::<std::result::Result<Vec<_>, _>>()?;
+	if patterns.is_empty() && pattern_file.is_none() && !stream {
+		// Without patterns, the predicates choose from all identities
+		for p in &predicates {
+			let mut pattern = FindPattern::parse("")?;
+			pattern.input = p.name();
+			patterns.push(pattern);
+		}
+	}
+	if patterns.is_empty() && pattern_file.is_none() && !stream {
+		info!("No further patterns specified, exiting.");
+		return Ok(Outcome::Success);
+	}
+	if opts.shard.is_some() {
+		info!("Keys are generated randomly, so '--shard' is not needed for the pattern search");
 	}
 
-	let mut patterns = vec![];
-	for inp in &opts.patterns {
-		if inp.len() > MAX_PATTERN_LEN {
-			return Err("Invalid pattern input".to_string());
+	print_patterns(&patterns, time_per_bit, opts.eta_warn);
+	if !opts.force {
+		check_feasible(&patterns, time_per_bit, opts.max_eta)?;
+	}
+
+	let pools = if opts.numa {
+		pool::numa_pools()?
+	} else {
+		Vec::new()
+	};
+	let threads = if pools.is_empty() {
+		rayon::current_num_threads()
+	} else {
+		pools.iter().map(|p| p.current_num_threads()).sum()
+	};
+
+	let data = RunData {
+		patterns: PatternSet::new(patterns),
+		max_matches: if opts.exit_when_found {
+			Some(1)
+		} else {
+			opts.max_matches
+		},
+		matches: AtomicU64::new(0),
+		max_attempts: opts.max_attempts,
+		batch: batch_sizer(&opts),
+		required_per_pattern: if opts.find_all {
+			Some(1)
+		} else if stream {
+			Some(opts.count_per_pattern.unwrap_or(1))
+		} else {
+			opts.count_per_pattern
+		},
+		near_misses: opts.near_misses,
+		predicates,
+		top: opts.top.map(|n| Leaderboard::new(n as usize)),
+		output,
+		attempts: AtomicU64::new(0),
+		thread_attempts: ThreadCounters::with_threads(threads),
+		progress: Progress::new(opts.quiet || opts.tui),
+		stop: AtomicBool::new(false),
+	};
+
+	#[cfg(unix)]
+	let status = status_socket(&opts)?;
+
+	#[cfg(feature = "dashboard")]
+	let dashboard = opts.dashboard.as_deref().map(dashboard::bind).transpose()?;
+
+	let stdin_patterns = stream.then(|| {
+		data.patterns.start_stdin();
+		patterns::read_stdin()
+	});
+
+	data.output.print_header();
+	let energy = opts.energy.then(energy::EnergyMeter::start).flatten();
+	let done = AtomicBool::new(false);
+	let mut stats = StatsTimer::new(
+		opts.stats_interval,
+		opts.output_format == OutputFormat::Json,
+	);
+	std::thread::scope(|s| {
+		#[cfg(feature = "dashboard")]
+		{
+			if let Some(server) = &dashboard {
+				let (data, done, start) = (&data, &done, Instant::now());
+				s.spawn(move || dashboard::serve(server, data, start, done));
+			}
 		}
-		let mut mask_builder = [0u8; 28]; // The max number of chars in a UID including the '=' at the end
-		let mut char_builder = String::with_capacity(28);
-		let mut i = 0;
-		for c in inp.chars() {
-			if c == '+' || c == '/' || c.is_ascii_alphanumeric() {
-				mask_builder[i] = 0b0011_1111;
-				char_builder.push(c);
-				i += 1;
-			} else if c == '_' || c == '?' {
-				mask_builder[i] = 0x0;
-				char_builder.push('A');
-				i += 1;
-			} else {
-				return Err("Invalid pattern input".to_string());
+		if opts.tui {
+			s.spawn(|| {
+				if let Err(e) = tui::run(&data, &done) {
+					error!("{}", e);
+				}
+			});
+		}
+		s.spawn(|| {
+			// A receiver can not be shared between threads, so it is moved here
+			let stdin_patterns = stdin_patterns;
+			let mut monitor = ThreadMonitor::new(&data.thread_attempts);
+			while !done.load(Ordering::Relaxed) {
+				let attempts = data.attempts.load(Ordering::Relaxed);
+				data.progress.update(
+					attempts,
+					stats.elapsed(),
+					&data.patterns.read(),
+					data.required_per_pattern,
+				);
+				#[cfg(unix)]
+				{
+					if let Some(status) = &status {
+						status.update(state::find_state(&data, stats.elapsed()));
+					}
+				}
+				if let Some(file) = &mut pattern_file {
+					file.reload(&data.patterns, attempts, control::take_reload_request());
+				}
+				if let Some(lines) = &stdin_patterns {
+					data.patterns.add_from_stdin(lines, attempts);
+					data.patterns.remove_retired();
+					if !data.patterns.stdin_open() && data.patterns.read().is_empty() {
+						info!("All patterns from stdin are satisfied");
+						data.stop.store(true, Ordering::Relaxed);
+					}
+				}
+				if let Some(top) = &data.top {
+					if control::take_dump_request() {
+						data.progress.suspend(|| top.print());
+					}
+				}
+				if stats.due() {
+					let mut s = Stats::new(attempts, stats.elapsed());
+					s.near_miss = best_near_miss(&data.patterns.read());
+					s.thread_rates = Some(
+						data.progress
+							.suspend(|| monitor.rates(&data.thread_attempts)),
+					);
+					data.progress.suspend(|| stats.print(&s));
+				}
+				if !data.is_stopped() && opts.max_time.map_or(false, |max| stats.elapsed() >= max) {
+					info!("Reached the time limit");
+					data.stop.store(true, Ordering::Relaxed);
+				}
+				std::thread::sleep(Duration::from_millis(250));
 			}
-			if i >= mask_builder.len() - 1 {
-				break;
+		});
+		if pools.is_empty() {
+			find_pattern_parallel::<false>(&data);
+		} else {
+			let workers = pools
+				.iter()
+				.map(|pool| s.spawn(|| pool.install(|| find_pattern_parallel::<false>(&data))))
+				.collect::<Vec<_>>();
+			for worker in workers {
+				worker.join().expect("Search thread panicked");
 			}
 		}
-		for _ in i..27 {
-			char_builder.push('A');
+		done.store(true, Ordering::Relaxed);
+	});
+	data.progress.finish();
+	if let Some(collected) = collected {
+		for found in collected.lock().unwrap().iter() {
+			println!("{}", data.output.format(found));
 		}
-		char_builder.push('=');
-
-		let mut mask = 0u64;
-		for (i, mask_b) in mask_builder.iter().copied().enumerate().take(10) {
-			mask |= (mask_b as u64) << (58 - (6 * i));
+	}
+	if opts.clipboard {
+		match data.output.found().last() {
+			Some(found) => clipboard::copy(&found.key_obfuscated),
+			None => info!("Nothing found to copy to the clipboard"),
 		}
+	}
+	if is_interrupted() {
+		info!("Interrupted");
+	}
+	if let Some(top) = &data.top {
+		top.print();
+	}
+	stats::print_summary(
+		data.attempts.load(Ordering::Relaxed),
+		stats.elapsed(),
+		&data.patterns.read(),
+	);
+	if let Some(meter) = &energy {
+		meter.print_summary(Some(data.matches.load(Ordering::Relaxed)));
+	}
+	data.output.notify(notify::Event::Finished(format!(
+		"Search finished after {} keys with {} matches",
+		data.attempts.load(Ordering::Relaxed),
+		data.matches.load(Ordering::Relaxed)
+	)));
+	data.output.finish();
+	info!("Done");
+	if data.matches.load(Ordering::Relaxed) == 0 && !is_interrupted() {
+		Ok(Outcome::NotFound)
+	} else {
+		Ok(Outcome::Success)
+	}
+}
 
-		let target_bytes = base64::decode(&char_builder).unwrap();
-		let mut text = BigEndian::read_u64(&target_bytes[0..8]);
-
-		text &= mask;
-		patterns.push(FindPattern { text, mask });
-		print!("Patterns: {} {:#066b} {:#066b}", char_builder, text, mask);
+/// Prints all patterns from the easiest to the hardest one.
+///
+/// With a benchmark result, patterns which are expected to take longer than `eta_warn` are
+/// flagged.
+fn print_patterns(patterns: &[FindPattern], time_per_bit: Option<Duration>, eta_warn: Duration) {
+	let mut sorted = patterns.iter().collect::<Vec<_>>();
+	sorted.sort_by_key(|p| p.bits());
+	for p in sorted {
+		let mut line = format!(
+			"Patterns: {} {:#066b} {:#066b} ({} bits)",
+			p.uid_template,
+			p.text,
+			p.mask,
+			p.bits()
+		);
 		if let Some(t) = time_per_bit {
-			print!(" {}", expect_time(t, mask.count_ones()));
+			line.push_str(&format!(" {}", expect_time(t, miss_log(p.bits()))));
+			if t.as_secs_f64() * 2f64.powi(p.bits() as i32) > eta_warn.as_secs_f64() {
+				warn!(
+					"{} (expected to take longer than {})",
+					line,
+					humantime::format_duration(eta_warn)
+				);
+				continue;
+			}
 		}
-		println!();
+		info!("{}", line);
 	}
 
-	let data = RunData {
-		patterns,
-		exit_when_found: opts.exit_when_found,
-	};
+	if let Some(t) = time_per_bit {
+		if patterns.len() > 1 {
+			// A key matches any pattern, if it does not miss all of them
+			let all_miss_log: f64 = patterns.iter().map(|p| miss_log(p.bits())).sum();
+			info!("Any pattern: {}", expect_time(t, all_miss_log));
+		}
+	}
+}
 
-	find_pattern_parallel::<false>(&data);
-	println!("Done");
-	Ok(())
+/// Fails if a pattern is expected to take longer than `max_eta`, unless the user confirms it on
+/// a terminal.
+fn check_feasible(
+	patterns: &[FindPattern],
+	time_per_bit: Option<Duration>,
+	max_eta: Duration,
+) -> std::result::Result<(), String> {
+	let secs = match time_per_bit {
+		Some(t) => t.as_secs_f64(),
+		None => {
+			info!("Measuring the key rate to estimate the patterns, '--force' skips this");
+			1.0 / bench::measure_keys(Duration::from_secs(1))?
+		}
+	};
+	let slow = patterns
+		.iter()
+		.map(|p| (p, secs * 2f64.powi(p.bits() as i32)))
+		.filter(|(_, mean)| *mean > max_eta.as_secs_f64())
+		.collect::<Vec<_>>();
+	if slow.is_empty() {
+		return Ok(());
+	}
+	for (p, mean) in &slow {
+		error!(
+			"Pattern {} is expected to take ~ {} at {:.0} keys per second",
+			p.input,
+			format_time(*mean),
+			1.0 / secs
+		);
+	}
+	if std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+		eprint!("Start the search anyway? [y/N] ");
+		let mut answer = String::new();
+		std::io::stdin()
+			.read_line(&mut answer)
+			.map_err(|e| format!("Failed to read the answer: {}", e))?;
+		if answer.trim().eq_ignore_ascii_case("y") {
+			return Ok(());
+		}
+	}
+	Err(format!(
+		"{} pattern(s) are expected to take longer than {}, shorten them or search with \
+		 '--force'",
+		slow.len(),
+		humantime::format_duration(max_eta)
+	))
 }
 
 fn bench() -> Duration {
 	let data = RunData {
-		exit_when_found: false,
-		patterns: vec![FindPattern {
+		max_matches: None,
+		matches: AtomicU64::new(0),
+		max_attempts: None,
+		batch: BatchSizer::fixed(BENCH_BATCH_SIZE),
+		required_per_pattern: None,
+		near_misses: false,
+		predicates: Vec::new(),
+		top: None,
+		output: Output::new(OutputFormat::Text, None),
+		attempts: AtomicU64::new(0),
+		thread_attempts: ThreadCounters::new(),
+		progress: Progress::new(true),
+		stop: AtomicBool::new(false),
+		patterns: PatternSet::new(vec![FindPattern {
+			input: String::new(),
+			uid_template: String::new(),
+			matches: AtomicU64::new(0),
+			retired: AtomicBool::new(false),
+			near_miss_chars: AtomicU32::new(0),
+			near_miss: Mutex::new(None),
+			since: 0,
 			text: 0,
 			mask: 0b111111_111111_111111_111111_111111_111111_111111_111111_111111_111111_0000,
-		}],
+			tail_text: 0,
+			tail_mask: 0,
+		}]),
 	};
 
 	let now = Instant::now();
 	const ITERS: u32 = 5;
 	for i in 0..ITERS {
-		println!("Iter {}/{}", i + 1, ITERS);
+		debug!("Iter {}/{}", i + 1, ITERS);
 		find_pattern_parallel::<true>(&data);
 	}
 	let elap = now.elapsed();
-	println!("Elapsed {:?}", elap);
-	let time_per_run = elap.div_f64((ITERS * FIND_PATTERN_BATCH_SIZE) as f64);
-	println!("Avg Run {:?}", time_per_run);
+	info!("Elapsed {:?}", elap);
+	let time_per_run = elap.div_f64((ITERS as u64 * BENCH_BATCH_SIZE) as f64);
+	info!("Avg Run {:?}", time_per_run);
 	time_per_run // estimation time is 2^(timer_per_run)
 }
 
-fn expect_time(dur: Duration, bits: u32) -> String {
-	let mut t = dur.as_secs_f64() * 2u64.pow(bits) as f64;
-	let mut u = "second(s)";
-	if t > 60.0 {
-		t /= 60.0;
-		u = "minute(s)";
+/// Estimates how long finding a pattern takes.
+///
+/// `miss_log` is `ln(1 - p)`, where `p` is the chance that a single key matches. The number of
+/// keys until the first match is geometrically distributed. Besides the mean, the time until a
+/// match was found with a probability of 50%, 90% and 99% is shown.
+fn expect_time(dur: Duration, miss_log: f64) -> String {
+	let secs = dur.as_secs_f64();
+	let mean = secs / -miss_log.exp_m1();
+	let mut res = format!("Expected time: ~ {}", format_time(mean));
+	let percentiles = [0.5, 0.9, 0.99]
+		.iter()
+		.map(|q| {
+			format!(
+				"{:.0}%: {}",
+				q * 100.0,
+				format_time(secs * (1.0 - q).ln() / miss_log)
+			)
+		})
+		.collect::<Vec<_>>();
+	res.push_str(&format!(" ({})", percentiles.join(", ")));
+	res
+}
 
-		if t > 60.0 {
-			t /= 60.0;
-			u = "hours(s)";
+/// `ln(1 - p)` for the chance `p = 2^-bits` that a key matches a pattern.
+///
+/// Using logarithms keeps the tiny probabilities of long patterns precise, and the logarithms of
+/// multiple patterns can be added to get the chance that a key matches none of them.
+fn miss_log(bits: u32) -> f64 {
+	(-2f64.powi(-(bits as i32))).ln_1p()
+}
 
-			if t > 24.0 {
-				t /= 24.0;
-				u = "day(s)";
-			}
+/// Formats seconds in the largest fitting unit, up to years.
+fn format_time(secs: f64) -> String {
+	const UNITS: &[(f64, &str)] = &[
+		(60.0, "minute(s)"),
+		(60.0, "hour(s)"),
+		(24.0, "day(s)"),
+		(7.0, "week(s)"),
+		// An average month in weeks
+		(365.25 / 12.0 / 7.0, "month(s)"),
+		(12.0, "year(s)"),
+	];
+	let mut t = secs;
+	let mut u = "second(s)";
+	for &(div, unit) in UNITS {
+		if t <= div {
+			break;
 		}
+		t /= div;
+		u = unit;
+	}
+	if t >= 1e6 {
+		format!("{:.2e} {}", t, u)
+	} else {
+		format!("{:.2} {}", t, u)
 	}
-	format!("Expected time: ~ {:.2} {}", t, u)
 }
 
-const FIND_PATTERN_BATCH_SIZE: u32 = 500_000u32;
+/// The number of keys generated by the benchmark in a single iteration.
+const BENCH_BATCH_SIZE: u64 = 500_000;
 
+/// Generates `sha1x::LANES` keys and hashes them together.
 fn find_pattern_sync<const BENCH: bool>(data: &RunData) -> bool {
-	let (priv_key, pub_key) = signature::EcdsaKeyPair::generate_key_pair(
-		&signature::ECDSA_P256_SHA256_ASN1_SIGNING,
-		&flakebi_ring::rand::SystemRandom::new(),
-	)
-	.unwrap();
-
-	// Compute uid
-	let pub_key = EccKeyPubP256::from_short(&pub_key).unwrap();
-	let hash = Sha1::digest(pub_key.to_ts().as_bytes());
+	const LANES: u64 = sha1x::LANES as u64;
+	control::wait_while_paused();
+	let first = data.attempts.fetch_add(LANES, Ordering::Relaxed);
+	data.thread_attempts.add(LANES);
+	let keys: [_; sha1x::LANES] =
+		std::array::from_fn(|i| backend::generate_pub_at(first + i as u64));
+	let omegas: [String; sha1x::LANES] = std::array::from_fn(|i| keys[i].1.to_ts());
+	let hashes = sha1x::digest(&std::array::from_fn(|i| omegas[i].as_bytes()));
+	for (i, ((priv_key, pub_key), hash)) in keys.iter().zip(&hashes).enumerate() {
+		let attempts = first + i as u64 + 1;
+		if data.max_attempts.map_or(false, |max| attempts > max) {
+			data.stop.store(true, Ordering::Relaxed);
+			return true;
+		}
+		let uid = UidBits::from_hash(hash);
+		if check_candidate::<BENCH>(data, attempts, priv_key, pub_key, uid) {
+			return true;
+		}
+	}
+	false
+}
 
-	for p in &data.patterns {
-		if BigEndian::read_u64(&hash[0..8]) & p.mask == p.text {
-			if BENCH {
-				return false;
+/// Reports the key if it matches a pattern, returns true when the search is done.
+fn check_candidate<const BENCH: bool>(
+	data: &RunData,
+	attempts: u64,
+	priv_key: &[u8],
+	pub_key: &EccKeyPubP256,
+	uid: UidBits,
+) -> bool {
+	let mut best_candidate: Option<(u32, &FindPattern)> = None;
+	let patterns = data.patterns.read();
+	// Near misses and the top list also look at the patterns which can not match
+	let positions = if !BENCH && (data.top.is_some() || data.near_misses) {
+		patterns.all()
+	} else {
+		patterns.candidates(uid.head)
+	};
+	for p in positions.iter().map(|i| &patterns[*i]) {
+		let diff = (uid.head ^ p.text) & p.mask;
+		if diff != 0 {
+			if !BENCH && data.top.is_some() {
+				// Count the constrained bits before the first mismatch
+				let score = (p.mask & !(u64::MAX >> diff.leading_zeros())).count_ones();
+				if best_candidate.map_or(true, |(s, _)| score > s) {
+					best_candidate = Some((score, p));
+				}
+			}
+			if !BENCH && data.near_misses {
+				// Every uid character has 6 bits
+				let chars = diff.leading_zeros() / 6;
+				if chars > p.near_miss_chars.load(Ordering::Relaxed) {
+					let tp_priv = EccKeyPrivP256::from_short(priv_key).unwrap();
+					record_near_miss(data, p, chars, &tp_priv);
+				}
+			}
+			continue;
+		}
+		if !p.tail_matches(uid.tail) {
+			continue;
+		}
+		if BENCH {
+			return false;
+		}
+		if p.retired.load(Ordering::Relaxed) {
+			continue;
+		}
+		if !predicate::all_accept(&data.predicates, pub_key) {
+			continue;
+		}
+		if !data.output.claim(&pub_key.get_uid().to_string()) {
+			debug!("Skipping already reported uid {}", pub_key.get_uid());
+			return false;
+		}
+		let mut satisfied = false;
+		if let Some(required) = data.required_per_pattern {
+			let counted = p
+				.matches
+				.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+					(n < required).then(|| n + 1)
+				});
+			match counted {
+				Ok(n) if n + 1 == required => {
+					p.retired.store(true, Ordering::Relaxed);
+					satisfied = true;
+				}
+				Ok(_) => {}
+				// Another worker found the last required identity at the same time
+				Err(_) => continue,
 			}
-			let tp_priv = EccKeyPrivP256::from_short(&priv_key).unwrap();
-			let export = tp_priv.to_ts();
-			println!("UID: {} KEY: {}", pub_key.get_uid(), export);
-			return data.exit_when_found;
+		} else {
+			p.matches.fetch_add(1, Ordering::Relaxed);
+		}
+		let matches = data.matches.fetch_add(1, Ordering::Relaxed) + 1;
+		let mut last = match data.max_matches {
+			// Another worker found the last wanted identity at the same time
+			Some(max) if matches > max => return true,
+			Some(max) => matches == max,
+			None => false,
+		};
+		if data.required_per_pattern.is_some() && !data.patterns.stdin_open() {
+			last |= patterns.iter().all(|p| p.retired.load(Ordering::Relaxed));
+		}
+		if last {
+			// Stop all other workers before printing, that takes a while
+			data.stop.store(true, Ordering::Relaxed);
+		}
+		let tp_priv = EccKeyPrivP256::from_short(priv_key).unwrap();
+		let found = Found::new(&tp_priv, p.input.clone(), attempts);
+		data.progress.suspend(|| data.output.print_found(&found));
+		if satisfied && data.patterns.reads_stdin() {
+			data.output.print_satisfied(&p.input);
+		}
+		return last;
+	}
+	if let (Some(top), Some((score, p))) = (&data.top, best_candidate) {
+		if top.qualifies(score) {
+			let tp_priv = EccKeyPrivP256::from_short(priv_key).unwrap();
+			top.insert(Entry {
+				score,
+				pattern: p.input.clone(),
+				uid: pub_key.get_uid().to_string(),
+				key: tp_priv.to_ts(),
+			});
 		}
 	}
 	false
 }
 
+/// Describes the near miss with the most matching characters over all patterns.
+fn best_near_miss(patterns: &[Arc<FindPattern>]) -> Option<String> {
+	patterns
+		.iter()
+		.filter_map(|p| {
+			let chars = p.near_miss_chars.load(Ordering::Relaxed);
+			(chars > 0).then(|| (chars, p))
+		})
+		.max_by_key(|(chars, _)| *chars)
+		.map(|(chars, p)| format!("{} {}/{}", p.input, chars, p.input.len()))
+}
+
+fn record_near_miss(data: &RunData, p: &FindPattern, chars: u32, key: &EccKeyPrivP256) {
+	let mut near_miss = p.near_miss.lock().unwrap();
+	// Check again, another worker could have found a better one in the meantime
+	if chars <= p.near_miss_chars.load(Ordering::Relaxed) {
+		return;
+	}
+	p.near_miss_chars.store(chars, Ordering::Relaxed);
+	let miss = NearMiss {
+		chars,
+		uid: key.to_pub().get_uid().to_string(),
+		key: key.to_ts(),
+	};
+	data.progress.suspend(|| {
+		info!(
+			"NEAR MISS: {}/{} {} UID: {} KEY: {}",
+			miss.chars,
+			p.input.len(),
+			p.input,
+			miss.uid,
+			miss.key
+		)
+	});
+	*near_miss = Some(miss);
+}
+
+/// Runs the search in batches on the current pool until it is stopped.
+///
+/// Every batch ends, so other searches on the same pool get their share of the threads. Items
+/// are handed out in chunks, which saves the scheduling for every few keys. The benchmark stops
+/// after one batch.
 fn find_pattern_parallel<const BENCH: bool>(data: &RunData) {
-	let mut found_any = false;
-	while !found_any {
-		found_any = (0..FIND_PATTERN_BATCH_SIZE)
+	let mut batch = data.batch;
+	while !data.is_stopped() {
+		// Every item generates several keys
+		let lanes = sha1x::LANES as u64;
+		(0..(batch.start() + lanes - 1) / lanes)
 			.into_par_iter()
-			.any(|_| find_pattern_sync::<BENCH>(data));
+			.with_min_len(PATTERN_CHUNK)
+			.any(|_| data.is_stopped() || find_pattern_sync::<BENCH>(data));
+		batch.finish();
 		if BENCH {
 			return;
 		}
@@ -256,39 +2148,422 @@ fn find_pattern_parallel<const BENCH: bool>(data: &RunData) {
 // Tool: Increase security level
 
 fn tool_improve_sec_level(opts: Opts) -> Result {
-	let identity = opts.identity.ok_or("Requires an identity (-i) to export")?;
+	if opts.estimate {
+		return tool_estimate_level(&opts);
+	}
+	if let Some(path) = &opts.identities_file {
+		return tool_improve_sec_levels(&opts, path);
+	}
+	let identity = Zeroizing::new(
+		opts.identity
+			.clone()
+			.ok_or("Requires an identity (-i) to export")?,
+	);
+	let best = improve_sec_level(&opts, &identity, opts.counter)?;
+	Ok(level_outcome(&opts, &best))
+}
+
+fn tool_estimate_level(opts: &Opts) -> Result {
+	let want = opts.level.ok_or("Requires a level to estimate")?;
+	let want = u8::try_from(want).map_err(|_| "The level is too high")?;
+	if let Some(identity) = &opts.identity {
+		let tp_priv =
+			EccKeyPrivP256::import_str(identity).map_err(|_| "Failed to read identity")?;
+		let offset = opts.counter.unwrap_or(0);
+		let level = get_hash_cash_level(&tp_priv.to_pub().to_ts(), offset);
+		info!("The identity has level {} at offset {}", level, offset);
+		if level >= want {
+			println!("LEVEL {}: already reached", want);
+			return Ok(Outcome::Success);
+		}
+	}
+	let threads = rayon::current_num_threads();
+	let rate = match calibration::load(threads).and_then(|c| c.hashcash_per_sec) {
+		Some(rate) => rate,
+		None => {
+			info!("Measuring the hashcash rate, run 'bench' for a longer measurement");
+			bench::measure_hashcash(Duration::from_secs(2))?
+		}
+	};
+	info!(
+		"{} offsets per second on {} threads",
+		progress::human_count(rate),
+		threads
+	);
+	// Every offset reaches the level independent of the others, with a chance of 2^-level
+	let time_per_offset = Duration::from_secs_f64(1.0 / rate);
+	println!(
+		"LEVEL {}: {}",
+		want,
+		expect_time(time_per_offset, miss_log(want.into()))
+	);
+	Ok(Outcome::Success)
+}
+
+/// Reaching the end of the offsets without the wanted level counts as not found.
+fn level_outcome(opts: &Opts, best: &Level) -> Outcome {
+	if opts
+		.level
+		.map_or(false, |want| u64::from(best.level) < want)
+	{
+		Outcome::NotFound
+	} else {
+		Outcome::Success
+	}
+}
+
+/// Levels all identities of `--identities-file`.
+fn tool_improve_sec_levels(opts: &Opts, path: &Path) -> Result {
+	let content = Zeroizing::new(
+		std::fs::read_to_string(path)
+			.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?,
+	);
+	let identities = content
+		.lines()
+		.map(str::trim)
+		.filter(|l| !l.is_empty() && !l.starts_with('#'))
+		.collect::<Vec<_>>();
+	let mut outcome = Outcome::Success;
+	for (i, identity) in identities.iter().enumerate() {
+		if is_interrupted() {
+			break;
+		}
+		let (identity, counter) = identity_from_str(identity)
+			.map_err(|e| format!("Identity {} in {}: {}", i + 1, path.display(), e))?;
+		let identity = Zeroizing::new(identity);
+		let uid = EccKeyPrivP256::import_str(&identity)
+			.map_err(|_| format!("Failed to read identity {} in {}", i + 1, path.display()))?
+			.to_pub()
+			.get_uid()
+			.to_string();
+		info!("Leveling identity {}/{}", i + 1, identities.len());
+		println!("UID: {}", uid);
+		let best = improve_sec_level(opts, &identity, counter)?;
+		println!(
+			"RESULT: {} LEVEL: {} OFFSET: {}",
+			uid, best.level, best.offset
+		);
+		if level_outcome(opts, &best) == Outcome::NotFound {
+			outcome = Outcome::NotFound;
+		}
+	}
+	Ok(outcome)
+}
+
+/// The best level of the offsets from `start` to `end`, hashed `sha1x::LANES` at a time.
+fn best_level(midstate: &sha1x::Midstate, start: u64, end: u64) -> Level {
+	let lanes = sha1x::LANES as u64;
+	let last_off = end - 1;
+	(0..(end - start + lanes - 1) / lanes)
+		.into_par_iter()
+		.map(|chunk| {
+			control::throttle();
+			// The last chunk repeats the last offset to fill all lanes
+			let offsets = std::array::from_fn(|i| (start + chunk * lanes + i as u64).min(last_off));
+			let levels = midstate.levels(&offsets);
+			let (offset, level) = offsets
+				.into_iter()
+				.zip(levels)
+				.max_by_key(|(_, level)| *level)
+				.expect("Lanes are not empty");
+			Level { level, offset }
+		})
+		.max_by(|x, y| x.level.cmp(&y.level))
+		.expect("No elements in max")
+}
+
+/// Searches a better level for one identity and returns the best level.
+fn improve_sec_level(
+	opts: &Opts,
+	identity: &str,
+	counter: Option<u64>,
+) -> std::result::Result<Level, String> {
+	let mut stats = StatsTimer::new(
+		opts.stats_interval,
+		opts.output_format == OutputFormat::Json,
+	);
+	let notifications = notifications_from_opts(opts)?;
+	let store = opts
+		.store
+		.then(|| store::Store::open(opts.store_path.as_deref()))
+		.transpose()?;
+	let audit = opts
+		.audit_log
+		.as_deref()
+		.map(audit::AuditLog::open)
+		.transpose()?;
+	let mut milestones = opts
+		.milestones
+		.as_deref()
+		.map(|path| {
+			output::open_append(path)
+				.map_err(|e| format!("Failed to open milestones {}: {}", path.display(), e))
+		})
+		.transpose()?;
 	let want_level = opts.level.ok_or("Requires a level to reach")? as u8;
-	let tp_priv = EccKeyPrivP256::import_str(&identity).map_err(|_| "Failed to read identity")?;
+	let tp_priv = EccKeyPrivP256::import_str(identity).map_err(|_| "Failed to read identity")?;
 	let omega = tp_priv.to_pub().to_ts();
+	let midstate = sha1x::Midstate::new(&omega);
 
-	let mut start_off = opts.level.unwrap();
+	// Continue from the counter of the identity, everything before it was already checked
+	let base = opts.level.unwrap();
+	let mut start_off = counter.unwrap_or(base);
+	let mut end_off = u64::MAX;
+	if let Some(shard) = opts.shard {
+		// The ranges only depend on the level, so a resumed shard stays in its own range
+		let (begin, end) = shard.range(base);
+		(start_off, end_off) = (start_off.max(begin), end);
+		info!(
+			"Shard {} checks offsets {} to {}",
+			shard, start_off, end_off
+		);
+	}
+	if let Some(max) = opts.max_offset {
+		end_off = end_off.min(max.saturating_add(1));
+		if start_off >= end_off {
+			return Err(format!(
+				"The search starts at offset {}, after '--max-offset'",
+				start_off
+			));
+		}
+	}
+	#[cfg(unix)]
+	let status = status_socket(opts)?;
 	let mut best = Level {
 		level: get_hash_cash_level(&omega, start_off),
 		offset: start_off,
 	};
-	const BATCH_SIZE: u64 = 500_000;
+	let mut batch = batch_sizer(opts);
+	let first_off = start_off;
+	let progress = Progress::new(opts.quiet);
+	let energy = opts.energy.then(energy::EnergyMeter::start).flatten();
 
 	loop {
-		let max_res = (start_off..(start_off + BATCH_SIZE))
-			.into_par_iter()
-			.map(|i| Level {
-				level: get_hash_cash_level(&omega, i),
-				offset: i,
-			})
-			.max_by(|x, y| x.level.cmp(&y.level))
-			.expect("No elements in max");
+		control::wait_while_paused();
+		if is_interrupted() {
+			info!(
+				"Interrupted, best level {} at offset {}",
+				best.level, best.offset
+			);
+			break;
+		}
+		if start_off >= end_off {
+			info!(
+				"Checked all offsets, best level {} at offset {}",
+				best.level, best.offset
+			);
+			break;
+		}
+		let batch_size = batch.start().min(end_off - start_off);
+		let max_res = best_level(&midstate, start_off, start_off + batch_size);
+		batch.finish();
+		start_off += batch_size;
+		progress.update_level(
+			start_off - first_off,
+			stats.elapsed(),
+			best.level.max(max_res.level),
+			want_level,
+		);
 		if max_res.level > best.level {
 			best = max_res;
-			println!("LEVEL: {} OFFSET: {}", best.level, best.offset);
+			progress.suspend(|| println!("LEVEL: {} OFFSET: {}", best.level, best.offset));
+			if let Some(file) = &mut milestones {
+				let line = Zeroizing::new(format!(
+					"LEVEL: {} IDENTITY: {}V{}",
+					best.level,
+					best.offset,
+					*Zeroizing::new(tp_priv.to_ts_obfuscated())
+				));
+				if let Err(e) = output::write_durable(file, &line) {
+					error!("Failed to write milestone: {}", e);
+				}
+			}
+			if let Some(store) = &store {
+				if let Err(e) = store.put(&store::Entry::new(&tp_priv, best.offset, None)) {
+					error!("{}", e);
+				}
+			}
 			if best.level >= want_level {
+				if let Some(notifications) = &notifications {
+					notifications.send(notify::Event::LevelReached {
+						uid: tp_priv.to_pub().get_uid().to_string(),
+						level: best.level,
+						offset: best.offset,
+					});
+				}
 				break;
 			}
 		}
-		start_off += BATCH_SIZE;
 
-		if start_off & 0x07FF_FFFF < BATCH_SIZE {
-			println!("STEP: {}", (start_off / BATCH_SIZE) * BATCH_SIZE);
+		// Print a step every 2^27 offsets
+		if (start_off - batch_size) >> 27 != start_off >> 27 {
+			progress.suspend(|| info!("STEP: {}", start_off));
+		}
+		if stats.due() {
+			let mut s = Stats::new(start_off - first_off, stats.elapsed());
+			s.offset = Some(start_off);
+			s.best_level = Some(best.level);
+			progress.suspend(|| stats.print(&s));
+		}
+		#[cfg(unix)]
+		{
+			if let Some(status) = &status {
+				status.update(state::level_state(
+					stats.elapsed(),
+					start_off - first_off,
+					start_off,
+					want_level,
+					&best,
+				));
+			}
 		}
 	}
-	Ok(())
+	if let Some(notifications) = &notifications {
+		notifications.send(notify::Event::Finished(format!(
+			"Level search finished with level {} at offset {}",
+			best.level, best.offset
+		)));
+		notifications.finish();
+	}
+	if let Some(audit) = &audit {
+		let uid = tp_priv.to_pub().get_uid().to_string();
+		audit.record("level", &uid, best.level, best.offset);
+	}
+	progress.finish();
+	let elapsed = stats.elapsed();
+	info!(
+		"Checked {} offsets in {}, {:.2} MH/s",
+		start_off - first_off,
+		humantime::format_duration(Duration::from_secs(elapsed.as_secs())),
+		(start_off - first_off) as f64 / elapsed.as_secs_f64().max(0.001) / 1e6
+	);
+	if let Some(meter) = &energy {
+		meter.print_summary(None);
+	}
+	Ok(best)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn check(args: &[&str]) -> std::result::Result<(), String> {
+		let mut opts =
+			Opts::try_parse_from(std::iter::once("mahtsidentity").chain(args.iter().copied()))
+				.map_err(|e| e.to_string())?;
+		check_input(&mut opts)
+	}
+
+	#[test]
+	fn valid_input_passes() {
+		let key = EccKeyPrivP256::create();
+		check(&["-i", &key.to_ts(), "Abc"]).unwrap();
+		check(&["-i", &format!("5V{}", key.to_ts_obfuscated()), "-x"]).unwrap();
+		check(&["--format", "{uid};{key}", "Abc"]).unwrap();
+	}
+
+	#[test]
+	fn invalid_input_is_rejected() {
+		assert!(check(&["-i", "not a key", "Abc"]).is_err());
+		assert!(check(&["--format", "{nope}", "Abc"]).is_err());
+		assert!(check(&["Ab-c"]).is_err());
+
+		let path = std::env::temp_dir().join(format!("mahts-patterns-{}", std::process::id()));
+		std::fs::write(&path, "Abc\nnot valid\n").unwrap();
+		let result = check(&["--patterns-file", path.to_str().unwrap()]);
+		std::fs::remove_file(&path).unwrap();
+		assert!(result.is_err());
+	}
+
+	#[test]
+	fn usage_errors_are_invalid_input() {
+		let err = Opts::try_parse_from(["mahtsidentity", "--no-such-option"]).unwrap_err();
+		assert_eq!(err.exit_code(), EXIT_INVALID_INPUT);
+	}
+
+	#[test]
+	fn outcomes_have_the_documented_codes() {
+		assert_eq!(exit_code(Ok(Outcome::Success)), 0);
+		assert_eq!(exit_code(Err("failed".to_string())), EXIT_ERROR);
+		assert_eq!(exit_code(Ok(Outcome::NotFound)), EXIT_NOT_FOUND);
+		for code in [
+			EXIT_ERROR,
+			EXIT_INVALID_INPUT,
+			EXIT_NOT_FOUND,
+			EXIT_INTERRUPTED,
+		] {
+			assert!(EXIT_CODES.contains(&format!("    {} ", code)));
+		}
+	}
+
+	fn matches(pattern: &str, pub_key: &EccKeyPubP256) -> bool {
+		FindPattern::parse(pattern)
+			.unwrap()
+			.matches(uid_bits(pub_key))
+	}
+
+	#[test]
+	fn suffixes_match_the_end_of_the_uid() {
+		let pub_key = EccKeyPrivP256::create().to_pub();
+		let uid = pub_key.get_uid().to_string();
+		// Without the '=' padding
+		let end = &uid[20..27];
+		assert!(matches(&format!("*{}", end), &pub_key));
+		assert!(matches(&format!("*{}=", end), &pub_key));
+		assert!(matches(&format!("*?{}", &end[1..]), &pub_key));
+
+		let other = if end.starts_with('A') { 'B' } else { 'A' };
+		assert!(!matches(&format!("*{}{}", other, &end[1..]), &pub_key));
+	}
+
+	#[test]
+	fn prefixes_and_suffixes_match_together() {
+		let pub_key = EccKeyPrivP256::create().to_pub();
+		let uid = pub_key.get_uid().to_string();
+		let (head, tail) = (&uid[..3], &uid[23..27]);
+		assert!(matches(&format!("{}*{}", head, tail), &pub_key));
+
+		let other = if head.starts_with('A') { 'B' } else { 'A' };
+		assert!(!matches(
+			&format!("{}{}*{}", other, &head[1..], tail),
+			&pub_key
+		));
+		let pattern = FindPattern::parse(&format!("{}*{}", head, tail)).unwrap();
+		assert_eq!(pattern.bits(), 3 * 6 + 3 * 6 + 4);
+	}
+
+	#[test]
+	fn impossible_last_characters_are_rejected() {
+		for c in "AEIMQUYcgkosw048".chars() {
+			assert!(FindPattern::parse(&format!("*{}", c)).is_ok(), "{}", c);
+		}
+		for c in "BDZadz19+/".chars() {
+			assert!(FindPattern::parse(&format!("*{}", c)).is_err(), "{}", c);
+			assert!(FindPattern::parse(&format!("Abc*{}=", c)).is_err(), "{}", c);
+		}
+	}
+
+	#[test]
+	fn identity_strings_keep_the_counter() {
+		let key = EccKeyPrivP256::create();
+		let identity = format!("1234V{}", key.to_ts_obfuscated());
+		let (parsed, counter) = identity_from_str(&identity).unwrap();
+		assert_eq!(parsed, key.to_ts());
+		assert_eq!(counter, Some(1234));
+
+		let parsed = EccKeyPrivP256::import_str(&parsed).unwrap();
+		assert_eq!(*identity_string(&parsed, counter), identity);
+		assert_eq!(*identity_string(&parsed, None), key.to_ts_obfuscated());
+	}
+
+	#[test]
+	fn overflowing_counters_are_rejected() {
+		let key = EccKeyPrivP256::create();
+		let identity = format!("18446744073709551616V{}", key.to_ts_obfuscated());
+		assert!(ts3ini::parse_identity(&identity).is_err());
+		assert!(check(&["-i", &identity, "-x"]).is_err());
+
+		let identity = format!("18446744073709551615V{}", key.to_ts_obfuscated());
+		assert_eq!(ts3ini::parse_identity(&identity).unwrap().0, u64::MAX);
+	}
 }
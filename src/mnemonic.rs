@@ -0,0 +1,73 @@
+//! Identities derived from a BIP39 mnemonic phrase, so they can be recreated from a
+//! memorized backup.
+//!
+//! The BIP39 seed of the phrase (with the passphrase from 'MAHTS_MNEMONIC_PASSPHRASE') is
+//! turned into a '--seed' by taking its first 32 bytes. The search then goes through the
+//! numbered keys of that seed, and '--key-number' recreates a found one.
+//!
+//! Independent of that, '--mnemonic-backup' writes the private key itself as 24 BIP39 words,
+//! which '--identity' accepts again.
+
+use std::io::BufRead;
+
+use bip39::Mnemonic;
+use rand_core::{OsRng, RngCore};
+use tsproto_types::crypto::EccKeyPrivP256;
+use zeroize::Zeroizing;
+
+use crate::{Outcome, Result};
+
+/// Reads the phrase, '-' reads it from stdin.
+fn read(phrase: &str) -> std::result::Result<Mnemonic, String> {
+	let phrase = if phrase == "-" {
+		let mut line = Zeroizing::new(String::new());
+		std::io::stdin()
+			.lock()
+			.read_line(&mut line)
+			.map_err(|e| format!("Failed to read mnemonic from stdin: {}", e))?;
+		line
+	} else {
+		Zeroizing::new(phrase.to_string())
+	};
+	Mnemonic::parse(phrase.trim()).map_err(|e| format!("Invalid mnemonic: {}", e))
+}
+
+/// The '--seed' of a phrase.
+pub fn seed(phrase: &str) -> std::result::Result<Zeroizing<[u8; 32]>, String> {
+	let passphrase = Zeroizing::new(std::env::var("MAHTS_MNEMONIC_PASSPHRASE").unwrap_or_default());
+	let bip39_seed = Zeroizing::new(read(phrase)?.to_seed(passphrase.as_str()));
+	let mut seed = Zeroizing::new([0u8; 32]);
+	seed.copy_from_slice(&bip39_seed[..32]);
+	Ok(seed)
+}
+
+/// The private key as 24 words.
+pub fn to_backup(key: &EccKeyPrivP256) -> Zeroizing<String> {
+	let scalar = Zeroizing::new(key.to_short());
+	let mnemonic = Mnemonic::from_entropy(&scalar).expect("32 bytes are valid entropy");
+	Zeroizing::new(mnemonic.to_string())
+}
+
+/// Checks if the identity looks like the words of `to_backup`.
+pub fn is_backup(identity: &str) -> bool {
+	let words = identity.split_whitespace().collect::<Vec<_>>();
+	words.len() == 24
+		&& words
+			.iter()
+			.all(|w| w.chars().all(|c| c.is_ascii_lowercase()))
+}
+
+pub fn from_backup(identity: &str) -> std::result::Result<EccKeyPrivP256, String> {
+	let mnemonic =
+		Mnemonic::parse(identity.trim()).map_err(|e| format!("Invalid key backup: {}", e))?;
+	let scalar = Zeroizing::new(mnemonic.to_entropy());
+	EccKeyPrivP256::from_short(&scalar).map_err(|_| "Invalid private key".to_string())
+}
+
+pub fn tool_new_mnemonic() -> Result {
+	let mut entropy = Zeroizing::new([0u8; 32]);
+	OsRng.fill_bytes(&mut *entropy);
+	let mnemonic = Mnemonic::from_entropy(&*entropy).expect("32 bytes are valid entropy");
+	println!("MNEMONIC: {}", mnemonic);
+	Ok(Outcome::Success)
+}
@@ -0,0 +1,415 @@
+//! Notifications about found identities and finished searches.
+//!
+//! Notifications are sent from a background thread, so a slow or unreachable service never
+//! holds up the search.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport};
+use log::{debug, info, warn};
+use serde_json::json;
+
+use crate::output::Found;
+
+/// Number of tries before a notification is dropped.
+const TRIES: u32 = 4;
+/// The wait before the first retry, it doubles with every further retry.
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+/// Events which wait to be sent, further events are dropped.
+const QUEUE_LEN: usize = 64;
+/// The longest wait for pending notifications when the search ends.
+const FINISH_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum Event {
+	Found(Found),
+	/// The level search reached the wanted level.
+	LevelReached {
+		uid: String,
+		level: u8,
+		offset: u64,
+	},
+	/// A search ended, with a short summary.
+	Finished(String),
+}
+
+impl Event {
+	/// A short human readable description, without private keys.
+	pub fn describe(&self) -> String {
+		match self {
+			Event::Found(found) => format!(
+				"Found {} for pattern {} (level {})",
+				found.uid, found.pattern, found.level
+			),
+			Event::LevelReached { uid, level, offset } => {
+				format!("Reached level {} at offset {} for {}", level, offset, uid)
+			}
+			Event::Finished(summary) => summary.clone(),
+		}
+	}
+}
+
+/// A service which is notified about events.
+pub trait Notifier: Send {
+	/// A short description for log messages.
+	fn name(&self) -> String;
+	fn send(&self, event: &Event) -> Result<(), String>;
+	/// If failed notifications should be sent again.
+	fn retry(&self) -> bool {
+		true
+	}
+}
+
+/// Sends events to all notifiers in the background.
+#[derive(Debug)]
+pub struct Notifications {
+	sender: Mutex<Option<SyncSender<Event>>>,
+	thread: Mutex<Option<JoinHandle<()>>>,
+	/// Events which were dropped because the queue was full.
+	dropped: AtomicU64,
+}
+
+impl Notifications {
+	pub fn start(notifiers: Vec<Box<dyn Notifier>>) -> Self {
+		let (sender, receiver) = mpsc::sync_channel::<Event>(QUEUE_LEN);
+		let thread = std::thread::spawn(move || {
+			for event in receiver {
+				for notifier in &notifiers {
+					send_with_retries(notifier.as_ref(), &event);
+				}
+			}
+		});
+		Self {
+			sender: Mutex::new(Some(sender)),
+			thread: Mutex::new(Some(thread)),
+			dropped: AtomicU64::new(0),
+		}
+	}
+
+	/// Queues the event, it is dropped if too many events are waiting.
+	pub fn send(&self, event: Event) {
+		if let Some(sender) = &*self.sender.lock().unwrap() {
+			if let Err(TrySendError::Full(event)) = sender.try_send(event) {
+				if self.dropped.fetch_add(1, Ordering::Relaxed) == 0 {
+					warn!(
+						"Too many pending notifications, dropping them: {}",
+						event.describe()
+					);
+				}
+			}
+		}
+	}
+
+	/// Waits until all pending notifications are sent, for at most `FINISH_TIMEOUT`. Later
+	/// events are dropped.
+	pub fn finish(&self) {
+		self.sender.lock().unwrap().take();
+		let dropped = self.dropped.load(Ordering::Relaxed);
+		if dropped > 0 {
+			warn!("Dropped {} notifications", dropped);
+		}
+		if let Some(thread) = self.thread.lock().unwrap().take() {
+			info!("Sending pending notifications");
+			let start = Instant::now();
+			while !thread.is_finished() {
+				if start.elapsed() >= FINISH_TIMEOUT {
+					warn!("Gave up sending the pending notifications");
+					return;
+				}
+				std::thread::sleep(Duration::from_millis(100));
+			}
+			if thread.join().is_err() {
+				warn!("Notification thread panicked");
+			}
+		}
+	}
+}
+
+fn send_with_retries(notifier: &dyn Notifier, event: &Event) {
+	let mut delay = RETRY_DELAY;
+	for i in 1..=TRIES {
+		match notifier.send(event) {
+			Ok(()) => {
+				debug!("Sent notification to {}", notifier.name());
+				return;
+			}
+			Err(e) if i < TRIES && notifier.retry() => {
+				debug!(
+					"Failed to notify {}, retrying in {:?}: {}",
+					notifier.name(),
+					delay,
+					e
+				);
+				std::thread::sleep(delay);
+				delay *= 2;
+			}
+			Err(e) => warn!("Failed to notify {}: {}", notifier.name(), e),
+		}
+	}
+}
+
+/// POSTs a json object for every event.
+pub struct Webhook {
+	pub url: String,
+}
+
+impl Notifier for Webhook {
+	fn name(&self) -> String {
+		// The path and query often contain a secret, keep them out of the logs
+		format!("webhook on {}", url_host(&self.url))
+	}
+
+	fn send(&self, event: &Event) -> Result<(), String> {
+		let body = match event {
+			Event::Found(found) => json!({
+				"event": "found",
+				"uid": found.uid,
+				"key": found.key,
+				"level": found.level,
+				"pattern": found.pattern,
+				"timestamp": found.timestamp,
+			}),
+			Event::LevelReached { uid, level, offset } => json!({
+				"event": "level_reached",
+				"uid": uid,
+				"level": level,
+				"offset": offset,
+			}),
+			Event::Finished(summary) => json!({
+				"event": "finished",
+				"summary": summary,
+			}),
+		};
+		ureq::post(&self.url)
+			.timeout(Duration::from_secs(30))
+			.send_json(body)
+			// Errors contain the url
+			.map_err(|e| match e {
+				ureq::Error::Status(code, _) => {
+					format!("The webhook answered with status {}", code)
+				}
+				ureq::Error::Transport(e) => format!("Failed to reach the webhook: {}", e.kind()),
+			})?;
+		Ok(())
+	}
+}
+
+/// The host and port of a url, without the user, path and query.
+fn url_host(url: &str) -> &str {
+	let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+	let authority = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+	authority
+		.rsplit_once('@')
+		.map_or(authority, |(_, host)| host)
+}
+
+/// What a Telegram message about a found identity contains.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TelegramDetails {
+	/// Only that something was found, the identity has to be taken from the output.
+	Minimal,
+	/// The uid and pattern.
+	Uid,
+	/// The uid, pattern and the private key.
+	Key,
+}
+
+/// Sends a message to a Telegram chat through a bot.
+pub struct Telegram {
+	pub token: String,
+	pub chat: String,
+	pub details: TelegramDetails,
+}
+
+impl Notifier for Telegram {
+	fn name(&self) -> String {
+		// The token is a secret, keep it out of the logs
+		format!("Telegram chat {}", self.chat)
+	}
+
+	fn send(&self, event: &Event) -> Result<(), String> {
+		let text = match (event, self.details) {
+			(Event::Found(_), TelegramDetails::Minimal) => {
+				"Match found, see the output file".to_string()
+			}
+			(Event::Found(found), TelegramDetails::Key) => {
+				format!("{}\nKey: {}", event.describe(), found.key)
+			}
+			_ => event.describe(),
+		};
+		let url = format!("https://api.telegram.org/bot{}/sendMessage", self.token);
+		ureq::post(&url)
+			.timeout(Duration::from_secs(30))
+			.send_json(json!({ "chat_id": self.chat, "text": text }))
+			// Errors contain the url with the token
+			.map_err(|e| match e {
+				ureq::Error::Status(code, _) => format!("Telegram answered with status {}", code),
+				ureq::Error::Transport(e) => format!("Failed to reach Telegram: {}", e.kind()),
+			})?;
+		Ok(())
+	}
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SmtpSecurity {
+	/// Upgrades the connection with STARTTLS, usually on port 587.
+	Starttls,
+	/// Connects with TLS, usually on port 465.
+	Tls,
+	/// Sends unencrypted, only use this with a relay in the local network.
+	None,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SmtpArgs {
+	#[clap(long, value_name = "HOST", requires_all = &["mail-from", "mail-to"])]
+	/// Sends an email about found identities, reached levels and finished searches through
+	/// this SMTP server.
+	pub smtp_server: Option<String>,
+
+	#[clap(long)]
+	/// The port of the SMTP server, defaults to the port of '--smtp-security'.
+	pub smtp_port: Option<u16>,
+
+	#[clap(long, value_enum, default_value = "starttls")]
+	/// How the connection to the SMTP server is encrypted.
+	pub smtp_security: SmtpSecurity,
+
+	#[clap(long)]
+	/// Logs in on the SMTP server with this user. The password is read from the
+	/// 'SMTP_PASSWORD' environment variable.
+	pub smtp_user: Option<String>,
+
+	#[clap(long, value_name = "ADDRESS")]
+	/// The sender of the emails, e.g. 'Miner <miner@example.com>'.
+	pub mail_from: Option<String>,
+
+	#[clap(long, value_name = "ADDRESS")]
+	/// The recipient of the emails.
+	pub mail_to: Option<String>,
+}
+
+/// Sends emails through an SMTP server.
+pub struct Smtp {
+	transport: SmtpTransport,
+	from: Mailbox,
+	to: Mailbox,
+}
+
+impl Smtp {
+	/// Returns `None` if no SMTP server is configured.
+	pub fn from_args(args: &SmtpArgs) -> Result<Option<Self>, String> {
+		let server = match &args.smtp_server {
+			Some(s) => s,
+			None => return Ok(None),
+		};
+		let parse_address = |address: &Option<String>| {
+			let address = address.as_deref().unwrap_or_default();
+			address
+				.parse::<Mailbox>()
+				.map_err(|e| format!("Invalid mail address {}: {}", address, e))
+		};
+		let from = parse_address(&args.mail_from)?;
+		let to = parse_address(&args.mail_to)?;
+		let mut builder = match args.smtp_security {
+			SmtpSecurity::Starttls => SmtpTransport::starttls_relay(server),
+			SmtpSecurity::Tls => SmtpTransport::relay(server),
+			SmtpSecurity::None => Ok(SmtpTransport::builder_dangerous(server)),
+		}
+		.map_err(|e| format!("Failed to set up SMTP for {}: {}", server, e))?
+		.timeout(Some(Duration::from_secs(30)));
+		if let Some(port) = args.smtp_port {
+			builder = builder.port(port);
+		}
+		if let Some(user) = &args.smtp_user {
+			let password = std::env::var("SMTP_PASSWORD")
+				.map_err(|_| "'--smtp-user' requires the 'SMTP_PASSWORD' environment variable")?;
+			builder = builder.credentials(Credentials::new(user.clone(), password));
+		}
+		Ok(Some(Self {
+			transport: builder.build(),
+			from,
+			to,
+		}))
+	}
+}
+
+impl Notifier for Smtp {
+	fn name(&self) -> String {
+		format!("mail to {}", self.to)
+	}
+
+	fn send(&self, event: &Event) -> Result<(), String> {
+		let subject = match event {
+			Event::Found(found) => format!("Found {}", found.uid),
+			Event::LevelReached { level, .. } => format!("Reached level {}", level),
+			Event::Finished(_) => "Search finished".to_string(),
+		};
+		let message = Message::builder()
+			.from(self.from.clone())
+			.to(self.to.clone())
+			.subject(subject)
+			.body(event.describe())
+			.map_err(|e| format!("Failed to build mail: {}", e))?;
+		self.transport.send(&message).map_err(|e| e.to_string())?;
+		Ok(())
+	}
+}
+
+/// Runs a shell command for every found identity.
+///
+/// The identity is passed in the `MATCH_UID`, `MATCH_KEY`, `MATCH_KEY_OBFUSCATED`,
+/// `MATCH_LEVEL`, `MATCH_PATTERN`, `MATCH_TIMESTAMP` and `MATCH_ATTEMPTS` environment variables.
+pub struct Hook {
+	pub command: String,
+}
+
+impl Notifier for Hook {
+	fn name(&self) -> String {
+		format!("'{}'", self.command)
+	}
+
+	fn send(&self, event: &Event) -> Result<(), String> {
+		let found = match event {
+			Event::Found(found) => found,
+			_ => return Ok(()),
+		};
+		#[cfg(windows)]
+		let mut command = std::process::Command::new("cmd");
+		#[cfg(windows)]
+		command.arg("/C");
+		#[cfg(not(windows))]
+		let mut command = std::process::Command::new("sh");
+		#[cfg(not(windows))]
+		command.arg("-c");
+		let status = command
+			.arg(&self.command)
+			.stdin(std::process::Stdio::null())
+			// Keep stdout for the found identities
+			.stdout(std::process::Stdio::null())
+			.env("MATCH_UID", &found.uid)
+			.env("MATCH_KEY", &found.key)
+			.env("MATCH_KEY_OBFUSCATED", &found.key_obfuscated)
+			.env("MATCH_LEVEL", found.level.to_string())
+			.env("MATCH_PATTERN", &found.pattern)
+			.env("MATCH_TIMESTAMP", &found.timestamp)
+			.env("MATCH_ATTEMPTS", found.attempts.to_string())
+			.status()
+			.map_err(|e| format!("Failed to run: {}", e))?;
+		if !status.success() {
+			return Err(format!("Exited with {}", status));
+		}
+		Ok(())
+	}
+
+	/// The command may have side effects, so it is run only once.
+	fn retry(&self) -> bool {
+		false
+	}
+}
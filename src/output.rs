@@ -0,0 +1,780 @@
+use std::collections::{HashSet, VecDeque};
+use std::fs::{File, OpenOptions};
+use std::io::{IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use log::{error, info, warn};
+use serde::Serialize;
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+use zeroize::{Zeroize, Zeroizing};
+
+use crate::audit::AuditLog;
+use crate::convert;
+use crate::db::Database;
+use crate::mnemonic;
+use crate::notify::{Event, Notifications};
+use crate::recipient::Recipient;
+use crate::store::{Entry, Store};
+use crate::ts3ini;
+
+/// Number of found identities which are kept for the state, the dashboard and the tui.
+const RECENT_FOUND: usize = 100;
+const CSV_HEADER: &str = "uid,key,key_obfuscated,level,pattern,timestamp";
+const TEMPLATE_FIELDS: &[&str] = &[
+	"uid",
+	"key",
+	"key_obfuscated",
+	"level",
+	"pattern",
+	"timestamp",
+	"attempts",
+];
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OutputFormat {
+	/// Human readable `UID: ... KEY: ...` lines.
+	Text,
+	/// One json object per line (NDJSON).
+	Json,
+	/// A header line followed by one comma separated row per identity.
+	Csv,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorChoice {
+	/// Colors if stdout is a terminal and 'NO_COLOR' is not set.
+	Auto,
+	Always,
+	Never,
+}
+
+impl ColorChoice {
+	pub fn enabled(self) -> bool {
+		match self {
+			Self::Auto => std::io::stdout().is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+			Self::Always => true,
+			Self::Never => false,
+		}
+	}
+}
+
+/// Decides how found identities are printed and stored.
+#[derive(Clone, Debug)]
+pub struct Output {
+	format: OutputFormat,
+	/// A user defined line format, overrides `format`.
+	template: Option<Template>,
+	/// Every found identity is additionally appended to this file.
+	file: Option<Arc<Mutex<File>>>,
+	/// Every found identity is additionally stored in this database.
+	db: Option<Arc<Database>>,
+	/// Every found identity is additionally written to its own file in this directory.
+	dir: Option<OutputDir>,
+	/// The private keys in `file` are encrypted to this recipient.
+	file_recipient: Option<Recipient>,
+	/// Every found identity is additionally added to this keystore.
+	store: Option<Arc<Store>>,
+	/// Records every found identity without its key.
+	audit: Option<Arc<AuditLog>>,
+	/// Prints the key of found identities as words after the line on stdout.
+	mnemonic_backup: bool,
+	/// Highlights the matched part of the uid on stdout.
+	color: bool,
+	/// Limits the identities printed to stdout, if they are also kept somewhere else.
+	print_limit: Option<Arc<PrintLimit>>,
+	/// Skips uids which were already reported or are in the database. Only keys of a seed
+	/// repeat, so random keys are not remembered.
+	dedup: bool,
+	/// Uids which were reported in this run, with `dedup`.
+	seen: Arc<Mutex<HashSet<String>>>,
+	/// Uids which are never reported.
+	blocked: Arc<HashSet<String>>,
+	/// Collects found identities instead of printing them to stdout.
+	collected: Option<Arc<Mutex<Vec<Found>>>>,
+	/// The last identities found in this run.
+	found: Arc<Mutex<VecDeque<Found>>>,
+	notifications: Option<Arc<Notifications>>,
+}
+
+/// A parsed `--format` string like `{uid};{key_obfuscated}`.
+///
+/// `{{` and `}}` can be used to print literal braces.
+#[derive(Clone, Debug)]
+pub struct Template {
+	parts: Vec<TemplatePart>,
+}
+
+#[derive(Clone, Debug)]
+enum TemplatePart {
+	Literal(String),
+	Field(&'static str),
+}
+
+impl Output {
+	pub fn new(format: OutputFormat, template: Option<Template>) -> Self {
+		Self {
+			format,
+			template,
+			file: None,
+			db: None,
+			dir: None,
+			file_recipient: None,
+			store: None,
+			audit: None,
+			mnemonic_backup: false,
+			color: false,
+			print_limit: None,
+			dedup: false,
+			seen: Default::default(),
+			blocked: Default::default(),
+			collected: None,
+			found: Default::default(),
+			notifications: None,
+		}
+	}
+
+	/// Collects all found identities in the returned list instead of printing them.
+	pub fn collect(&mut self) -> Arc<Mutex<Vec<Found>>> {
+		self.collected.get_or_insert_with(Default::default).clone()
+	}
+
+	/// Sends a notification for every found identity.
+	pub fn set_notifications(&mut self, notifications: Notifications) {
+		self.notifications = Some(Arc::new(notifications));
+	}
+
+	pub fn notify(&self, event: Event) {
+		if let Some(notifications) = &self.notifications {
+			notifications.send(event);
+		}
+	}
+
+	/// Waits for pending notifications, call this before exiting.
+	pub fn finish(&self) {
+		if let Some(limit) = &self.print_limit {
+			let skipped = std::mem::take(&mut limit.state.lock().unwrap().skipped);
+			self.report_skipped(skipped);
+		}
+		if let Some(notifications) = &self.notifications {
+			notifications.finish();
+		}
+	}
+
+	/// Prints at most `per_sec` identities per second to stdout, the others are only counted.
+	///
+	/// Call this after opening the other outputs, without them every identity is printed
+	/// because stdout is the only place which has it.
+	pub fn set_print_limit(&mut self, per_sec: u32) {
+		if per_sec > 0 && !self.kept_in().is_empty() {
+			self.print_limit = Some(Arc::new(PrintLimit {
+				per_sec,
+				state: Mutex::new(LimitState {
+					window: Instant::now(),
+					printed: 0,
+					skipped: 0,
+				}),
+			}));
+		}
+	}
+
+	/// Where found identities are kept besides stdout.
+	fn kept_in(&self) -> Vec<&'static str> {
+		let mut kept = Vec::new();
+		if self.file.is_some() {
+			kept.push("the output file");
+		}
+		if self.dir.is_some() {
+			kept.push("the output directory");
+		}
+		if self.db.is_some() {
+			kept.push("the database");
+		}
+		if self.store.is_some() {
+			kept.push("the keystore");
+		}
+		kept
+	}
+
+	fn report_skipped(&self, skipped: u64) {
+		if skipped > 0 {
+			info!(
+				"+{} more matches written to {}",
+				skipped,
+				self.kept_in().join(" and ")
+			);
+		}
+	}
+
+	/// Appends all found identities to the given file, creating it if needed.
+	pub fn open_file(&mut self, path: &Path) -> Result<(), String> {
+		let mut file = open_append(path)
+			.map_err(|e| format!("Failed to open output file {}: {}", path.display(), e))?;
+		let is_empty = file
+			.metadata()
+			.map_err(|e| format!("Failed to read output file {}: {}", path.display(), e))?
+			.len() == 0;
+		if is_empty {
+			if let Some(header) = self.header() {
+				write_durable(&mut file, &header).map_err(|e| {
+					format!("Failed to write output file {}: {}", path.display(), e)
+				})?;
+			}
+		}
+		self.file = Some(Arc::new(Mutex::new(file)));
+		Ok(())
+	}
+
+	/// Encrypts the private keys in the output file, the rest of the line stays readable.
+	pub fn set_file_recipient(&mut self, recipient: Recipient) {
+		self.file_recipient = Some(recipient);
+	}
+
+	pub fn set_audit_log(&mut self, audit: Arc<AuditLog>) {
+		self.audit = Some(audit);
+	}
+
+	/// Skips uids which were already reported, for keys which are derived from a seed.
+	pub fn set_dedup(&mut self) {
+		self.dedup = true;
+	}
+
+	/// Adds a 'MNEMONIC: <words>' line for every found identity, only with the text format.
+	pub fn set_mnemonic_backup(&mut self) -> Result<(), String> {
+		if self.format != OutputFormat::Text || self.template.is_some() {
+			return Err("'--mnemonic-backup' only works with the text output format".to_string());
+		}
+		self.mnemonic_backup = true;
+		Ok(())
+	}
+
+	/// Highlights the characters of the uid which matched the pattern, only for the text format.
+	pub fn set_color(&mut self, color: bool) {
+		self.color = color && self.format == OutputFormat::Text && self.template.is_none();
+	}
+
+	/// Adds all found identities to the keystore.
+	pub fn set_store(&mut self, store: Store) {
+		self.store = Some(Arc::new(store));
+	}
+
+	/// Writes every found identity to `<uid>.json` or `<uid>.ini` in the directory.
+	pub fn open_dir(
+		&mut self,
+		path: &Path,
+		format: DirFormat,
+		nickname: &str,
+	) -> Result<(), String> {
+		std::fs::create_dir_all(path).map_err(|e| {
+			format!(
+				"Failed to create output directory {}: {}",
+				path.display(),
+				e
+			)
+		})?;
+		self.dir = Some(OutputDir {
+			path: path.to_path_buf(),
+			format,
+			nickname: nickname.to_string(),
+		});
+		Ok(())
+	}
+
+	/// Stores all found identities in the given SQLite database.
+	pub fn open_db(&mut self, path: &Path) -> Result<(), String> {
+		self.db = Some(Arc::new(Database::open(path)?));
+		Ok(())
+	}
+
+	/// Reads uids which must not be reported, one per line.
+	///
+	/// Empty lines and lines starting with `#` are skipped.
+	pub fn open_blocklist(&mut self, path: &Path) -> Result<(), String> {
+		let content = std::fs::read_to_string(path)
+			.map_err(|e| format!("Failed to read blocklist {}: {}", path.display(), e))?;
+		self.blocked = Arc::new(
+			content
+				.lines()
+				.map(str::trim)
+				.filter(|l| !l.is_empty() && !l.starts_with('#'))
+				.map(str::to_string)
+				.collect(),
+		);
+		Ok(())
+	}
+
+	/// Remembers a uid, returns `false` if it was already reported before and should be skipped.
+	///
+	/// Uids from the blocklist are always skipped.
+	pub fn claim(&self, uid: &str) -> bool {
+		if self.blocked.contains(uid) {
+			warn!("Skipping uid {}, it is on the blocklist", uid);
+			return false;
+		}
+		if !self.dedup {
+			return true;
+		}
+		if let Some(db) = &self.db {
+			match db.contains(uid) {
+				Ok(true) => return false,
+				Ok(false) => {}
+				Err(e) => warn!("Failed to read database: {}", e),
+			}
+		}
+		let mut seen = self.seen.lock().unwrap();
+		if seen.contains(uid) {
+			false
+		} else {
+			seen.insert(uid.to_string());
+			true
+		}
+	}
+
+	/// Returns the last `RECENT_FOUND` identities found in this run, the oldest first.
+	pub fn found(&self) -> Vec<Found> {
+		self.found.lock().unwrap().iter().cloned().collect()
+	}
+
+	pub fn format(&self, found: &Found) -> String {
+		match &self.template {
+			Some(template) => template.render(found),
+			None => found.format(self.format),
+		}
+	}
+
+	/// Everything that has to precede the first found identity.
+	fn header(&self) -> Option<String> {
+		if self.template.is_none() && self.format == OutputFormat::Csv {
+			Some(CSV_HEADER.to_string())
+		} else {
+			None
+		}
+	}
+
+	/// Prints everything that has to precede the first found identity.
+	pub fn print_header(&self) {
+		if let Some(header) = self.header() {
+			println!("{}", header);
+		}
+	}
+
+	/// Acknowledges a pattern from stdin which has enough matches, after its identities.
+	pub fn print_satisfied(&self, pattern: &str) {
+		let line = match self.format {
+			OutputFormat::Json => serde_json::json!({ "satisfied": pattern }).to_string(),
+			_ => format!("SATISFIED: {}", pattern),
+		};
+		let stdout = std::io::stdout();
+		let mut lock = stdout.lock();
+		let _ = writeln!(lock, "{}", line);
+		let _ = lock.flush();
+	}
+
+	/// Formats a found identity with encrypted private keys.
+	fn encrypted_line(&self, found: &Found, recipient: &Recipient) -> Result<String, String> {
+		let mut found = found.clone();
+		found.key = recipient.encrypt(&found.key)?;
+		found.key_obfuscated = recipient.encrypt(&found.key_obfuscated)?;
+		Ok(self.format(&found))
+	}
+
+	/// Prints a found identity and flushes stdout immediately, so the output can be streamed.
+	///
+	/// If an output file is set, the identity is written and synced to the file first.
+	pub fn print_found(&self, found: &Found) {
+		let line = self.format(found);
+		if let Some(file) = &self.file {
+			let file_line = match &self.file_recipient {
+				Some(recipient) => self.encrypted_line(found, recipient),
+				None => Ok(line.clone()),
+			};
+			let result = file_line.and_then(|l| {
+				write_durable(&mut file.lock().unwrap(), &l).map_err(|e| e.to_string())
+			});
+			if let Err(e) = result {
+				error!("Failed to write to output file: {}", e);
+			}
+		}
+		if let Some(db) = &self.db {
+			if let Err(e) = db.insert(found) {
+				error!("Failed to write to database: {}", e);
+			}
+		}
+		if let Some(dir) = &self.dir {
+			if let Err(e) = dir.write(found) {
+				error!("{}", e);
+			}
+		}
+		if let Some(store) = &self.store {
+			if let Err(e) = store.put(&Entry::from_found(found)) {
+				error!("{}", e);
+			}
+		}
+		if let Some(audit) = &self.audit {
+			audit.record("find", &found.uid, found.level, 0);
+		}
+
+		let mut recent = self.found.lock().unwrap();
+		if recent.len() == RECENT_FOUND {
+			recent.pop_front();
+		}
+		recent.push_back(found.clone());
+		drop(recent);
+		self.notify(Event::Found(found.clone()));
+		if let Some(collected) = &self.collected {
+			collected.lock().unwrap().push(found.clone());
+			return;
+		}
+
+		if let Some(limit) = &self.print_limit {
+			let mut state = limit.state.lock().unwrap();
+			if state.window.elapsed() >= Duration::from_secs(1) {
+				let skipped = std::mem::take(&mut state.skipped);
+				self.report_skipped(skipped);
+				state.window = Instant::now();
+				state.printed = 0;
+			}
+			if state.printed >= limit.per_sec {
+				state.skipped += 1;
+				return;
+			}
+			state.printed += 1;
+		}
+
+		let stdout = std::io::stdout();
+		let mut lock = stdout.lock();
+		let line = if self.color {
+			format!("UID: {} KEY: {}", found.highlighted_uid(), found.key)
+		} else {
+			line
+		};
+		// Ignore errors from a closed pipe, the search should not panic because of it.
+		let _ = writeln!(lock, "{}", line);
+		if self.mnemonic_backup {
+			if let Ok(key) = EccKeyPrivP256::import_str(&found.key) {
+				let _ = writeln!(lock, "MNEMONIC: {}", *mnemonic::to_backup(&key));
+			}
+		}
+		let _ = lock.flush();
+	}
+}
+
+#[derive(Debug)]
+struct PrintLimit {
+	per_sec: u32,
+	state: Mutex<LimitState>,
+}
+
+#[derive(Debug)]
+struct LimitState {
+	/// The start of the current second.
+	window: Instant,
+	printed: u32,
+	/// Identities which were not printed since the last summary.
+	skipped: u64,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DirFormat {
+	/// The identity as json object, like '--output-format json'.
+	Json,
+	/// An ini file for the identity import of the TeamSpeak 3 client.
+	Ini,
+}
+
+#[derive(Clone, Debug)]
+struct OutputDir {
+	path: PathBuf,
+	format: DirFormat,
+	/// The nickname in ini files.
+	nickname: String,
+}
+
+impl OutputDir {
+	/// Writes to a temporary file first and renames it, so a file is never seen half written.
+	fn write(&self, found: &Found) -> Result<(), String> {
+		let (content, extension) = match self.format {
+			DirFormat::Json => (
+				Zeroizing::new(found.format(OutputFormat::Json) + "\n"),
+				"json",
+			),
+			DirFormat::Ini => {
+				let key = EccKeyPrivP256::import_str(&found.key)
+					.map_err(|_| format!("Invalid key for {}", found.uid))?;
+				(
+					Zeroizing::new(ts3ini::to_ini(&key, &self.nickname, 0)),
+					"ini",
+				)
+			}
+		};
+		let name = convert::uid_file_name(&found.uid);
+		let path = self.path.join(format!("{}.{}", name, extension));
+		let tmp = self.path.join(format!(".{}.{}.tmp", name, extension));
+		let write = || -> std::io::Result<()> {
+			let mut file = create_private(&tmp)?;
+			file.write_all(content.as_bytes())?;
+			file.sync_all()?;
+			std::fs::rename(&tmp, &path)?;
+			// The rename is only durable once the directory is written
+			#[cfg(unix)]
+			File::open(&self.path)?.sync_all()?;
+			Ok(())
+		};
+		write().map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+	}
+}
+
+/// Options which create files that are only readable by the owner on unix, they contain
+/// private keys.
+fn private_options() -> OpenOptions {
+	let mut options = OpenOptions::new();
+	options.create(true);
+	#[cfg(unix)]
+	std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+	options
+}
+
+/// Opens a file for appending, new files are only readable by the owner.
+pub fn open_append(path: &Path) -> std::io::Result<File> {
+	private_options().append(true).open(path)
+}
+
+/// Creates or truncates a file, new files are only readable by the owner.
+pub fn create_private(path: &Path) -> std::io::Result<File> {
+	private_options().write(true).truncate(true).open(path)
+}
+
+/// Replaces the content of a file like `std::fs::write`, new files are only readable by the
+/// owner.
+pub fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+	create_private(path)?.write_all(content)
+}
+
+/// Appends a line with a single write and waits until it reached the disk.
+///
+/// The file is opened in append mode, so a crash can at most lose the line which is currently
+/// written, but never corrupt earlier lines.
+pub fn write_durable(file: &mut File, line: &str) -> std::io::Result<()> {
+	let mut buf = String::with_capacity(line.len() + 1);
+	buf.push_str(line);
+	buf.push('\n');
+	file.write_all(buf.as_bytes())?;
+	file.sync_data()
+}
+
+impl Template {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let mut parts = Vec::new();
+		let mut literal = String::new();
+		let mut chars = s.chars().peekable();
+		while let Some(c) = chars.next() {
+			match c {
+				'{' if chars.peek() == Some(&'{') => {
+					chars.next();
+					literal.push('{');
+				}
+				'}' if chars.peek() == Some(&'}') => {
+					chars.next();
+					literal.push('}');
+				}
+				'{' => {
+					let mut name = String::new();
+					loop {
+						match chars.next() {
+							Some('}') => break,
+							Some(c) => name.push(c),
+							None => {
+								return Err(format!("Unclosed placeholder '{{{}' in format", name))
+							}
+						}
+					}
+					let field = TEMPLATE_FIELDS
+						.iter()
+						.find(|f| **f == name)
+						.ok_or_else(|| {
+							format!(
+								"Unknown placeholder '{{{}}}' in format, available are: {}",
+								name,
+								TEMPLATE_FIELDS.join(", ")
+							)
+						})?;
+					if !literal.is_empty() {
+						parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+					}
+					parts.push(TemplatePart::Field(*field));
+				}
+				'}' => {
+					return Err("Unmatched '}' in format, use '}}' for a literal brace".to_string())
+				}
+				c => literal.push(c),
+			}
+		}
+		if !literal.is_empty() {
+			parts.push(TemplatePart::Literal(literal));
+		}
+		Ok(Self { parts })
+	}
+
+	pub fn render(&self, found: &Found) -> String {
+		let mut res = String::new();
+		for part in &self.parts {
+			match part {
+				TemplatePart::Literal(s) => res.push_str(s),
+				TemplatePart::Field(field) => res.push_str(&found.field(field)),
+			}
+		}
+		res
+	}
+}
+
+/// A found identity.
+#[derive(Clone, Debug, Serialize)]
+pub struct Found {
+	pub uid: String,
+	pub key: String,
+	pub key_obfuscated: String,
+	/// The security level at offset 0.
+	pub level: u8,
+	pub pattern: String,
+	/// Time when the identity was found, in RFC 3339 format.
+	pub timestamp: String,
+	/// Number of generated keys until this identity was found.
+	pub attempts: u64,
+}
+
+impl Drop for Found {
+	fn drop(&mut self) {
+		self.key.zeroize();
+		self.key_obfuscated.zeroize();
+	}
+}
+
+impl Found {
+	pub fn new(key: &EccKeyPrivP256, pattern: String, attempts: u64) -> Self {
+		let pub_key = key.to_pub();
+		Self {
+			uid: pub_key.get_uid().to_string(),
+			key: key.to_ts(),
+			key_obfuscated: key.to_ts_obfuscated(),
+			level: get_hash_cash_level(&pub_key.to_ts(), 0),
+			pattern,
+			timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+			attempts,
+		}
+	}
+
+	/// Returns the value of a field by its name in `TEMPLATE_FIELDS`.
+	fn field(&self, name: &str) -> String {
+		match name {
+			"uid" => self.uid.clone(),
+			"key" => self.key.clone(),
+			"key_obfuscated" => self.key_obfuscated.clone(),
+			"level" => self.level.to_string(),
+			"pattern" => self.pattern.clone(),
+			"timestamp" => self.timestamp.clone(),
+			"attempts" => self.attempts.to_string(),
+			_ => unreachable!("Unknown template field {}", name),
+		}
+	}
+
+	/// The uid with the characters fixed by the pattern in bold green and the wildcards dimmed.
+	///
+	/// Patterns of predicates are their names, the uid is not colored if it does not match.
+	fn highlighted_uid(&self) -> String {
+		let fixed = |c: char| c != '_' && c != '?';
+		let (head, tail) = self.pattern.split_once('*').unwrap_or((&self.pattern, ""));
+		let tail = tail.strip_suffix('=').unwrap_or(tail);
+		// A suffix ends before the '=' of the uid
+		let end = self.uid.trim_end_matches('=').len();
+		let (head_len, tail_len) = (head.chars().count(), tail.chars().count());
+		if self.pattern.is_empty() || head_len + tail_len > end {
+			return self.uid.clone();
+		}
+		let mut spec = vec![None; self.uid.len()];
+		for (i, c) in head.chars().enumerate() {
+			spec[i] = Some(c);
+		}
+		for (i, c) in tail.chars().enumerate() {
+			spec[end - tail_len + i] = Some(c);
+		}
+		let matches = spec
+			.iter()
+			.zip(self.uid.chars())
+			.all(|(p, u)| p.map_or(true, |p| !fixed(p) || p == u));
+		if !matches {
+			return self.uid.clone();
+		}
+		self.uid
+			.chars()
+			.zip(&spec)
+			.map(|(u, p)| match p {
+				Some(p) if fixed(*p) => format!("\x1b[1;32m{}\x1b[0m", u),
+				Some(_) => format!("\x1b[2m{}\x1b[0m", u),
+				None => u.to_string(),
+			})
+			.collect()
+	}
+
+	pub fn format(&self, format: OutputFormat) -> String {
+		match format {
+			OutputFormat::Text => format!("UID: {} KEY: {}", self.uid, self.key),
+			OutputFormat::Json => {
+				serde_json::to_string(self).expect("Found is always serializable")
+			}
+			OutputFormat::Csv => [
+				csv_field(&self.uid),
+				csv_field(&self.key),
+				csv_field(&self.key_obfuscated),
+				self.level.to_string(),
+				csv_field(&self.pattern),
+				csv_field(&self.timestamp),
+			]
+			.join(","),
+		}
+	}
+}
+
+/// Quotes a csv field if it contains characters with a special meaning.
+fn csv_field(s: &str) -> String {
+	if s.contains(|c| matches!(c, ',' | '"' | '\n' | '\r')) {
+		format!("\"{}\"", s.replace('"', "\"\""))
+	} else {
+		s.to_string()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn templates_render_placeholders_and_braces() {
+		let found = Found::new(&EccKeyPrivP256::create(), "Abc".to_string(), 42);
+		let template = Template::parse("{uid};{{x}};{pattern}:{level}").unwrap();
+		assert_eq!(
+			template.render(&found),
+			format!("{};{{x}};Abc:{}", found.uid, found.level)
+		);
+		assert_eq!(Template::parse("plain").unwrap().render(&found), "plain");
+		assert_eq!(Template::parse("").unwrap().render(&found), "");
+	}
+
+	#[test]
+	fn templates_reject_invalid_placeholders() {
+		for invalid in ["{nope}", "{uid", "a{", "uid}", "{}", "{UID}"] {
+			assert!(Template::parse(invalid).is_err(), "{}", invalid);
+		}
+	}
+
+	#[test]
+	fn csv_fields_are_quoted_when_needed() {
+		assert_eq!(csv_field("Abc+/="), "Abc+/=");
+		assert_eq!(csv_field(""), "");
+		assert_eq!(csv_field("a,b"), "\"a,b\"");
+		assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+		assert_eq!(csv_field("a\nb"), "\"a\nb\"");
+		assert_eq!(csv_field("a\rb"), "\"a\rb\"");
+	}
+}
@@ -0,0 +1,331 @@
+use std::io::BufRead;
+use std::ops::Deref;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::sync::{Arc, RwLock, RwLockReadGuard};
+use std::time::SystemTime;
+
+use log::{info, warn};
+
+use crate::FindPattern;
+
+/// The leading uid bits which index the patterns, the first two characters.
+const INDEX_BITS: u32 = 12;
+
+/// The patterns of a running search, patterns can be added and removed while it runs.
+#[derive(Debug)]
+pub struct PatternSet {
+	list: RwLock<Patterns>,
+	/// Set if patterns are read from stdin, satisfied patterns are acknowledged then.
+	stdin: AtomicBool,
+	/// Set until stdin is closed, the search continues when all patterns are satisfied.
+	stdin_open: AtomicBool,
+}
+
+impl PatternSet {
+	pub fn new(patterns: Vec<FindPattern>) -> Self {
+		Self {
+			list: RwLock::new(Patterns::new(patterns.into_iter().map(Arc::new).collect())),
+			stdin: AtomicBool::new(false),
+			stdin_open: AtomicBool::new(false),
+		}
+	}
+
+	/// The current patterns, hold the guard only briefly, it blocks changes.
+	pub fn read(&self) -> RwLockReadGuard<'_, Patterns> {
+		self.list.read().unwrap()
+	}
+
+	/// Adds a pattern, returns `false` if the same pattern is already searched.
+	pub fn add(&self, pattern: FindPattern) -> bool {
+		let mut list = self.list.write().unwrap();
+		if list.iter().any(|p| p.input == pattern.input) {
+			return false;
+		}
+		list.update(|l| l.push(Arc::new(pattern)));
+		true
+	}
+
+	/// Call this before the first `add_from_stdin`.
+	pub fn start_stdin(&self) {
+		self.stdin.store(true, Ordering::Relaxed);
+		self.stdin_open.store(true, Ordering::Relaxed);
+	}
+
+	pub fn reads_stdin(&self) -> bool {
+		self.stdin.load(Ordering::Relaxed)
+	}
+
+	/// More patterns may be added while this is `true`.
+	pub fn stdin_open(&self) -> bool {
+		self.stdin_open.load(Ordering::Relaxed)
+	}
+
+	/// Adds the patterns which were read from stdin so far.
+	///
+	/// `attempts` is the current number of generated keys.
+	pub fn add_from_stdin(&self, lines: &Receiver<String>, attempts: u64) {
+		loop {
+			let line = match lines.try_recv() {
+				Ok(line) => line,
+				Err(TryRecvError::Empty) => return,
+				Err(TryRecvError::Disconnected) => {
+					if self.stdin_open.swap(false, Ordering::Relaxed) {
+						info!("Stdin closed, stopping when all patterns are satisfied");
+					}
+					return;
+				}
+			};
+			let input = line.trim();
+			if input.is_empty() || input.starts_with('#') {
+				continue;
+			}
+			match FindPattern::parse(input) {
+				Ok(mut pattern) => {
+					pattern.since = attempts;
+					if self.add(pattern) {
+						info!("Added pattern {}", input);
+					} else {
+						warn!("Already searching for {}", input);
+					}
+				}
+				Err(e) => warn!("Skipping pattern {}: {}", input, e),
+			}
+		}
+	}
+
+	/// Removes all patterns which have enough matches.
+	pub fn remove_retired(&self) {
+		let retired = |p: &Arc<FindPattern>| p.retired.load(Ordering::Relaxed);
+		// Avoid blocking the workers with the write lock if there is nothing to do
+		if self.read().iter().any(retired) {
+			self.list
+				.write()
+				.unwrap()
+				.update(|l| l.retain(|p| !retired(p)));
+		}
+	}
+
+	/// Removes a pattern and returns it.
+	pub fn remove(&self, input: &str) -> Option<Arc<FindPattern>> {
+		let mut list = self.list.write().unwrap();
+		let pos = list.iter().position(|p| p.input == input)?;
+		let mut removed = None;
+		list.update(|l| removed = Some(l.remove(pos)));
+		removed
+	}
+}
+
+/// The list of patterns with an index by the leading bits of the uid, so checking a uid
+/// against hundreds of patterns only compares the few which can match.
+#[derive(Debug, Default)]
+pub struct Patterns {
+	list: Vec<Arc<FindPattern>>,
+	/// The positions of the patterns which can match uids with these leading bits, in the
+	/// order of `list`.
+	buckets: Vec<Vec<usize>>,
+	/// The positions of all patterns.
+	all: Vec<usize>,
+}
+
+impl Patterns {
+	fn new(list: Vec<Arc<FindPattern>>) -> Self {
+		let mut patterns = Self {
+			list,
+			..Self::default()
+		};
+		patterns.index();
+		patterns
+	}
+
+	fn update(&mut self, f: impl FnOnce(&mut Vec<Arc<FindPattern>>)) {
+		f(&mut self.list);
+		self.index();
+	}
+
+	fn index(&mut self) {
+		let shift = 64 - INDEX_BITS;
+		let all_bits = (1 << INDEX_BITS) - 1;
+		self.buckets = vec![Vec::new(); 1 << INDEX_BITS];
+		for (i, p) in self.list.iter().enumerate() {
+			let fixed = ((p.text & p.mask) >> shift) as usize;
+			let wildcards = !(p.mask >> shift) as usize & all_bits;
+			// Add the pattern to every bucket its wildcards allow
+			let mut bits = wildcards;
+			loop {
+				self.buckets[fixed | bits].push(i);
+				if bits == 0 {
+					break;
+				}
+				bits = (bits - 1) & wildcards;
+			}
+		}
+		self.all = (0..self.list.len()).collect();
+	}
+
+	/// The positions of the patterns which can match a uid with these leading bits.
+	pub fn candidates(&self, uid_bits: u64) -> &[usize] {
+		&self.buckets[(uid_bits >> (64 - INDEX_BITS)) as usize]
+	}
+
+	/// The positions of all patterns, for checks which look at the patterns a uid misses.
+	pub fn all(&self) -> &[usize] {
+		&self.all
+	}
+}
+
+impl Deref for Patterns {
+	type Target = Vec<Arc<FindPattern>>;
+
+	fn deref(&self) -> &Self::Target {
+		&self.list
+	}
+}
+
+/// A file with one pattern per line, lines starting with `#` are ignored.
+///
+/// The patterns of a running search follow the file when it is reloaded.
+#[derive(Debug)]
+pub struct PatternFile {
+	path: PathBuf,
+	modified: Option<SystemTime>,
+	/// The patterns which were added from the file.
+	inputs: Vec<String>,
+}
+
+impl PatternFile {
+	/// Reads the file and returns its patterns.
+	pub fn open(path: &Path) -> Result<(Self, Vec<FindPattern>), String> {
+		let mut file = Self {
+			path: path.to_path_buf(),
+			modified: modified(path),
+			inputs: Vec::new(),
+		};
+		let patterns = file
+			.read()?
+			.iter()
+			.map(|p| FindPattern::parse(p))
+			.collect::<Result<Vec<_>, _>>()?;
+		file.inputs = patterns.iter().map(|p| p.input.clone()).collect();
+		Ok((file, patterns))
+	}
+
+	/// Reloads the file if it was modified or `force` is set.
+	///
+	/// Patterns which were removed from the file are removed from `set` and new patterns are
+	/// added. `attempts` is the current number of generated keys.
+	pub fn reload(&mut self, set: &PatternSet, attempts: u64, force: bool) {
+		let modified = modified(&self.path);
+		if !force && modified == self.modified {
+			return;
+		}
+		self.modified = modified;
+		let inputs = match self.read() {
+			Ok(inputs) => inputs,
+			Err(e) => {
+				warn!("{}", e);
+				return;
+			}
+		};
+		self.inputs.retain(|input| {
+			if inputs.contains(input) {
+				return true;
+			}
+			if set.remove(input).is_some() {
+				info!("Removed pattern {}", input);
+			}
+			false
+		});
+		for input in inputs {
+			if self.inputs.contains(&input) {
+				continue;
+			}
+			match FindPattern::parse(&input) {
+				Ok(mut pattern) => {
+					pattern.since = attempts;
+					if set.add(pattern) {
+						info!("Added pattern {}", input);
+						self.inputs.push(input);
+					}
+				}
+				Err(e) => warn!("Skipping pattern from {}: {}", self.path.display(), e),
+			}
+		}
+	}
+
+	fn read(&self) -> Result<Vec<String>, String> {
+		let content = std::fs::read_to_string(&self.path)
+			.map_err(|e| format!("Failed to read {}: {}", self.path.display(), e))?;
+		Ok(content
+			.lines()
+			.map(str::trim)
+			.filter(|l| !l.is_empty() && !l.starts_with('#'))
+			.map(str::to_string)
+			.collect())
+	}
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Reads patterns from stdin in the background, one per line.
+///
+/// The reader blocks until stdin is closed, so it is not bound to the search.
+pub fn read_stdin() -> Receiver<String> {
+	let (sender, receiver) = mpsc::channel();
+	std::thread::spawn(move || {
+		for line in std::io::stdin().lock().lines() {
+			match line {
+				Ok(line) => {
+					if sender.send(line).is_err() {
+						break;
+					}
+				}
+				Err(e) => {
+					warn!("Failed to read patterns from stdin: {}", e);
+					break;
+				}
+			}
+		}
+	});
+	receiver
+}
+
+#[cfg(test)]
+mod tests {
+	use rand_core::{OsRng, RngCore};
+
+	use super::*;
+	use crate::UidBits;
+
+	#[test]
+	fn candidates_contain_every_matching_pattern() {
+		let inputs = [
+			"Abc", "?bc", "A?c", "_", "Ab", "*w", "?a*08", "aB?D", "/+", "A",
+		];
+		let patterns = Patterns::new(
+			inputs
+				.iter()
+				.map(|p| Arc::new(FindPattern::parse(p).unwrap()))
+				.collect(),
+		);
+		for (i, p) in patterns.iter().enumerate() {
+			for _ in 0..100 {
+				// A random uid which matches the pattern
+				let uid = UidBits {
+					head: p.text | (OsRng.next_u64() & !p.mask),
+					tail: p.tail_text | (OsRng.next_u64() & !p.tail_mask),
+				};
+				assert!(p.matches(uid));
+				assert!(
+					patterns.candidates(uid.head).contains(&i),
+					"{} is not a candidate for {:x}",
+					p.input,
+					uid.head
+				);
+			}
+		}
+	}
+}
@@ -0,0 +1,316 @@
+//! Runs a list of jobs from a TOML file, each one finds an identity, levels it and exports it.
+//!
+//! ```toml
+//! [[job]]
+//! name = "alice"
+//! patterns = ["Alice"]
+//! level = 30
+//! export = "ini"
+//! path = "alice.ini"
+//! ```
+//!
+//! Instead of `patterns`, a job can start from an existing `identity`. After every step, the
+//! state of all jobs is written to a checkpoint next to the file, so running the file again
+//! continues where it stopped. The checkpoint contains the private keys.
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use clap::ValueEnum;
+use log::info;
+use serde::{Deserialize, Serialize};
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+use zeroize::Zeroizing;
+
+use crate::control::is_interrupted;
+use crate::convert::ExportFormat;
+use crate::output::{self, Output, OutputFormat};
+use crate::patterns::PatternSet;
+use crate::progress::Progress;
+use crate::stats::ThreadCounters;
+use crate::{
+	batch_sizer, find_pattern_parallel, identity_from_str, improve_sec_level, FindPattern, Opts,
+	Outcome, Result, RunData,
+};
+
+#[derive(clap::Args, Debug)]
+pub struct RunArgs {
+	/// The TOML file with the jobs.
+	file: PathBuf,
+
+	#[clap(long)]
+	/// Where the progress is stored, defaults to the file with '.state.json' appended.
+	checkpoint: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobFile {
+	job: Vec<JobSpec>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JobSpec {
+	/// Identifies the job in the checkpoint, defaults to its number.
+	name: Option<String>,
+	#[serde(default)]
+	patterns: Vec<String>,
+	/// An identity in any format '--identity' accepts, instead of searching one.
+	identity: Option<String>,
+	level: Option<u8>,
+	/// The name of an '--export-format'.
+	export: Option<String>,
+	path: Option<PathBuf>,
+	/// The nickname of the ini export, defaults to the name of the job.
+	nickname: Option<String>,
+}
+
+/// What is done of a job.
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct JobState {
+	key: Option<String>,
+	counter: u64,
+	level: u8,
+	exported: bool,
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+struct Checkpoint {
+	jobs: BTreeMap<String, JobState>,
+}
+
+impl Checkpoint {
+	fn load(path: &Path) -> std::result::Result<Self, String> {
+		match std::fs::read_to_string(path) {
+			Ok(content) => serde_json::from_str(&content)
+				.map_err(|e| format!("Invalid checkpoint {}: {}", path.display(), e)),
+			Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+			Err(e) => Err(format!(
+				"Failed to read checkpoint {}: {}",
+				path.display(),
+				e
+			)),
+		}
+	}
+
+	fn save(&self, path: &Path) -> std::result::Result<(), String> {
+		let content = Zeroizing::new(
+			serde_json::to_string_pretty(self).expect("Checkpoint is always serializable"),
+		);
+		output::write_private(path, content.as_bytes())
+			.map_err(|e| format!("Failed to write checkpoint {}: {}", path.display(), e))
+	}
+}
+
+impl Drop for JobState {
+	fn drop(&mut self) {
+		if let Some(key) = &mut self.key {
+			zeroize::Zeroize::zeroize(key);
+		}
+	}
+}
+
+/// One line of the report at the end.
+struct Report {
+	name: String,
+	uid: Option<String>,
+	level: u8,
+	path: Option<PathBuf>,
+	elapsed: Duration,
+	done: bool,
+}
+
+pub fn tool_run(args: RunArgs, mut opts: Opts) -> Result {
+	let content = std::fs::read_to_string(&args.file)
+		.map_err(|e| format!("Failed to read {}: {}", args.file.display(), e))?;
+	let file = toml::from_str::<JobFile>(&content)
+		.map_err(|e| format!("Invalid job file {}: {}", args.file.display(), e))?;
+	let checkpoint_path = args.checkpoint.unwrap_or_else(|| {
+		let mut path = args.file.clone().into_os_string();
+		path.push(".state.json");
+		PathBuf::from(path)
+	});
+	let mut checkpoint = Checkpoint::load(&checkpoint_path)?;
+
+	let mut names = Vec::new();
+	for (i, job) in file.job.iter().enumerate() {
+		let name = job.name.clone().unwrap_or_else(|| format!("job {}", i + 1));
+		if names.contains(&name) {
+			return Err(format!("The job name '{}' is used twice", name));
+		}
+		if job.patterns.is_empty() == job.identity.is_none() {
+			return Err(format!(
+				"Job '{}' needs either patterns or an identity",
+				name
+			));
+		}
+		if job.export.is_some() != job.path.is_some() {
+			return Err(format!(
+				"Job '{}' needs both 'export' and 'path' to export",
+				name
+			));
+		}
+		if let Some(export) = &job.export {
+			ExportFormat::from_str(export, true)
+				.map_err(|_| format!("Job '{}' has an unknown export '{}'", name, export))?;
+		}
+		names.push(name);
+	}
+
+	let mut reports = Vec::new();
+	for (job, name) in file.job.iter().zip(names) {
+		if is_interrupted() {
+			break;
+		}
+		let start = Instant::now();
+		info!("Starting job '{}'", name);
+		let state = checkpoint.jobs.entry(name.clone()).or_default();
+		let mut report = Report {
+			name: name.clone(),
+			uid: None,
+			level: 0,
+			path: job.path.clone(),
+			elapsed: Duration::ZERO,
+			done: false,
+		};
+		if state.key.is_none() {
+			let (key, counter) = match &job.identity {
+				Some(identity) => {
+					identity_from_str(identity).map_err(|e| format!("Job '{}': {}", name, e))?
+				}
+				None => match find(&opts, &job.patterns)? {
+					Some(key) => (key, None),
+					None => {
+						report.elapsed = start.elapsed();
+						reports.push(report);
+						break;
+					}
+				},
+			};
+			state.key = Some(key);
+			state.counter = counter.unwrap_or(0);
+			checkpoint.save(&checkpoint_path)?;
+		}
+		let state = checkpoint.jobs.get_mut(&name).expect("State was added");
+		let key = Zeroizing::new(state.key.clone().expect("Key was set"));
+		let tp_priv = EccKeyPrivP256::import_str(&key).map_err(|_| "Failed to read identity")?;
+		let omega = tp_priv.to_pub().to_ts();
+		report.uid = Some(tp_priv.to_pub().get_uid().to_string());
+		state.level = get_hash_cash_level(&omega, state.counter);
+
+		if let Some(want) = job.level.filter(|want| state.level < *want) {
+			opts.level = Some(want.into());
+			let best = improve_sec_level(&opts, &key, Some(state.counter))?;
+			if best.level > state.level {
+				state.level = best.level;
+				state.counter = best.offset;
+			}
+			checkpoint.save(&checkpoint_path)?;
+			let state = &checkpoint.jobs[&name];
+			if state.level < want {
+				report.level = state.level;
+				report.elapsed = start.elapsed();
+				reports.push(report);
+				break;
+			}
+		}
+
+		let state = checkpoint.jobs.get_mut(&name).expect("State was added");
+		report.level = state.level;
+		if let (Some(export), Some(path), false) = (&job.export, &job.path, state.exported) {
+			let format = ExportFormat::from_str(export, true).expect("Export was checked");
+			let nickname = job.nickname.as_deref().unwrap_or(&name);
+			let content = format.format(&tp_priv, Some(state.counter), nickname)?;
+			output::write_private(path, content.as_bytes())
+				.map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+			info!("Exported job '{}' to {}", name, path.display());
+			state.exported = true;
+			checkpoint.save(&checkpoint_path)?;
+		}
+		report.done = true;
+		report.elapsed = start.elapsed();
+		reports.push(report);
+	}
+
+	let total = file.job.len();
+	let done = reports.iter().filter(|r| r.done).count();
+	for report in &reports {
+		let mut line = format!(
+			"JOB: {} UID: {} LEVEL: {}",
+			report.name,
+			report.uid.as_deref().unwrap_or("-"),
+			report.level
+		);
+		if let Some(path) = &report.path {
+			line.push_str(&format!(" FILE: {}", path.display()));
+		}
+		if !report.done {
+			line.push_str(" UNFINISHED");
+		}
+		println!("{}", line);
+		info!(
+			"Job '{}' took {}",
+			report.name,
+			humantime::format_duration(Duration::from_secs(report.elapsed.as_secs()))
+		);
+	}
+	info!("Finished {} of {} jobs", done, total);
+	if done == total {
+		Ok(Outcome::Success)
+	} else if is_interrupted() {
+		info!("Interrupted, run the file again to continue");
+		Ok(Outcome::Success)
+	} else {
+		Ok(Outcome::NotFound)
+	}
+}
+
+/// Searches one identity for the patterns, `None` if the search was interrupted.
+fn find(opts: &Opts, patterns: &[String]) -> std::result::Result<Option<String>, String> {
+	let patterns = patterns
+		.iter()
+		.map(|p| FindPattern::parse(p))
+		.collect::<std::result::Result<Vec<_>, _>>()?;
+	let mut output = Output::new(OutputFormat::Text, None);
+	let results = output.collect();
+	let data = RunData {
+		patterns: PatternSet::new(patterns),
+		max_matches: Some(1),
+		matches: AtomicU64::new(0),
+		max_attempts: opts.max_attempts,
+		batch: batch_sizer(opts),
+		required_per_pattern: None,
+		near_misses: false,
+		predicates: Vec::new(),
+		top: None,
+		output,
+		attempts: AtomicU64::new(0),
+		thread_attempts: ThreadCounters::new(),
+		progress: Progress::new(opts.quiet),
+		stop: AtomicBool::new(false),
+	};
+	let start = Instant::now();
+	let done = AtomicBool::new(false);
+	std::thread::scope(|s| {
+		s.spawn(|| {
+			while !done.load(Ordering::Relaxed) {
+				data.progress.update(
+					data.attempts.load(Ordering::Relaxed),
+					start.elapsed(),
+					&data.patterns.read(),
+					None,
+				);
+				std::thread::sleep(Duration::from_millis(250));
+			}
+		});
+		find_pattern_parallel::<false>(&data);
+		done.store(true, Ordering::Relaxed);
+	});
+	data.progress.finish();
+	let found = results.lock().unwrap().first().map(|f| f.key.clone());
+	Ok(found)
+}
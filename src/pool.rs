@@ -0,0 +1,189 @@
+use log::{debug, warn};
+
+/// A list of cpu cores like `0-3,8,10-11`.
+#[derive(Clone, Debug)]
+pub struct CoreList(pub Vec<usize>);
+
+impl CoreList {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let mut cores = Vec::new();
+		for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+			let parse = |n: &str| {
+				n.trim()
+					.parse::<usize>()
+					.map_err(|_| format!("Invalid core '{}' in core list", n))
+			};
+			match part.split_once('-') {
+				Some((start, end)) => {
+					let (start, end) = (parse(start)?, parse(end)?);
+					if start > end {
+						return Err(format!("Invalid core range '{}'", part));
+					}
+					cores.extend(start..=end);
+				}
+				None => cores.push(parse(part)?),
+			}
+		}
+		if cores.is_empty() {
+			return Err("The core list is empty".to_string());
+		}
+		Ok(Self(cores))
+	}
+}
+
+/// Sets up the global thread pool.
+///
+/// If `pin` is set, every worker thread is pinned to one core. `Some(None)` uses all cores,
+/// otherwise only the listed ones.
+pub fn build_global(threads: Option<usize>, pin: Option<Option<CoreList>>) -> Result<(), String> {
+	let cores = match pin {
+		None => None,
+		Some(list) => Some(pin_cores(list)?),
+	};
+	if threads.is_none() && cores.is_none() {
+		return Ok(());
+	}
+
+	let mut builder = rayon::ThreadPoolBuilder::new();
+	if let Some(t) = threads.or_else(|| cores.as_ref().map(Vec::len)) {
+		builder = builder.num_threads(t);
+	}
+	if let Some(cores) = cores {
+		builder = builder.start_handler(move |i| {
+			let core = cores[i % cores.len()];
+			if core_affinity::set_for_current(core) {
+				debug!("Pinned worker {} to core {}", i, core.id);
+			} else {
+				warn!("Failed to pin worker {} to core {}", i, core.id);
+			}
+		});
+	}
+	builder
+		.build_global()
+		.map_err(|e| format!("Failed to create thread pool: {}", e))
+}
+
+/// Returns the cores which should be used for pinning.
+fn pin_cores(list: Option<CoreList>) -> Result<Vec<core_affinity::CoreId>, String> {
+	let available =
+		core_affinity::get_core_ids().ok_or("Pinning threads is not supported on this system")?;
+	let list = match list {
+		Some(list) => list,
+		None => return Ok(available),
+	};
+	list.0
+		.into_iter()
+		.map(|id| {
+			available
+				.iter()
+				.find(|c| c.id == id)
+				.copied()
+				.ok_or_else(|| format!("Core {} does not exist", id))
+		})
+		.collect()
+}
+
+/// Creates one thread pool per NUMA node, the workers are pinned to the cpus of their node.
+///
+/// Memory is allocated local to the node which touches it first, so pinned workers keep their
+/// buffers on their own node. Returns an empty list if the system has only a single node.
+pub fn numa_pools() -> Result<Vec<rayon::ThreadPool>, String> {
+	let nodes = numa_nodes()?;
+	if nodes.len() < 2 {
+		warn!("Found at most one NUMA node, using a single thread pool");
+		return Ok(Vec::new());
+	}
+	nodes
+		.into_iter()
+		.enumerate()
+		.map(|(node, cpus)| {
+			debug!("NUMA node {} has cpus {:?}", node, cpus.0);
+			let cpus = cpus.0;
+			rayon::ThreadPoolBuilder::new()
+				.num_threads(cpus.len())
+				.thread_name(move |i| format!("node{}-worker{}", node, i))
+				.start_handler(move |i| {
+					core_affinity::set_for_current(core_affinity::CoreId { id: cpus[i] });
+				})
+				.build()
+				.map_err(|e| format!("Failed to create thread pool for NUMA node {}: {}", node, e))
+		})
+		.collect()
+}
+
+/// Returns the cpus of every NUMA node.
+#[cfg(target_os = "linux")]
+pub fn numa_nodes() -> Result<Vec<CoreList>, String> {
+	let dir = match std::fs::read_dir("/sys/devices/system/node") {
+		Ok(dir) => dir,
+		Err(_) => return Ok(Vec::new()),
+	};
+	let mut nodes = Vec::new();
+	for entry in dir.flatten() {
+		let name = entry.file_name();
+		let id = match name.to_str().and_then(|n| n.strip_prefix("node")) {
+			Some(id) => match id.parse::<usize>() {
+				Ok(id) => id,
+				Err(_) => continue,
+			},
+			None => continue,
+		};
+		let cpulist = std::fs::read_to_string(entry.path().join("cpulist"))
+			.map_err(|e| format!("Failed to read cpus of NUMA node {}: {}", id, e))?;
+		// Nodes without cpus only provide memory
+		if let Ok(cpus) = CoreList::parse(&cpulist) {
+			nodes.push((id, cpus));
+		}
+	}
+	nodes.sort_by_key(|(id, _)| *id);
+	Ok(nodes.into_iter().map(|(_, cpus)| cpus).collect())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn numa_nodes() -> Result<Vec<CoreList>, String> {
+	Ok(Vec::new())
+}
+
+/// Returns the performance cores of a hybrid cpu, or `None` if all cores are of the same kind.
+///
+/// Intel hybrid cpus list their performance cores in `/sys/devices/cpu_core/cpus`. Otherwise,
+/// e.g. on ARM big.LITTLE systems, the cores with the lowest maximum frequency are treated as
+/// efficiency cores.
+#[cfg(target_os = "linux")]
+pub fn perf_cores() -> Option<CoreList> {
+	if let Ok(cpus) = std::fs::read_to_string("/sys/devices/cpu_core/cpus") {
+		if let Ok(cores) = CoreList::parse(&cpus) {
+			return Some(cores);
+		}
+	}
+
+	let mut freqs = Vec::new();
+	for core in core_affinity::get_core_ids()? {
+		let path = format!(
+			"/sys/devices/system/cpu/cpu{}/cpufreq/cpuinfo_max_freq",
+			core.id
+		);
+		let freq = std::fs::read_to_string(path)
+			.ok()?
+			.trim()
+			.parse::<u64>()
+			.ok()?;
+		freqs.push((core.id, freq));
+	}
+	let min = freqs.iter().map(|(_, f)| *f).min()?;
+	let perf = freqs
+		.into_iter()
+		.filter(|(_, f)| *f > min)
+		.map(|(id, _)| id)
+		.collect::<Vec<_>>();
+	if perf.is_empty() {
+		None
+	} else {
+		Some(CoreList(perf))
+	}
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn perf_cores() -> Option<CoreList> {
+	None
+}
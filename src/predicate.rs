@@ -0,0 +1,77 @@
+//! User provided match predicates, loaded from dynamic libraries.
+//!
+//! A predicate library exports one C function, which gets the uid and the 20 bytes of its
+//! SHA-1 hash for every identity that matches a pattern. It returns non-zero to accept it:
+//!
+//! ```rust,ignore
+//! #[no_mangle]
+//! pub extern "C" fn mahts_predicate(uid: *const u8, uid_len: usize, hash: *const u8) -> i32 {
+//!     let hash = unsafe { std::slice::from_raw_parts(hash, 20) };
+//!     (hash.iter().map(|b| *b as u32).sum::<u32>() % 7 == 0) as i32
+//! }
+//! ```
+//!
+//! The function is called from all worker threads at the same time.
+
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+
+use libloading::Library;
+use sha1::{Digest, Sha1};
+use tsproto_types::crypto::EccKeyPubP256;
+
+const SYMBOL: &[u8] = b"mahts_predicate";
+
+type PredicateFn = unsafe extern "C" fn(uid: *const u8, uid_len: usize, hash: *const u8) -> c_int;
+
+#[derive(Debug)]
+pub struct Predicate {
+	path: PathBuf,
+	function: PredicateFn,
+	// Keeps the function loaded
+	_library: Library,
+}
+
+impl Predicate {
+	pub fn load(path: &Path) -> Result<Self, String> {
+		// Loading runs the initialization code of the library, the user asked for that
+		let library = unsafe { Library::new(path) }
+			.map_err(|e| format!("Failed to load predicate {}: {}", path.display(), e))?;
+		let function = unsafe { library.get::<PredicateFn>(SYMBOL) }
+			.map(|f| *f)
+			.map_err(|e| {
+				format!(
+					"{} does not export 'mahts_predicate': {}",
+					path.display(),
+					e
+				)
+			})?;
+		Ok(Self {
+			path: path.to_path_buf(),
+			function,
+			_library: library,
+		})
+	}
+
+	/// The file name, used as the pattern name when searching without patterns.
+	pub fn name(&self) -> String {
+		self.path
+			.file_stem()
+			.map(|s| s.to_string_lossy().into_owned())
+			.unwrap_or_else(|| self.path.display().to_string())
+	}
+
+	fn accepts(&self, uid: &str, hash: &[u8]) -> bool {
+		unsafe { (self.function)(uid.as_ptr(), uid.len(), hash.as_ptr()) != 0 }
+	}
+}
+
+/// Checks if all predicates accept the key.
+pub fn all_accept(predicates: &[Predicate], pub_key: &EccKeyPubP256) -> bool {
+	if predicates.is_empty() {
+		return true;
+	}
+	let hash = Sha1::digest(pub_key.to_ts().as_bytes());
+	let uid = base64::encode(hash);
+	predicates.iter().all(|p| p.accepts(&uid, &hash))
+}
@@ -0,0 +1,97 @@
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Priority {
+	/// Only uses cpu time which nothing else wants.
+	Idle,
+	/// Lets other programs run first.
+	Low,
+	/// The normal priority of the process.
+	Normal,
+}
+
+/// Sets the priority of the calling thread and all threads it creates afterwards.
+///
+/// On Linux, `idle` uses the `SCHED_IDLE` scheduling policy and the idle io class, other unix
+/// systems get the highest nice value. On Windows, this sets the priority class of the process.
+pub fn set(priority: Priority) -> Result<(), String> {
+	match priority {
+		Priority::Normal => Ok(()),
+		Priority::Low => set_nice(10),
+		Priority::Idle => set_idle(),
+	}
+}
+
+#[cfg(target_os = "linux")]
+fn set_idle() -> Result<(), String> {
+	// IOPRIO_WHO_PROCESS and IOPRIO_CLASS_IDLE from linux/ioprio.h
+	const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+	const IOPRIO_CLASS_IDLE: libc::c_int = 3;
+	const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+	// Scheduling attributes are per thread on Linux and inherited by new threads
+	let param: libc::sched_param = unsafe { std::mem::zeroed() };
+	if unsafe { libc::sched_setscheduler(0, libc::SCHED_IDLE, &param) } != 0 {
+		return Err(format!(
+			"Failed to set idle scheduling: {}",
+			std::io::Error::last_os_error()
+		));
+	}
+	// The search does nearly no io, so failing here is not important
+	unsafe {
+		libc::syscall(
+			libc::SYS_ioprio_set,
+			IOPRIO_WHO_PROCESS,
+			0,
+			IOPRIO_CLASS_IDLE << IOPRIO_CLASS_SHIFT,
+		);
+	}
+	Ok(())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn set_idle() -> Result<(), String> {
+	set_nice(19)
+}
+
+#[cfg(unix)]
+fn set_nice(nice: libc::c_int) -> Result<(), String> {
+	if unsafe { libc::setpriority(libc::PRIO_PROCESS, 0, nice) } != 0 {
+		return Err(format!(
+			"Failed to lower the priority: {}",
+			std::io::Error::last_os_error()
+		));
+	}
+	Ok(())
+}
+
+#[cfg(windows)]
+fn set_idle() -> Result<(), String> {
+	set_priority_class(windows_sys::Win32::System::Threading::IDLE_PRIORITY_CLASS)
+}
+
+#[cfg(windows)]
+fn set_nice(_: i32) -> Result<(), String> {
+	set_priority_class(windows_sys::Win32::System::Threading::BELOW_NORMAL_PRIORITY_CLASS)
+}
+
+#[cfg(windows)]
+fn set_priority_class(class: u32) -> Result<(), String> {
+	use windows_sys::Win32::System::Threading::{GetCurrentProcess, SetPriorityClass};
+
+	if unsafe { SetPriorityClass(GetCurrentProcess(), class) } == 0 {
+		return Err(format!(
+			"Failed to lower the priority: {}",
+			std::io::Error::last_os_error()
+		));
+	}
+	Ok(())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_idle() -> Result<(), String> {
+	Err("Changing the priority is not supported on this system".to_string())
+}
+
+#[cfg(not(any(unix, windows)))]
+fn set_nice(_: i32) -> Result<(), String> {
+	set_idle()
+}
@@ -0,0 +1,216 @@
+use std::fs::File;
+use std::io::Write;
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use indicatif::{ProgressBar, ProgressDrawTarget, ProgressStyle};
+use serde_json::{json, Value};
+
+use crate::{control, FindPattern};
+
+/// The shortest time between two progress events.
+const EVENT_INTERVAL: Duration = Duration::from_secs(1);
+
+static EVENTS: Mutex<Option<Events>> = Mutex::new(None);
+
+/// Machine readable progress with '--progress-fd', one json object per line.
+#[derive(Debug)]
+#[cfg_attr(not(unix), allow(dead_code))]
+struct Events {
+	file: File,
+	last: Option<Instant>,
+}
+
+/// Writes progress events to this open file descriptor, e.g. a pipe of a GUI.
+#[cfg(unix)]
+pub fn open_events(fd: i32) -> Result<(), String> {
+	use std::os::unix::io::FromRawFd;
+
+	if fd <= 2 {
+		return Err("'--progress-fd' must not be stdin, stdout or stderr".to_string());
+	}
+	if unsafe { libc::fcntl(fd, libc::F_GETFD) } == -1 {
+		return Err(format!("File descriptor {} is not open", fd));
+	}
+	// The descriptor was passed to this process for this use only
+	let file = unsafe { File::from_raw_fd(fd) };
+	*EVENTS.lock().unwrap() = Some(Events { file, last: None });
+	Ok(())
+}
+
+/// Writes an event, progress events at most once per `EVENT_INTERVAL`.
+fn emit(event: Value, throttle: bool) {
+	let mut events = EVENTS.lock().unwrap();
+	let events = match events.as_mut() {
+		Some(events) => events,
+		None => return,
+	};
+	if throttle && events.last.map_or(false, |l| l.elapsed() < EVENT_INTERVAL) {
+		return;
+	}
+	events.last = Some(Instant::now());
+	// A closed pipe must not stop the search
+	let _ = writeln!(events.file, "{}", event).and_then(|_| events.file.flush());
+}
+
+/// A status line on stderr which is updated in place while searching.
+#[derive(Debug)]
+pub struct Progress {
+	bar: ProgressBar,
+}
+
+impl Progress {
+	pub fn new(hidden: bool) -> Self {
+		let bar = if hidden {
+			ProgressBar::hidden()
+		} else {
+			let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr());
+			bar.set_style(
+				ProgressStyle::with_template("{spinner} [{elapsed_precise}] {msg}")
+					.expect("Progress template is valid"),
+			);
+			bar.enable_steady_tick(Duration::from_millis(200));
+			bar
+		};
+		Self { bar }
+	}
+
+	/// Hides the progress line while `f` prints something.
+	pub fn suspend<R, F: FnOnce() -> R>(&self, f: F) -> R {
+		self.bar.suspend(f)
+	}
+
+	/// `required` is the number of matches each pattern needs, if there is a limit.
+	pub fn update(
+		&self,
+		attempts: u64,
+		elapsed: Duration,
+		patterns: &[Arc<FindPattern>],
+		required: Option<u64>,
+	) {
+		let rate = attempts as f64 / elapsed.as_secs_f64().max(0.001);
+		let mut msg = format!(
+			"{} keys, {} keys/s",
+			human_count(attempts as f64),
+			human_count(rate)
+		);
+		for p in patterns {
+			msg.push_str(&format!(", {}", p.input));
+			if let Some(required) = required {
+				msg.push_str(&format!(
+					" {}/{}",
+					p.matches.load(Ordering::Relaxed),
+					required
+				));
+			}
+			if !p.retired.load(Ordering::Relaxed) {
+				// Every key is independent, so the remaining time does not decrease while
+				// searching.
+				let eta = 2f64.powi(p.bits() as i32) / rate;
+				msg.push_str(&format!(" ~{}", short_duration(eta)));
+			}
+		}
+		if control::is_paused() {
+			msg.push_str(" (paused)");
+		}
+		self.bar.set_message(msg);
+
+		let eta = patterns
+			.iter()
+			.filter(|p| !p.retired.load(Ordering::Relaxed))
+			.map(|p| 2f64.powi(p.bits() as i32) / rate)
+			.reduce(f64::min);
+		emit(
+			json!({
+				"event": "progress",
+				"search": "find",
+				"elapsed_secs": elapsed.as_secs(),
+				"attempts": attempts,
+				"rate": rate,
+				"eta_secs": eta.filter(|e| e.is_finite()),
+				"matches": patterns.iter().map(|p| p.matches.load(Ordering::Relaxed)).sum::<u64>(),
+				"paused": control::is_paused(),
+				"patterns": patterns
+					.iter()
+					.map(|p| json!({
+						"pattern": p.input,
+						"matches": p.matches.load(Ordering::Relaxed),
+					}))
+					.collect::<Vec<_>>(),
+			}),
+			true,
+		);
+	}
+
+	/// Shows the progress of the level search.
+	pub fn update_level(&self, offsets: u64, elapsed: Duration, best: u8, want: u8) {
+		let rate = offsets as f64 / elapsed.as_secs_f64().max(0.001);
+		let mut msg = format!(
+			"{} offsets, {:.2} MH/s, best level {}/{}",
+			human_count(offsets as f64),
+			rate / 1e6,
+			best,
+			want
+		);
+		if control::is_paused() {
+			msg.push_str(" (paused)");
+		}
+		self.bar.set_message(msg);
+
+		// Every offset reaches the level with a chance of 2^-level, independent of the others
+		let eta = (best < want).then(|| 2f64.powi(want.into()) / rate);
+		emit(
+			json!({
+				"event": "progress",
+				"search": "level",
+				"elapsed_secs": elapsed.as_secs(),
+				"attempts": offsets,
+				"rate": rate,
+				"eta_secs": eta.filter(|e| e.is_finite()),
+				"best_level": best,
+				"want_level": want,
+				"paused": control::is_paused(),
+			}),
+			true,
+		);
+	}
+
+	pub fn finish(&self) {
+		self.bar.finish_and_clear();
+		emit(json!({ "event": "finished" }), false);
+	}
+}
+
+/// Formats a number with a k/M/G/T suffix.
+pub fn human_count(n: f64) -> String {
+	const UNITS: &[&str] = &["", "k", "M", "G", "T"];
+	let mut n = n;
+	let mut unit = 0;
+	while n >= 1000.0 && unit < UNITS.len() - 1 {
+		n /= 1000.0;
+		unit += 1;
+	}
+	if unit == 0 {
+		format!("{:.0}", n)
+	} else {
+		format!("{:.2}{}", n, UNITS[unit])
+	}
+}
+
+/// Formats seconds in the largest fitting unit.
+pub fn short_duration(secs: f64) -> String {
+	if !secs.is_finite() {
+		return "inf".to_string();
+	}
+	let mut t = secs;
+	let mut u = "s";
+	for (div, unit) in [(60.0, "m"), (60.0, "h"), (24.0, "d"), (365.0, "y")] {
+		if t < div {
+			break;
+		}
+		t /= div;
+		u = unit;
+	}
+	format!("{:.1}{}", t, u)
+}
@@ -0,0 +1,54 @@
+//! The randomart of OpenSSH for uids, so two identities can be compared at a glance.
+//!
+//! This is the drunken bishop algorithm of `ssh-keygen -lv` on the 20 byte hash of the uid.
+
+const WIDTH: usize = 17;
+const HEIGHT: usize = 9;
+/// The symbols for the number of visits of a field, the last two mark start and end.
+const SYMBOLS: &[u8] = b" .o+=*BOX@%&#/^SE";
+
+/// Draws the randomart of a uid, returns `None` if the uid is no valid base64.
+pub fn render(uid: &str) -> Option<String> {
+	let hash = base64::decode(uid).ok()?;
+	let mut field = [[0u8; WIDTH]; HEIGHT];
+	let (mut x, mut y) = (WIDTH / 2, HEIGHT / 2);
+	let start = (x, y);
+	let max = (SYMBOLS.len() - 3) as u8;
+	for byte in hash {
+		for step in 0..4 {
+			let bits = byte >> (step * 2);
+			x = if bits & 1 == 0 {
+				x.saturating_sub(1)
+			} else {
+				(x + 1).min(WIDTH - 1)
+			};
+			y = if bits & 2 == 0 {
+				y.saturating_sub(1)
+			} else {
+				(y + 1).min(HEIGHT - 1)
+			};
+			field[y][x] = (field[y][x] + 1).min(max);
+		}
+	}
+
+	let title = "[P256 UID]";
+	let mut art = format!("+{:-^width$}+\n", title, width = WIDTH);
+	for (row, line) in field.iter().enumerate() {
+		art.push('|');
+		for (column, visits) in line.iter().enumerate() {
+			let symbol = if (column, row) == start {
+				b'S'
+			} else if (column, row) == (x, y) {
+				b'E'
+			} else {
+				SYMBOLS[*visits as usize]
+			};
+			art.push(symbol as char);
+		}
+		art.push_str("|\n");
+	}
+	art.push('+');
+	art.push_str(&"-".repeat(WIDTH));
+	art.push('+');
+	Some(art)
+}
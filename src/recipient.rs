@@ -0,0 +1,129 @@
+//! Encrypts private keys to an age or GPG recipient, so they can only be read elsewhere.
+//!
+//! The recipient can live on a hardware token, the private key then never exists unencrypted
+//! on disk: age plugin recipients like 'age1yubikey1...' of age-plugin-yubikey are backed by
+//! a PIV slot, and GPG keys can be on an OpenPGP card. Encrypting only needs the public key,
+//! the token is needed to decrypt.
+
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::str::FromStr;
+
+#[derive(Clone)]
+pub enum Recipient {
+	/// An age public key like `age1...`.
+	Age(age::x25519::Recipient),
+	/// A recipient of an age plugin like `age1yubikey1...`, the plugin binary
+	/// `age-plugin-<name>` must be installed.
+	Plugin(age::plugin::Recipient),
+	/// A key id, fingerprint or mail address in the GPG keyring.
+	Gpg(String),
+}
+
+impl fmt::Debug for Recipient {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Age(recipient) => write!(f, "Age({})", recipient),
+			Self::Plugin(recipient) => write!(f, "Plugin({})", recipient.plugin()),
+			Self::Gpg(recipient) => write!(f, "Gpg({})", recipient),
+		}
+	}
+}
+
+impl Recipient {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		if s.starts_with("age1") {
+			if let Ok(recipient) = age::x25519::Recipient::from_str(s) {
+				return Ok(Self::Age(recipient));
+			}
+			age::plugin::Recipient::from_str(s)
+				.map(Self::Plugin)
+				.map_err(|e| format!("Invalid age recipient {}: {}", s, e))
+		} else {
+			Ok(Self::Gpg(s.to_string()))
+		}
+	}
+
+	/// Encrypts the data, the result is base64 encoded to fit in a line.
+	///
+	/// Decrypt it with `base64 -d | age -d -i <key file>` or `base64 -d | gpg -d`.
+	pub fn encrypt(&self, data: &str) -> Result<String, String> {
+		let encrypted = match self {
+			Self::Age(recipient) => age_encrypt(Box::new(recipient.clone()), data)?,
+			Self::Plugin(recipient) => {
+				let plugin = age::plugin::RecipientPluginV1::new(
+					recipient.plugin(),
+					&[recipient.clone()],
+					&[],
+					PluginCallbacks,
+				)
+				.map_err(|e| format!("Failed to start age-plugin-{}: {}", recipient.plugin(), e))?;
+				age_encrypt(Box::new(plugin), data)?
+			}
+			Self::Gpg(recipient) => {
+				let mut child = Command::new("gpg")
+					.args(["--batch", "--yes", "--encrypt", "--recipient", recipient])
+					.stdin(Stdio::piped())
+					.stdout(Stdio::piped())
+					.stderr(Stdio::piped())
+					.spawn()
+					.map_err(|e| format!("Failed to run gpg: {}", e))?;
+				// Dropping stdin closes it, so gpg knows the input is complete
+				child
+					.stdin
+					.take()
+					.expect("Stdin is piped")
+					.write_all(data.as_bytes())
+					.map_err(|e| format!("Failed to write to gpg: {}", e))?;
+				let output = child
+					.wait_with_output()
+					.map_err(|e| format!("Failed to run gpg: {}", e))?;
+				if !output.status.success() {
+					return Err(format!(
+						"gpg failed: {}",
+						String::from_utf8_lossy(&output.stderr).trim()
+					));
+				}
+				output.stdout
+			}
+		};
+		Ok(base64::encode(encrypted))
+	}
+}
+
+fn age_encrypt(recipient: Box<dyn age::Recipient + Send>, data: &str) -> Result<Vec<u8>, String> {
+	let encryptor = age::Encryptor::with_recipients(vec![recipient]).ok_or("No age recipient")?;
+	let mut encrypted = Vec::new();
+	let mut writer = encryptor
+		.wrap_output(&mut encrypted)
+		.map_err(|e| format!("Failed to encrypt: {}", e))?;
+	writer
+		.write_all(data.as_bytes())
+		.and_then(|_| writer.finish())
+		.map_err(|e| format!("Failed to encrypt: {}", e))?;
+	Ok(encrypted)
+}
+
+/// Plugins only need the public key to encrypt, their messages are logged and questions are
+/// not answered because the search runs unattended.
+#[derive(Clone, Copy, Debug)]
+struct PluginCallbacks;
+
+impl age::Callbacks for PluginCallbacks {
+	fn display_message(&self, message: &str) {
+		log::info!("{}", message);
+	}
+
+	fn confirm(&self, _message: &str, _yes: &str, _no: Option<&str>) -> Option<bool> {
+		None
+	}
+
+	fn request_public_string(&self, _description: &str) -> Option<String> {
+		None
+	}
+
+	fn request_passphrase(&self, _description: &str) -> Option<age::secrecy::SecretString> {
+		None
+	}
+}
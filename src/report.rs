@@ -0,0 +1,265 @@
+//! Summarizes the identities of earlier searches, from the database of '--db' and the files of
+//! '--output' with '--output-format json'.
+//!
+//! Runs are told apart by the attempts of their identities, which start again at 0. Runs which
+//! wrote to the same file at the same time are mixed up.
+
+use std::collections::{BTreeMap, HashSet};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use log::{info, warn};
+use serde::Deserialize;
+
+use crate::progress::{human_count, short_duration};
+use crate::ts3client;
+use crate::{Outcome, Result};
+
+/// The width of the longest bar of the level distribution.
+const BAR_WIDTH: usize = 40;
+
+#[derive(clap::Args, Debug)]
+pub struct StatsArgs {
+	#[clap(required = true)]
+	/// SQLite databases of '--db' and json files of '--output', in any mix. Identities which
+	/// are in several files are counted once.
+	files: Vec<PathBuf>,
+}
+
+/// A found identity, without its key.
+#[derive(Debug, Deserialize)]
+struct Record {
+	uid: String,
+	pattern: String,
+	level: u8,
+	/// In RFC 3339 format.
+	timestamp: String,
+	attempts: u64,
+}
+
+/// The identities of one search, in the order they were found.
+struct Run<'a> {
+	records: Vec<&'a Record>,
+	/// Keys per second, from the first to the last identity.
+	rate: Option<f64>,
+}
+
+pub fn tool_stats(args: StatsArgs) -> Result {
+	let mut seen = HashSet::new();
+	let mut records = Vec::new();
+	for path in &args.files {
+		let read = if is_sqlite(path)? {
+			read_db(path)?
+		} else {
+			read_json(path)?
+		};
+		info!("Read {} identities from {}", read.len(), path.display());
+		records.extend(read.into_iter().filter(|r| seen.insert(r.uid.clone())));
+	}
+	let mut records = records
+		.into_iter()
+		.filter_map(|r| {
+			let time = humantime::parse_rfc3339(&r.timestamp).ok()?;
+			Some((time, r))
+		})
+		.collect::<Vec<_>>();
+	if records.is_empty() {
+		info!("No identities in the files");
+		return Ok(Outcome::NotFound);
+	}
+	records.sort_by_key(|(time, _)| *time);
+
+	println!(
+		"Identities: {} from {} to {}",
+		records.len(),
+		day(&records[0].1),
+		day(&records[records.len() - 1].1)
+	);
+	print_patterns(&records);
+	print_levels(&records);
+	print_days(&records);
+	print_runs(&runs(&records));
+	Ok(Outcome::Success)
+}
+
+fn is_sqlite(path: &Path) -> std::result::Result<bool, String> {
+	let mut header = [0; 16];
+	let mut file = std::fs::File::open(path)
+		.map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+	Ok(file.read_exact(&mut header).is_ok() && header == *b"SQLite format 3\0")
+}
+
+fn read_db(path: &Path) -> std::result::Result<Vec<Record>, String> {
+	let conn = ts3client::open_read_only(path)?;
+	let invalid = |e: rusqlite::Error| format!("Failed to read {}: {}", path.display(), e);
+	let mut stmt = conn
+		.prepare("SELECT uid, pattern, level, timestamp, attempts FROM found")
+		.map_err(invalid)?;
+	let rows = stmt
+		.query_map([], |row| {
+			Ok(Record {
+				uid: row.get(0)?,
+				pattern: row.get(1)?,
+				level: row.get(2)?,
+				timestamp: row.get(3)?,
+				attempts: row.get::<_, i64>(4)? as u64,
+			})
+		})
+		.map_err(invalid)?;
+	rows.collect::<rusqlite::Result<_>>().map_err(invalid)
+}
+
+/// Reads the json lines of a file, other lines like the text format or an encrypted key are
+/// skipped.
+fn read_json(path: &Path) -> std::result::Result<Vec<Record>, String> {
+	let content = std::fs::read_to_string(path)
+		.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	let mut skipped = 0;
+	let records = content
+		.lines()
+		.filter(|l| !l.trim().is_empty())
+		.filter_map(|l| {
+			let record = serde_json::from_str(l).ok();
+			skipped += record.is_none() as usize;
+			record
+		})
+		.collect();
+	if skipped > 0 {
+		warn!(
+			"Skipped {} lines of {} which are not json, write them with '--output-format json'",
+			skipped,
+			path.display()
+		);
+	}
+	Ok(records)
+}
+
+/// The day of an identity, the timestamps are in UTC.
+fn day(record: &Record) -> &str {
+	record.timestamp.get(..10).unwrap_or(&record.timestamp)
+}
+
+fn print_patterns(records: &[(SystemTime, Record)]) {
+	let mut patterns = BTreeMap::<&str, Vec<u8>>::new();
+	for (_, r) in records {
+		patterns
+			.entry(r.pattern.as_str())
+			.or_default()
+			.push(r.level);
+	}
+	let mut patterns = patterns.into_iter().collect::<Vec<_>>();
+	patterns.sort_by_key(|(_, levels)| std::cmp::Reverse(levels.len()));
+	println!();
+	println!("{:<16} {:>8}  levels min/avg/max", "pattern", "matches");
+	for (pattern, levels) in patterns {
+		let avg = levels.iter().map(|l| *l as f64).sum::<f64>() / levels.len() as f64;
+		println!(
+			"{:<16} {:>8}  {}/{:.1}/{}",
+			if pattern.is_empty() { "-" } else { pattern },
+			levels.len(),
+			levels.iter().min().expect("Patterns have a level"),
+			avg,
+			levels.iter().max().expect("Patterns have a level")
+		);
+	}
+}
+
+fn print_levels(records: &[(SystemTime, Record)]) {
+	let mut levels = BTreeMap::<u8, usize>::new();
+	for (_, r) in records {
+		*levels.entry(r.level).or_default() += 1;
+	}
+	let max = levels.values().copied().max().unwrap_or(1);
+	println!();
+	println!("{:>5} {:>8}", "level", "count");
+	for (level, count) in levels {
+		let bar = (count * BAR_WIDTH + max - 1) / max;
+		println!("{:>5} {:>8} {}", level, count, "#".repeat(bar));
+	}
+}
+
+fn print_days(records: &[(SystemTime, Record)]) {
+	let mut days = BTreeMap::<&str, usize>::new();
+	for (_, r) in records {
+		*days.entry(day(r)).or_default() += 1;
+	}
+	println!();
+	println!("{:<10} {:>8}", "day", "matches");
+	for (day, count) in days {
+		println!("{:<10} {:>8}", day, count);
+	}
+}
+
+/// Splits the identities into runs, a new run starts when the attempts do not increase.
+fn runs(records: &[(SystemTime, Record)]) -> Vec<Run> {
+	let mut runs: Vec<Vec<&(SystemTime, Record)>> = Vec::new();
+	for record in records {
+		match runs.last_mut() {
+			Some(run)
+				if run
+					.last()
+					.map_or(false, |(_, r)| r.attempts < record.1.attempts) =>
+			{
+				run.push(record)
+			}
+			_ => runs.push(vec![record]),
+		}
+	}
+	runs.into_iter()
+		.map(|run| {
+			let (first, last) = (run[0], run[run.len() - 1]);
+			let secs = last
+				.0
+				.duration_since(first.0)
+				.map_or(0.0, |d| d.as_secs_f64());
+			let rate = (secs > 0.0).then(|| (last.1.attempts - first.1.attempts) as f64 / secs);
+			Run {
+				records: run.into_iter().map(|(_, r)| r).collect(),
+				rate,
+			}
+		})
+		.collect()
+}
+
+fn print_runs(runs: &[Run]) {
+	let mut first = runs
+		.iter()
+		.map(|r| r.records[0].attempts as f64)
+		.collect::<Vec<_>>();
+	let mut rates = runs.iter().filter_map(|r| r.rate).collect::<Vec<_>>();
+	// The time until the first match, for the runs whose rate is known
+	let mut times = runs
+		.iter()
+		.filter_map(|r| Some(r.records[0].attempts as f64 / r.rate?))
+		.collect::<Vec<_>>();
+	println!();
+	println!("Runs: {}", runs.len());
+	println!(
+		"Keys until the first match: median {}, mean {}, max {}",
+		human_count(median(&mut first)),
+		human_count(mean(&first)),
+		human_count(first.iter().copied().fold(0.0, f64::max))
+	);
+	if !rates.is_empty() {
+		println!(
+			"Throughput: median {}/s over {} runs with several matches",
+			human_count(median(&mut rates)),
+			rates.len()
+		);
+		println!(
+			"Time until the first match: median {}, mean {}",
+			short_duration(median(&mut times)),
+			short_duration(mean(&times))
+		);
+	}
+}
+
+fn median(values: &mut [f64]) -> f64 {
+	values.sort_by(f64::total_cmp);
+	values[values.len() / 2]
+}
+
+fn mean(values: &[f64]) -> f64 {
+	values.iter().sum::<f64>() / values.len() as f64
+}
@@ -0,0 +1,135 @@
+//! The random generators for private keys.
+//!
+//! With '--seed', key number n is the first key `SecretKey::random` draws from ChaCha20 with
+//! the seed and the stream n + '--seed-start', so anyone with the seed can recreate it.
+//!
+//! Only the p256 backend can use another generator than the one of the operating system, ring
+//! always uses its own.
+
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use log::{info, warn};
+use p256::SecretKey;
+use rand_chacha::ChaCha20Rng;
+use rand_core::{OsRng, RngCore, SeedableRng};
+use zeroize::Zeroizing;
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RngKind {
+	/// The generator of the key backend.
+	System,
+	/// The generator of the operating system, through getrandom.
+	Os,
+	/// ChaCha20, seeded once from the operating system or '--rng-seed-file'.
+	ChachaSeeded,
+}
+
+static CHACHA: AtomicBool = AtomicBool::new(false);
+static SEEDED: AtomicBool = AtomicBool::new(false);
+/// The seed of '--seed' as little endian words, atomics keep the hot loop free of locks.
+static SEED: [AtomicU64; 4] = [
+	AtomicU64::new(0),
+	AtomicU64::new(0),
+	AtomicU64::new(0),
+	AtomicU64::new(0),
+];
+static SEED_START: AtomicU64 = AtomicU64::new(0);
+/// Seeds the generators of the worker threads.
+static MASTER: Mutex<Option<ChaCha20Rng>> = Mutex::new(None);
+
+thread_local! {
+	static THREAD_RNG: RefCell<Option<ChaCha20Rng>> = RefCell::new(None);
+}
+
+/// Sets the generator for all following keys. The seed file can also be a device like
+/// `/dev/hwrng`, 32 bytes are read from it.
+pub fn select(kind: RngKind, seed_file: Option<&Path>) -> Result<(), String> {
+	if kind != RngKind::ChachaSeeded {
+		if seed_file.is_some() {
+			return Err("'--rng-seed-file' only works with '--rng chacha-seeded'".to_string());
+		}
+		return Ok(());
+	}
+	let mut seed = Zeroizing::new([0u8; 32]);
+	match seed_file {
+		Some(path) => {
+			File::open(path)
+				.and_then(|mut f| f.read_exact(&mut *seed))
+				.map_err(|e| format!("Failed to read seed from {}: {}", path.display(), e))?;
+			info!("Seeding ChaCha20 from {}", path.display());
+		}
+		None => {
+			OsRng.fill_bytes(&mut *seed);
+			info!("Seeding ChaCha20 from the operating system");
+		}
+	}
+	*MASTER.lock().unwrap() = Some(ChaCha20Rng::from_seed(*seed));
+	CHACHA.store(true, Ordering::Relaxed);
+	Ok(())
+}
+
+/// Reads a seed of 64 hex characters.
+pub fn parse_seed(s: &str) -> Result<[u8; 32], String> {
+	let invalid = || "The seed must be 64 hex characters (32 bytes)".to_string();
+	if s.len() != 64 || !s.is_ascii() {
+		return Err(invalid());
+	}
+	let mut seed = [0u8; 32];
+	for (i, b) in seed.iter_mut().enumerate() {
+		*b = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).map_err(|_| invalid())?;
+	}
+	Ok(seed)
+}
+
+/// Derives all following keys from the seed, the first one gets the number `start`.
+pub fn select_seed(seed: &[u8; 32], start: u64) {
+	warn!("The keys are derived from '--seed', anyone who knows it knows the keys");
+	for (part, chunk) in SEED.iter().zip(seed.chunks(8)) {
+		let mut word = [0u8; 8];
+		word.copy_from_slice(chunk);
+		part.store(u64::from_le_bytes(word), Ordering::Relaxed);
+	}
+	SEED_START.store(start, Ordering::Relaxed);
+	SEEDED.store(true, Ordering::Relaxed);
+}
+
+/// The key with this number, if '--seed' is used.
+pub fn seeded_key(index: u64) -> Option<SecretKey> {
+	if !SEEDED.load(Ordering::Relaxed) {
+		return None;
+	}
+	let mut seed = Zeroizing::new([0u8; 32]);
+	for (chunk, part) in seed.chunks_mut(8).zip(&SEED) {
+		chunk.copy_from_slice(&part.load(Ordering::Relaxed).to_le_bytes());
+	}
+	let mut rng = ChaCha20Rng::from_seed(*seed);
+	rng.set_stream(SEED_START.load(Ordering::Relaxed).wrapping_add(index));
+	Some(SecretKey::random(&mut rng))
+}
+
+fn thread_rng() -> ChaCha20Rng {
+	let mut seed = Zeroizing::new([0u8; 32]);
+	MASTER
+		.lock()
+		.unwrap()
+		.as_mut()
+		.expect("ChaCha20 is seeded")
+		.fill_bytes(&mut *seed);
+	ChaCha20Rng::from_seed(*seed)
+}
+
+/// Generates a private key with the selected generator.
+pub fn secret_key() -> SecretKey {
+	if !CHACHA.load(Ordering::Relaxed) {
+		return SecretKey::random(&mut OsRng);
+	}
+	THREAD_RNG.with(|rng| {
+		let mut rng = rng.borrow_mut();
+		SecretKey::random(rng.get_or_insert_with(thread_rng))
+	})
+}
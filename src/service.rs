@@ -0,0 +1,150 @@
+use std::ffi::OsString;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{error, info};
+use windows_service::service::{
+	ServiceAccess, ServiceControl, ServiceControlAccept, ServiceErrorControl, ServiceExitCode,
+	ServiceInfo, ServiceStartType, ServiceState, ServiceStatus, ServiceType,
+};
+use windows_service::service_control_handler::{self, ServiceControlHandlerResult};
+use windows_service::service_manager::{ServiceManager, ServiceManagerAccess};
+use windows_service::{define_windows_service, service_dispatcher};
+
+use crate::{control, Opts, Outcome, Result};
+
+const SERVICE_NAME: &str = "MahTsIdentity";
+const SERVICE_TYPE: ServiceType = ServiceType::OWN_PROCESS;
+
+/// The options of the search, handed from `run` to the service thread.
+static SERVICE_OPTS: Mutex<Option<Opts>> = Mutex::new(None);
+/// The exit code of the search, handed back from the service thread.
+static EXIT_CODE: Mutex<i32> = Mutex::new(0);
+
+#[derive(clap::Subcommand, Debug)]
+pub enum ServiceCommand {
+	/// Registers a service which runs a search with the given arguments.
+	///
+	/// Results can only be written to '--output' or '--db', use absolute paths. Example:
+	/// `service install -- -o C:\ids.txt abc`
+	Install {
+		#[clap(allow_hyphen_values = true)]
+		/// The arguments of the search, like on the command line.
+		args: Vec<String>,
+	},
+	/// Removes the service.
+	Uninstall,
+}
+
+pub fn tool_service(cmd: ServiceCommand) -> Result {
+	match cmd {
+		ServiceCommand::Install { args } => install(args),
+		ServiceCommand::Uninstall => uninstall(),
+	}
+}
+
+fn install(args: Vec<String>) -> Result {
+	if !args
+		.iter()
+		.any(|a| a.starts_with("-o") || a.starts_with("--output") || a == "--db")
+	{
+		return Err("The service requires '--output' or '--db' to store results".to_string());
+	}
+	let manager =
+		ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CREATE_SERVICE)
+			.map_err(|e| format!("Failed to open the service manager: {}", e))?;
+	let executable_path =
+		std::env::current_exe().map_err(|e| format!("Failed to find the executable: {}", e))?;
+	let mut launch_arguments = vec![OsString::from("--windows-service")];
+	launch_arguments.extend(args.into_iter().map(OsString::from));
+	let info = ServiceInfo {
+		name: SERVICE_NAME.into(),
+		display_name: "MahTsIdentity search".into(),
+		service_type: SERVICE_TYPE,
+		start_type: ServiceStartType::AutoStart,
+		error_control: ServiceErrorControl::Normal,
+		executable_path,
+		launch_arguments,
+		dependencies: Vec::new(),
+		account_name: None,
+		account_password: None,
+	};
+	manager
+		.create_service(&info, ServiceAccess::empty())
+		.map_err(|e| format!("Failed to install the service: {}", e))?;
+	info!("Installed the service {}", SERVICE_NAME);
+	Ok(Outcome::Success)
+}
+
+fn uninstall() -> Result {
+	let manager = ServiceManager::local_computer(None::<&str>, ServiceManagerAccess::CONNECT)
+		.map_err(|e| format!("Failed to open the service manager: {}", e))?;
+	let service = manager
+		.open_service(SERVICE_NAME, ServiceAccess::DELETE)
+		.map_err(|e| format!("Failed to open the service: {}", e))?;
+	service
+		.delete()
+		.map_err(|e| format!("Failed to uninstall the service: {}", e))?;
+	info!("Uninstalled the service {}", SERVICE_NAME);
+	Ok(Outcome::Success)
+}
+
+/// Runs the search as a service, called when started by the service manager.
+///
+/// Returns the exit code.
+pub fn run(opts: Opts) -> i32 {
+	*SERVICE_OPTS.lock().unwrap() = Some(opts);
+	if let Err(e) = service_dispatcher::start(SERVICE_NAME, ffi_service_main) {
+		error!("Failed to start the service: {}", e);
+		return 1;
+	}
+	*EXIT_CODE.lock().unwrap()
+}
+
+define_windows_service!(ffi_service_main, service_main);
+
+fn service_main(_: Vec<OsString>) {
+	let handler = |event| match event {
+		ServiceControl::Stop | ServiceControl::Shutdown => {
+			control::interrupt();
+			ServiceControlHandlerResult::NoError
+		}
+		ServiceControl::Interrogate => ServiceControlHandlerResult::NoError,
+		_ => ServiceControlHandlerResult::NotImplemented,
+	};
+	let status = match service_control_handler::register(SERVICE_NAME, handler) {
+		Ok(status) => status,
+		Err(e) => {
+			error!("Failed to register the service control handler: {}", e);
+			return;
+		}
+	};
+	let set_state = |state, exit_code| {
+		let res = status.set_service_status(ServiceStatus {
+			service_type: SERVICE_TYPE,
+			current_state: state,
+			controls_accepted: if state == ServiceState::Running {
+				ServiceControlAccept::STOP | ServiceControlAccept::SHUTDOWN
+			} else {
+				ServiceControlAccept::empty()
+			},
+			exit_code: ServiceExitCode::ServiceSpecific(exit_code),
+			checkpoint: 0,
+			wait_hint: Duration::default(),
+			process_id: None,
+		});
+		if let Err(e) = res {
+			error!("Failed to set the service status: {}", e);
+		}
+	};
+
+	set_state(ServiceState::Running, 0);
+	let opts = SERVICE_OPTS
+		.lock()
+		.unwrap()
+		.take()
+		.expect("Service started twice");
+	let code = crate::exit_code(crate::run(opts));
+	*EXIT_CODE.lock().unwrap() = code;
+	set_state(ServiceState::Stopped, code as u32);
+}
@@ -0,0 +1,354 @@
+//! SHA-1 of many messages at once, for the uids in the pattern search and the hashcash
+//! levels of the level search.
+//!
+//! Every step of the hash is done for all lanes in a loop over arrays, which the compiler turns
+//! into SIMD instructions. The AVX2 and AVX-512 versions are the same code compiled with these
+//! features, the fastest one is picked at runtime.
+//!
+//! On ARMv8 with the SHA-1 instructions, hashing one message after the other with them is
+//! faster. The sha-1 crate uses them with its 'asm' feature, which is enabled for aarch64
+//! builds, so the level search and tsproto use them too.
+//!
+//! For the levels, all messages start with the same public key. Its full blocks are hashed
+//! once into a midstate, only the rest with the offset is hashed per offset.
+
+use sha1::{Digest, Sha1};
+use tsproto::algorithms::get_hash_cash_level;
+
+/// The number of messages hashed together, fills one AVX-512 register of 32 bit words.
+pub const LANES: usize = 16;
+
+type Words = [u32; LANES];
+
+const INIT: [u32; 5] = [0x67452301, 0xEFCDAB89, 0x98BADCFE, 0x10325476, 0xC3D2E1F0];
+const K: [u32; 4] = [0x5A827999, 0x6ED9EBA1, 0x8F1BBCDC, 0xCA62C1D6];
+
+/// The longest decimal offset.
+const MAX_DIGITS: usize = 20;
+
+/// Hashes all messages. Lanes are only combined if the messages have the same number of
+/// blocks, which is almost always the case for public keys.
+pub fn digest(messages: &[&[u8]; LANES]) -> [[u8; 20]; LANES] {
+	let blocks = block_count(messages[0].len());
+	if has_sha1_instructions() || messages.iter().any(|m| block_count(m.len()) != blocks) {
+		return std::array::from_fn(|i| Sha1::digest(messages[i]).into());
+	}
+	dispatch(&INIT, 0, messages, blocks)
+}
+
+fn dispatch(
+	init: &[u32; 5],
+	before: usize,
+	messages: &[&[u8]; LANES],
+	blocks: usize,
+) -> [[u8; 20]; LANES] {
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	{
+		if is_x86_feature_detected!("avx512f") {
+			return unsafe { digest_avx512(init, before, messages, blocks) };
+		}
+		if is_x86_feature_detected!("avx2") {
+			return unsafe { digest_avx2(init, before, messages, blocks) };
+		}
+	}
+	digest_lanes(init, before, messages, blocks)
+}
+
+/// The state of SHA-1 after the full blocks of a public key.
+#[derive(Clone, Debug)]
+pub struct Midstate {
+	omega: String,
+	state: [u32; 5],
+	/// The length of the hashed blocks.
+	before: usize,
+}
+
+impl Midstate {
+	pub fn new(omega: &str) -> Self {
+		let before = omega.len() / 64 * 64;
+		let mut state = INIT.map(|h| [h; LANES]);
+		for block in omega.as_bytes()[..before].chunks(64) {
+			let mut w = [[0u32; LANES]; 16];
+			for (i, word) in w.iter_mut().enumerate() {
+				*word = [u32::from_be_bytes(block[i * 4..i * 4 + 4].try_into().unwrap()); LANES];
+			}
+			compress(&mut state, &mut w);
+		}
+		Self {
+			omega: omega.to_string(),
+			state: state.map(|s| s[0]),
+			before,
+		}
+	}
+
+	/// The hashcash levels of the offsets, like `get_hash_cash_level`.
+	pub fn levels(&self, offsets: &[u64; LANES]) -> [u8; LANES] {
+		let rest = &self.omega.as_bytes()[self.before..];
+		if has_sha1_instructions() {
+			return offsets.map(|o| get_hash_cash_level(&self.omega, o));
+		}
+		let mut buffers = [[0u8; 64 + MAX_DIGITS]; LANES];
+		let mut lens = [0; LANES];
+		for ((buffer, len), offset) in buffers.iter_mut().zip(&mut lens).zip(offsets) {
+			buffer[..rest.len()].copy_from_slice(rest);
+			*len = rest.len() + write_decimal(&mut buffer[rest.len()..], *offset);
+		}
+		let messages = std::array::from_fn(|i| &buffers[i][..lens[i]]);
+		let blocks = block_count(lens[0]);
+		if lens.iter().any(|l| block_count(*l) != blocks) {
+			return offsets.map(|o| get_hash_cash_level(&self.omega, o));
+		}
+		dispatch(&self.state, self.before, &messages, blocks).map(|hash| level(&hash))
+	}
+}
+
+/// Writes the number like `to_string` and returns the number of digits.
+fn write_decimal(buffer: &mut [u8], mut n: u64) -> usize {
+	let mut digits = [0u8; MAX_DIGITS];
+	let mut len = 0;
+	loop {
+		digits[MAX_DIGITS - 1 - len] = b'0' + (n % 10) as u8;
+		len += 1;
+		n /= 10;
+		if n == 0 {
+			break;
+		}
+	}
+	buffer[..len].copy_from_slice(&digits[MAX_DIGITS - len..]);
+	len
+}
+
+/// The number of zero bits at the start of the hash, counted from the lowest bit of each byte
+/// like TeamSpeak does.
+fn level(hash: &[u8; 20]) -> u8 {
+	let mut level = 0;
+	for b in hash {
+		if *b != 0 {
+			return level + b.trailing_zeros() as u8;
+		}
+		level += 8;
+	}
+	level
+}
+
+/// The name of the implementation `digest` uses on this cpu.
+pub fn implementation() -> &'static str {
+	if has_sha1_instructions() {
+		return "ARMv8 SHA-1 instructions";
+	}
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	{
+		if is_x86_feature_detected!("avx512f") {
+			return "AVX-512 multi-buffer";
+		}
+		if is_x86_feature_detected!("avx2") {
+			return "AVX2 multi-buffer";
+		}
+	}
+	"portable multi-buffer"
+}
+
+fn has_sha1_instructions() -> bool {
+	// The 'sha2' feature of Rust includes the SHA-1 instructions
+	#[cfg(target_arch = "aarch64")]
+	{
+		std::arch::is_aarch64_feature_detected!("sha2")
+	}
+	#[cfg(not(target_arch = "aarch64"))]
+	{
+		false
+	}
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx512f")]
+unsafe fn digest_avx512(
+	init: &[u32; 5],
+	before: usize,
+	messages: &[&[u8]; LANES],
+	blocks: usize,
+) -> [[u8; 20]; LANES] {
+	digest_lanes(init, before, messages, blocks)
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+#[target_feature(enable = "avx2")]
+unsafe fn digest_avx2(
+	init: &[u32; 5],
+	before: usize,
+	messages: &[&[u8]; LANES],
+	blocks: usize,
+) -> [[u8; 20]; LANES] {
+	digest_lanes(init, before, messages, blocks)
+}
+
+/// The number of 64 byte blocks after padding, which adds at least 9 bytes.
+fn block_count(len: usize) -> usize {
+	(len + 8) / 64 + 1
+}
+
+/// The big endian word at `pos` of the padded message, `before` bytes were already hashed.
+#[inline(always)]
+fn padded_word(message: &[u8], before: usize, blocks: usize, pos: usize) -> u32 {
+	if pos + 4 <= message.len() {
+		return u32::from_be_bytes(message[pos..pos + 4].try_into().unwrap());
+	}
+	let bits = ((before + message.len()) as u64 * 8).to_be_bytes();
+	let length_start = blocks * 64 - 8;
+	let mut word = [0u8; 4];
+	for (i, b) in word.iter_mut().enumerate() {
+		let p = pos + i;
+		*b = if p < message.len() {
+			message[p]
+		} else if p == message.len() {
+			0x80
+		} else if p >= length_start {
+			bits[p - length_start]
+		} else {
+			0
+		};
+	}
+	u32::from_be_bytes(word)
+}
+
+#[inline(always)]
+fn digest_lanes(
+	init: &[u32; 5],
+	before: usize,
+	messages: &[&[u8]; LANES],
+	blocks: usize,
+) -> [[u8; 20]; LANES] {
+	let mut state = init.map(|h| [h; LANES]);
+	for block in 0..blocks {
+		let mut w = [[0u32; LANES]; 16];
+		for (lane, message) in messages.iter().enumerate() {
+			for (i, word) in w.iter_mut().enumerate() {
+				word[lane] = padded_word(message, before, blocks, block * 64 + i * 4);
+			}
+		}
+		compress(&mut state, &mut w);
+	}
+	std::array::from_fn(|lane| {
+		let mut hash = [0u8; 20];
+		for (chunk, h) in hash.chunks_mut(4).zip(&state) {
+			chunk.copy_from_slice(&h[lane].to_be_bytes());
+		}
+		hash
+	})
+}
+
+#[inline(always)]
+fn compress(state: &mut [Words; 5], w: &mut [Words; 16]) {
+	let [mut a, mut b, mut c, mut d, mut e] = *state;
+	for t in 0..80 {
+		if t >= 16 {
+			// w[t] = (w[t - 3] ^ w[t - 8] ^ w[t - 14] ^ w[t - 16]) <<< 1, in a ring of 16 words
+			let mut next = [0u32; LANES];
+			for (l, n) in next.iter_mut().enumerate() {
+				*n = (w[(t + 13) % 16][l] ^ w[(t + 8) % 16][l] ^ w[(t + 2) % 16][l] ^ w[t % 16][l])
+					.rotate_left(1);
+			}
+			w[t % 16] = next;
+		}
+		let round = t / 20;
+		let k = K[round];
+		let wt = &w[t % 16];
+		for l in 0..LANES {
+			let f = match round {
+				0 => (b[l] & c[l]) | (!b[l] & d[l]),
+				2 => (b[l] & c[l]) | (b[l] & d[l]) | (c[l] & d[l]),
+				_ => b[l] ^ c[l] ^ d[l],
+			};
+			let temp = a[l]
+				.rotate_left(5)
+				.wrapping_add(f)
+				.wrapping_add(e[l])
+				.wrapping_add(k)
+				.wrapping_add(wt[l]);
+			e[l] = d[l];
+			d[l] = c[l];
+			c[l] = b[l].rotate_left(30);
+			b[l] = a[l];
+			a[l] = temp;
+		}
+	}
+	for (s, v) in state.iter_mut().zip([a, b, c, d, e]) {
+		for l in 0..LANES {
+			s[l] = s[l].wrapping_add(v[l]);
+		}
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// Lengths around the padding and block boundaries, up to several blocks.
+	const LENGTHS: [usize; 10] = [0, 1, 55, 56, 63, 64, 100, 119, 120, 200];
+
+	/// Different messages of the same length in every lane.
+	fn messages(len: usize) -> [Vec<u8>; LANES] {
+		std::array::from_fn(|lane| (0..len).map(|i| (i * 7 + lane * 31) as u8).collect())
+	}
+
+	fn check(name: &str, hash: impl Fn(&[&[u8]; LANES], usize) -> [[u8; 20]; LANES]) {
+		for len in LENGTHS {
+			let messages = messages(len);
+			let refs = std::array::from_fn(|i| messages[i].as_slice());
+			let hashes = hash(&refs, block_count(len));
+			for (lane, (message, got)) in messages.iter().zip(hashes).enumerate() {
+				let expected: [u8; 20] = Sha1::digest(message).into();
+				assert_eq!(got, expected, "{} lane {} of length {}", name, lane, len);
+			}
+		}
+	}
+
+	#[test]
+	fn lanes_match_sha1() {
+		check("scalar", |m, blocks| digest_lanes(&INIT, 0, m, blocks));
+		check("digest", |m, _| digest(m));
+	}
+
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	#[test]
+	fn avx2_matches_sha1() {
+		if is_x86_feature_detected!("avx2") {
+			check("avx2", |m, blocks| unsafe {
+				digest_avx2(&INIT, 0, m, blocks)
+			});
+		}
+	}
+
+	#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+	#[test]
+	fn avx512_matches_sha1() {
+		if is_x86_feature_detected!("avx512f") {
+			check("avx512", |m, blocks| unsafe {
+				digest_avx512(&INIT, 0, m, blocks)
+			});
+		}
+	}
+
+	#[test]
+	fn mixed_lengths_match_sha1() {
+		let messages: [Vec<u8>; LANES] = std::array::from_fn(|lane| vec![b'a'; lane * 13]);
+		let refs = std::array::from_fn(|i| messages[i].as_slice());
+		for (message, hash) in messages.iter().zip(digest(&refs)) {
+			assert_eq!(hash, <[u8; 20]>::from(Sha1::digest(message)));
+		}
+	}
+
+	#[test]
+	fn midstate_levels_match_hashcash() {
+		for len in [10, 64, 90, 130] {
+			let omega = "M".repeat(len);
+			let midstate = Midstate::new(&omega);
+			// Crosses the digit count changes at 10, 100 and 2^32
+			for start in [0, 90, 4_294_967_290, u64::MAX - LANES as u64] {
+				let offsets = std::array::from_fn(|i| start + i as u64);
+				let expected = offsets.map(|o| get_hash_cash_level(&omega, o));
+				assert_eq!(midstate.levels(&offsets), expected, "omega length {}", len);
+			}
+		}
+	}
+}
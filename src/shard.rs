@@ -0,0 +1,82 @@
+/// One of several independent invocations which split the offsets of the level search.
+///
+/// Written like `3/8` on the command line, shards are numbered starting from 1.
+#[derive(Clone, Copy, Debug)]
+pub struct Shard {
+	/// Starts at 0.
+	index: u64,
+	count: u64,
+}
+
+impl Shard {
+	pub fn parse(s: &str) -> Result<Self, String> {
+		let invalid = || format!("Invalid shard '{}', expected e.g. '3/8'", s);
+		let (index, count) = s.split_once('/').ok_or_else(invalid)?;
+		let index = index.trim().parse::<u64>().map_err(|_| invalid())?;
+		let count = count.trim().parse::<u64>().map_err(|_| invalid())?;
+		if index == 0 || index > count {
+			return Err(format!(
+				"Invalid shard '{}', the number must be between 1 and {}",
+				s, count
+			));
+		}
+		Ok(Self {
+			index: index - 1,
+			count,
+		})
+	}
+
+	/// Returns the offsets after `base` which belong to this shard, the end is exclusive.
+	///
+	/// The offsets are split into contiguous ranges, so every shard can be checkpointed on its
+	/// own. `base` has to be the same for all shards and resumes, the last shard gets the rest.
+	pub fn range(&self, base: u64) -> (u64, u64) {
+		let size = (u64::MAX - base) / self.count;
+		let begin = base + self.index * size;
+		if self.index + 1 == self.count {
+			(begin, u64::MAX)
+		} else {
+			(begin, begin + size)
+		}
+	}
+}
+
+impl std::fmt::Display for Shard {
+	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+		write!(f, "{}/{}", self.index + 1, self.count)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn parse() {
+		let shard = Shard::parse("3/8").unwrap();
+		assert_eq!((shard.index, shard.count), (2, 8));
+		assert_eq!(shard.to_string(), "3/8");
+		assert!(Shard::parse("1/1").is_ok());
+		for invalid in ["0/8", "9/8", "3/0", "3", "a/8", "3/-8"] {
+			assert!(Shard::parse(invalid).is_err(), "{}", invalid);
+		}
+	}
+
+	#[test]
+	fn shards_are_disjoint_and_cover_everything() {
+		for base in [0, 20, u64::MAX - 5] {
+			for count in [1, 3, 7, 8] {
+				let mut next = base;
+				for shard in 1..=count {
+					let (begin, end) = Shard::parse(&format!("{}/{}", shard, count))
+						.unwrap()
+						.range(base);
+					assert_eq!(begin, next, "shard {}/{} from {}", shard, count, base);
+					assert!(begin <= end);
+					next = end;
+				}
+				assert_eq!(next, u64::MAX, "{} shards from {}", count, base);
+			}
+		}
+	}
+}
@@ -0,0 +1,129 @@
+//! Signatures which prove the ownership of a uid without revealing the private key.
+//!
+//! A signature is printed as `<public key>:<signature>`, so it can be checked with only the
+//! uid and the message.
+
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use tsproto_types::crypto::{EccKeyPrivP256, EccKeyPubP256};
+use zeroize::Zeroizing;
+
+use crate::{Outcome, Result};
+
+#[derive(clap::Args, Debug)]
+pub struct SignArgs {
+	#[clap(short, long)]
+	/// The message to sign, e.g. a text the server admin asked for.
+	message: String,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+	#[clap(long)]
+	/// The uid which should have signed the message.
+	uid: String,
+
+	#[clap(long)]
+	/// The signature printed by 'sign'.
+	sig: String,
+
+	#[clap(short, long)]
+	/// The signed message.
+	message: String,
+}
+
+pub fn tool_sign(args: SignArgs, identity: Option<&str>) -> Result {
+	let identity = identity.ok_or("Requires an identity (-i) to sign")?;
+	let tp_priv = EccKeyPrivP256::import_str(identity).map_err(|_| "Failed to read identity")?;
+	let key = SigningKey::from_bytes(&Zeroizing::new(tp_priv.to_short()))
+		.map_err(|_| "Invalid private key")?;
+	let signature: Signature = key.sign(args.message.as_bytes());
+	println!("UID: {}", tp_priv.to_pub().get_uid());
+	println!(
+		"SIGNATURE: {}:{}",
+		tp_priv.to_pub().to_ts(),
+		base64::encode(signature.to_der().as_bytes())
+	);
+	Ok(Outcome::Success)
+}
+
+pub fn tool_verify(args: VerifyArgs) -> Result {
+	let (omega, signature) = args
+		.sig
+		.trim()
+		.split_once(':')
+		.ok_or("The signature needs the format <public key>:<signature>")?;
+	let pub_key = EccKeyPubP256::from_ts(omega).map_err(|_| "Failed to read public key")?;
+	if pub_key.get_uid().to_string() != args.uid.trim() {
+		return Err(format!(
+			"The signature is from {}, not {}",
+			pub_key.get_uid(),
+			args.uid.trim()
+		));
+	}
+	let signature = base64::decode(signature)
+		.ok()
+		.and_then(|s| Signature::from_der(&s).ok())
+		.ok_or("Failed to read signature")?;
+	verifying_key(omega)?
+		.verify(args.message.as_bytes(), &signature)
+		.map_err(|_| "The signature is invalid")?;
+	println!("VALID: {}", args.uid.trim());
+	Ok(Outcome::Success)
+}
+
+/// Reads the public key from the libtomcrypt DER format of TeamSpeak.
+///
+/// The key is a sequence of a bit string with flags, the key size and the x and y
+/// coordinates.
+fn verifying_key(omega: &str) -> std::result::Result<VerifyingKey, String> {
+	let der = base64::decode(omega).map_err(|_| "Failed to read public key")?;
+	let content = der_element(&der, 0x30)
+		.map(|(content, _)| content)
+		.ok_or("Failed to read public key")?;
+	let mut rest = content;
+	let mut integers = Vec::new();
+	while !rest.is_empty() {
+		let tag = rest[0];
+		let (value, next) = der_element(rest, tag).ok_or("Failed to read public key")?;
+		if tag == 0x02 {
+			integers.push(value);
+		}
+		rest = next;
+	}
+	let (x, y) = match integers.as_slice() {
+		[_, x, y, ..] => (*x, *y),
+		_ => return Err("Failed to read public key".to_string()),
+	};
+	let mut point = vec![0x04];
+	for coordinate in [x, y] {
+		let coordinate = &coordinate[coordinate.iter().take_while(|b| **b == 0).count()..];
+		if coordinate.len() > 32 {
+			return Err("Failed to read public key".to_string());
+		}
+		point.resize(point.len() + 32 - coordinate.len(), 0);
+		point.extend_from_slice(coordinate);
+	}
+	VerifyingKey::from_sec1_bytes(&point).map_err(|_| "Failed to read public key".to_string())
+}
+
+/// Splits a DER element with the given tag into its content and the following bytes.
+fn der_element(data: &[u8], tag: u8) -> Option<(&[u8], &[u8])> {
+	if data.len() < 2 || data[0] != tag {
+		return None;
+	}
+	let (len, header) = match data[1] {
+		len if len < 0x80 => (len as usize, 2),
+		0x81 => (*data.get(2)? as usize, 3),
+		0x82 => (
+			u16::from_be_bytes([*data.get(2)?, *data.get(3)?]) as usize,
+			4,
+		),
+		_ => return None,
+	};
+	let end = header.checked_add(len)?;
+	if data.len() < end {
+		return None;
+	}
+	Some((&data[header..end], &data[end..]))
+}
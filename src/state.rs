@@ -0,0 +1,83 @@
+//! Snapshots of a running search for the status socket and the dashboards.
+
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use serde_json::{json, Value};
+
+use crate::{control, Level, RunData};
+
+/// The state of the pattern search.
+pub fn find_state(data: &RunData, elapsed: Duration) -> Value {
+	let attempts = data.attempts.load(Ordering::Relaxed);
+	let rate = attempts as f64 / elapsed.as_secs_f64().max(0.001);
+	let patterns = data
+		.patterns
+		.read()
+		.iter()
+		.map(|p| {
+			json!({
+				"pattern": p.input,
+				"matches": p.matches.load(Ordering::Relaxed),
+				"near_miss_chars": p.near_miss_chars.load(Ordering::Relaxed),
+				"retired": p.retired.load(Ordering::Relaxed),
+				// Every key is independent, so the expected time does not decrease
+				"eta_secs": 2f64.powi(p.bits() as i32) / rate,
+			})
+		})
+		.collect::<Vec<_>>();
+	// Keys are left out, the state may be served over the network
+	let found = data
+		.output
+		.found()
+		.iter()
+		.map(|f| {
+			json!({
+				"uid": f.uid,
+				"pattern": f.pattern,
+				"level": f.level,
+				"timestamp": f.timestamp,
+			})
+		})
+		.collect::<Vec<_>>();
+	let mut state = json!({
+		"tool": "find",
+		"paused": control::is_paused(),
+		"elapsed_secs": elapsed.as_secs(),
+		"attempts": attempts,
+		"rate": rate,
+		"matches": data.matches.load(Ordering::Relaxed),
+		"patterns": patterns,
+		"found": found,
+	});
+	if let Some(top) = &data.top {
+		let top = top
+			.entries()
+			.iter()
+			.map(|e| json!({ "score": e.score, "pattern": e.pattern, "uid": e.uid }))
+			.collect::<Vec<_>>();
+		state["top"] = json!(top);
+	}
+	state
+}
+
+/// The state of the level search, `offset` is the next offset which will be checked.
+pub fn level_state(
+	elapsed: Duration,
+	attempts: u64,
+	offset: u64,
+	want_level: u8,
+	best: &Level,
+) -> Value {
+	json!({
+		"tool": "level",
+		"paused": control::is_paused(),
+		"elapsed_secs": elapsed.as_secs(),
+		"attempts": attempts,
+		"rate": attempts as f64 / elapsed.as_secs_f64().max(0.001),
+		"offset": offset,
+		"want_level": want_level,
+		"best_level": best.level,
+		"best_offset": best.offset,
+	})
+}
@@ -0,0 +1,273 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{info, warn};
+use serde::Serialize;
+
+use crate::logfile;
+use crate::progress::human_count;
+use crate::FindPattern;
+
+/// A periodic statistics line for headless runs.
+#[derive(Debug, Serialize)]
+pub struct Stats {
+	pub event: &'static str,
+	pub elapsed_secs: u64,
+	/// Generated keys or checked offsets.
+	pub attempts: u64,
+	/// Attempts per second.
+	pub rate: f64,
+	/// The next offset which will be checked by the level tool.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub offset: Option<u64>,
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub best_level: Option<u8>,
+	/// The best near miss of the pattern search, like `pattern 3/5`.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub near_miss: Option<String>,
+	/// Attempts per second of every worker thread since the last statistics line.
+	#[serde(skip_serializing_if = "Option::is_none")]
+	pub thread_rates: Option<Vec<f64>>,
+}
+
+/// Decides when the next statistics line is due.
+#[derive(Debug)]
+pub struct StatsTimer {
+	interval: Option<Duration>,
+	start: Instant,
+	last: Instant,
+	json: bool,
+}
+
+impl Stats {
+	pub fn new(attempts: u64, elapsed: Duration) -> Self {
+		Self {
+			event: "stats",
+			elapsed_secs: elapsed.as_secs(),
+			attempts,
+			rate: attempts as f64 / elapsed.as_secs_f64().max(0.001),
+			offset: None,
+			best_level: None,
+			near_miss: None,
+			thread_rates: None,
+		}
+	}
+}
+
+impl StatsTimer {
+	/// Without an interval, no statistics are printed.
+	pub fn new(interval: Option<Duration>, json: bool) -> Self {
+		let now = Instant::now();
+		Self {
+			interval,
+			start: now,
+			last: now,
+			json,
+		}
+	}
+
+	pub fn elapsed(&self) -> Duration {
+		self.start.elapsed()
+	}
+
+	/// Returns `true` once per interval.
+	pub fn due(&mut self) -> bool {
+		match self.interval {
+			Some(interval) if self.last.elapsed() >= interval => {
+				self.last = Instant::now();
+				true
+			}
+			_ => false,
+		}
+	}
+
+	/// Prints the statistics to stderr.
+	pub fn print(&self, stats: &Stats) {
+		if self.json {
+			let line = serde_json::to_string(stats).expect("Stats are always serializable");
+			logfile::mirror(&line);
+			eprintln!("{}", line);
+			return;
+		}
+		let mut line = format!(
+			"STATS: elapsed {} attempts {} rate {}/s",
+			humantime::format_duration(Duration::from_secs(stats.elapsed_secs)),
+			stats.attempts,
+			human_count(stats.rate)
+		);
+		if let Some(offset) = stats.offset {
+			line.push_str(&format!(" offset {}", offset));
+		}
+		if let Some(level) = stats.best_level {
+			line.push_str(&format!(" best level {}", level));
+		}
+		if let Some(near_miss) = &stats.near_miss {
+			line.push_str(&format!(" near miss {}", near_miss));
+		}
+		if let Some(rates) = &stats.thread_rates {
+			let rates = rates.iter().map(|r| human_count(*r)).collect::<Vec<_>>();
+			line.push_str(&format!(" threads [{}]", rates.join(" ")));
+		}
+		logfile::mirror(&line);
+		eprintln!("{}", line);
+	}
+}
+
+/// Prints what a pattern search accomplished.
+pub fn print_summary(attempts: u64, elapsed: Duration, patterns: &[Arc<FindPattern>]) {
+	let threads = rayon::current_num_threads();
+	let rate = attempts as f64 / elapsed.as_secs_f64().max(0.001);
+	info!(
+		"Tested {} keys in {} ({} keys/s, {} keys/s per thread on {} threads)",
+		attempts,
+		humantime::format_duration(Duration::from_secs(elapsed.as_secs())),
+		human_count(rate),
+		human_count(rate / threads as f64),
+		threads
+	);
+	for p in patterns {
+		info!(
+			"Pattern {}: {} match(es)",
+			p.input,
+			p.matches.load(Ordering::Relaxed)
+		);
+	}
+}
+
+/// A counter per worker thread, of any thread pool.
+#[derive(Debug)]
+pub struct ThreadCounters {
+	/// Tells these counters apart in the slots of the threads.
+	id: u64,
+	counters: Vec<PaddedCounter>,
+	next: AtomicUsize,
+}
+
+static NEXT_COUNTERS_ID: AtomicU64 = AtomicU64::new(1);
+
+/// How many counters a thread remembers its slot in, the oldest one is forgotten first.
+const MAX_SLOTS: usize = 8;
+
+thread_local! {
+	/// The ids of the counters the thread counted for and its slot in them.
+	static SLOTS: RefCell<Vec<(u64, usize)>> = RefCell::new(Vec::new());
+}
+
+/// Keeps every counter in its own cache line, so threads do not slow each other down.
+#[derive(Debug, Default)]
+#[repr(align(64))]
+struct PaddedCounter(AtomicU64);
+
+impl ThreadCounters {
+	/// Counters for the threads of the global thread pool.
+	pub fn new() -> Self {
+		Self::with_threads(rayon::current_num_threads())
+	}
+
+	/// Counters for `threads` threads, e.g. of all pools with '--numa'.
+	pub fn with_threads(threads: usize) -> Self {
+		Self {
+			id: NEXT_COUNTERS_ID.fetch_add(1, Ordering::Relaxed),
+			counters: (0..threads.max(1))
+				.map(|_| PaddedCounter::default())
+				.collect(),
+			next: AtomicUsize::new(0),
+		}
+	}
+
+	/// Counts `n` for the calling thread. Threads get a slot when they count the first time.
+	pub fn add(&self, n: u64) {
+		let i = SLOTS.with(|slots| {
+			let mut slots = slots.borrow_mut();
+			if let Some(&(_, i)) = slots.iter().find(|(id, _)| *id == self.id) {
+				return i;
+			}
+			let i = self.next.fetch_add(1, Ordering::Relaxed);
+			if slots.len() == MAX_SLOTS {
+				slots.remove(0);
+			}
+			slots.push((self.id, i));
+			i
+		});
+		// More threads than expected share the counters
+		self.counters[i % self.counters.len()]
+			.0
+			.fetch_add(n, Ordering::Relaxed);
+	}
+
+	pub fn values(&self) -> Vec<u64> {
+		self.counters
+			.iter()
+			.map(|c| c.0.load(Ordering::Relaxed))
+			.collect()
+	}
+}
+
+impl Default for ThreadCounters {
+	fn default() -> Self {
+		Self::new()
+	}
+}
+
+/// A thread is slow if it reaches less than this part of the median rate.
+const SLOW_FRACTION: f64 = 0.75;
+/// Number of statistics intervals in a row a thread must be slow before it is reported.
+const SLOW_INTERVALS: u32 = 3;
+
+/// Computes the rate of every thread between two statistics lines and warns about threads
+/// which are slower than the others for a while.
+#[derive(Debug)]
+pub struct ThreadMonitor {
+	last: Vec<u64>,
+	last_time: Instant,
+	/// Number of intervals in a row every thread was slow.
+	slow: Vec<u32>,
+}
+
+impl ThreadMonitor {
+	pub fn new(counters: &ThreadCounters) -> Self {
+		let last = counters.values();
+		Self {
+			slow: vec![0; last.len()],
+			last,
+			last_time: Instant::now(),
+		}
+	}
+
+	/// The rates since the last call.
+	pub fn rates(&mut self, counters: &ThreadCounters) -> Vec<f64> {
+		let values = counters.values();
+		let secs = self.last_time.elapsed().as_secs_f64().max(0.001);
+		let rates = values
+			.iter()
+			.zip(&self.last)
+			.map(|(now, last)| (now - last) as f64 / secs)
+			.collect::<Vec<_>>();
+		self.last = values;
+		self.last_time = Instant::now();
+
+		let mut sorted = rates.clone();
+		sorted.sort_by(|a, b| a.total_cmp(b));
+		let median = sorted.get(sorted.len() / 2).copied().unwrap_or_default();
+		for (i, (rate, slow)) in rates.iter().zip(&mut self.slow).enumerate() {
+			if *rate >= median * SLOW_FRACTION {
+				*slow = 0;
+				continue;
+			}
+			*slow += 1;
+			// Only warn once until the thread catches up again
+			if *slow == SLOW_INTERVALS {
+				warn!(
+					"Thread {} runs at {}/s, {:.0}% of the median. Its core may be throttled, \
+					 busy with other work or an efficiency core",
+					i,
+					human_count(*rate),
+					rate / median * 100.0
+				);
+			}
+		}
+		rates
+	}
+}
@@ -0,0 +1,97 @@
+//! A local socket which reports the state of a running search.
+//!
+//! Every connection gets one json object with the current state, e.g. via
+//! `mah_ts_identity status --socket <path>` or `nc -U <path>`.
+
+use std::io::{Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use log::{debug, warn};
+use serde_json::Value;
+
+use crate::{Outcome, Result};
+
+#[derive(clap::Args, Debug)]
+pub struct StatusArgs {
+	#[clap(long)]
+	/// The '--status-socket' of the running instance.
+	socket: PathBuf,
+}
+
+/// Serves the latest state on a unix socket, the socket is removed when this is dropped.
+pub struct StatusSocket {
+	path: PathBuf,
+	state: Arc<Mutex<Value>>,
+}
+
+/// Binds the socket, the search serves it later with `StatusSocket::serve`.
+pub fn listen(path: &Path) -> std::result::Result<UnixListener, String> {
+	// A socket left over from a crashed run blocks binding
+	if UnixStream::connect(path).is_err() {
+		let _ = std::fs::remove_file(path);
+	}
+	UnixListener::bind(path)
+		.map_err(|e| format!("Failed to bind status socket {}: {}", path.display(), e))
+}
+
+impl StatusSocket {
+	pub fn bind(path: &Path) -> std::result::Result<Self, String> {
+		Ok(Self::serve(path, listen(path)?))
+	}
+
+	/// Answers on a socket which was bound with `listen`.
+	pub fn serve(path: &Path, listener: UnixListener) -> Self {
+		let state = Arc::new(Mutex::new(Value::Null));
+		let thread_state = state.clone();
+		std::thread::spawn(move || {
+			for stream in listener.incoming() {
+				let res = stream.and_then(|mut stream| {
+					let mut line = thread_state.lock().unwrap().to_string();
+					line.push('\n');
+					stream.write_all(line.as_bytes())
+				});
+				if let Err(e) = res {
+					debug!("Failed to answer on the status socket: {}", e);
+				}
+			}
+		});
+		Self {
+			path: path.to_path_buf(),
+			state,
+		}
+	}
+
+	pub fn update(&self, state: Value) {
+		*self.state.lock().unwrap() = state;
+	}
+}
+
+impl Drop for StatusSocket {
+	fn drop(&mut self) {
+		if let Err(e) = std::fs::remove_file(&self.path) {
+			warn!(
+				"Failed to remove status socket {}: {}",
+				self.path.display(),
+				e
+			);
+		}
+	}
+}
+
+pub fn tool_status(args: StatusArgs) -> Result {
+	let mut stream = UnixStream::connect(&args.socket).map_err(|e| {
+		format!(
+			"Failed to connect to status socket {}: {}",
+			args.socket.display(),
+			e
+		)
+	})?;
+	let mut state = String::new();
+	stream
+		.read_to_string(&mut state)
+		.map_err(|e| format!("Failed to read status: {}", e))?;
+	print!("{}", state);
+	Ok(Outcome::Success)
+}
@@ -0,0 +1,254 @@
+//! A local keystore which collects identities with their level and a label.
+
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use rusqlite::{params, Connection, OptionalExtension};
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+use zeroize::Zeroize;
+
+use crate::output::{self, Found};
+use crate::{Opts, Outcome, Result};
+
+const FILE_NAME: &str = "store.db";
+
+#[derive(clap::Subcommand, Debug)]
+pub enum StoreCommand {
+	/// Adds the identity given by '--identity' or raises the level of a stored one.
+	Add {
+		#[clap(long)]
+		/// A name for the identity, which can be used instead of the uid.
+		label: Option<String>,
+	},
+	/// Lists the uid, level and label of all stored identities.
+	List,
+	/// Prints a stored identity with its private key.
+	Show {
+		/// The uid or label.
+		name: String,
+	},
+	/// Removes an identity from the store.
+	Remove {
+		/// The uid or label.
+		name: String,
+	},
+	/// Prints a stored identity as '<counter>V<obfuscated key>', which the TeamSpeak client
+	/// imports.
+	Export {
+		/// The uid or label.
+		name: String,
+	},
+}
+
+#[derive(Debug)]
+pub struct Entry {
+	pub uid: String,
+	pub key: String,
+	/// The hashcash offset of the security level.
+	pub counter: u64,
+	pub level: u8,
+	pub label: Option<String>,
+	pub created: String,
+}
+
+impl Drop for Entry {
+	fn drop(&mut self) {
+		self.key.zeroize();
+	}
+}
+
+impl Entry {
+	pub fn new(key: &EccKeyPrivP256, counter: u64, label: Option<String>) -> Self {
+		let pub_key = key.to_pub();
+		Self {
+			uid: pub_key.get_uid().to_string(),
+			key: key.to_ts(),
+			counter,
+			level: get_hash_cash_level(&pub_key.to_ts(), counter),
+			label,
+			created: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+		}
+	}
+
+	pub fn from_found(found: &Found) -> Self {
+		Self {
+			uid: found.uid.clone(),
+			key: found.key.clone(),
+			counter: 0,
+			level: found.level,
+			label: None,
+			created: found.timestamp.clone(),
+		}
+	}
+}
+
+#[derive(Debug)]
+pub struct Store {
+	conn: Mutex<Connection>,
+}
+
+/// `<data dir>/mahtsidentity/store.db`
+pub fn default_path() -> Option<PathBuf> {
+	Some(dirs::data_dir()?.join("mahtsidentity").join(FILE_NAME))
+}
+
+impl Store {
+	/// Opens the store at the given path or the default path.
+	pub fn open(path: Option<&Path>) -> std::result::Result<Self, String> {
+		let path = match path {
+			Some(p) => p.to_path_buf(),
+			None => default_path().ok_or("Found no data directory for the store")?,
+		};
+		if let Some(dir) = path.parent().filter(|d| !d.as_os_str().is_empty()) {
+			std::fs::create_dir_all(dir)
+				.map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+		}
+		// Creates the file readable only by the owner, SQLite gives its journal the same mode
+		output::open_append(&path)
+			.map_err(|e| format!("Failed to create store {}: {}", path.display(), e))?;
+		let conn = Connection::open(&path)
+			.map_err(|e| format!("Failed to open store {}: {}", path.display(), e))?;
+		conn.execute_batch(
+			"CREATE TABLE IF NOT EXISTS identities (
+				uid TEXT PRIMARY KEY NOT NULL,
+				key TEXT NOT NULL,
+				counter INTEGER NOT NULL,
+				level INTEGER NOT NULL,
+				label TEXT UNIQUE,
+				created TEXT NOT NULL
+			);",
+		)
+		.map_err(|e| format!("Failed to initialize store {}: {}", path.display(), e))?;
+		Ok(Self {
+			conn: Mutex::new(conn),
+		})
+	}
+
+	/// Adds an identity, a stored identity keeps its label and only takes a higher level.
+	pub fn put(&self, entry: &Entry) -> std::result::Result<(), String> {
+		let conn = self.conn.lock().unwrap();
+		conn.execute(
+			"INSERT INTO identities (uid, key, counter, level, label, created)
+				VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+				ON CONFLICT (uid) DO UPDATE SET
+					counter = CASE WHEN excluded.level > level THEN excluded.counter ELSE counter END,
+					level = MAX(level, excluded.level),
+					label = COALESCE(label, excluded.label)",
+			params![
+				entry.uid,
+				entry.key,
+				entry.counter as i64,
+				entry.level,
+				entry.label,
+				entry.created,
+			],
+		)
+		.map_err(|e| format!("Failed to store {}: {}", entry.uid, e))?;
+		Ok(())
+	}
+
+	pub fn list(&self) -> std::result::Result<Vec<Entry>, String> {
+		let conn = self.conn.lock().unwrap();
+		let mut stmt = conn
+			.prepare(
+				"SELECT uid, key, counter, level, label, created FROM identities ORDER BY created",
+			)
+			.map_err(|e| e.to_string())?;
+		let rows = stmt.query_map([], read_entry).map_err(|e| e.to_string())?;
+		rows.collect::<rusqlite::Result<_>>()
+			.map_err(|e| format!("Failed to read store: {}", e))
+	}
+
+	/// Finds an identity by its uid or label.
+	pub fn get(&self, name: &str) -> std::result::Result<Option<Entry>, String> {
+		let conn = self.conn.lock().unwrap();
+		conn.query_row(
+			"SELECT uid, key, counter, level, label, created FROM identities
+				WHERE uid = ?1 OR label = ?1",
+			[name],
+			read_entry,
+		)
+		.optional()
+		.map_err(|e| format!("Failed to read store: {}", e))
+	}
+
+	/// Returns `false` if no identity has this uid or label.
+	pub fn remove(&self, name: &str) -> std::result::Result<bool, String> {
+		let conn = self.conn.lock().unwrap();
+		let changed = conn
+			.execute(
+				"DELETE FROM identities WHERE uid = ?1 OR label = ?1",
+				[name],
+			)
+			.map_err(|e| format!("Failed to remove {}: {}", name, e))?;
+		Ok(changed != 0)
+	}
+}
+
+fn read_entry(row: &rusqlite::Row) -> rusqlite::Result<Entry> {
+	Ok(Entry {
+		uid: row.get(0)?,
+		key: row.get(1)?,
+		counter: row.get::<_, i64>(2)? as u64,
+		level: row.get(3)?,
+		label: row.get(4)?,
+		created: row.get(5)?,
+	})
+}
+
+pub fn tool_store(command: StoreCommand, opts: &Opts) -> Result {
+	let store = Store::open(opts.store_path.as_deref())?;
+	let get = |name: &str| {
+		store
+			.get(name)?
+			.ok_or_else(|| format!("No identity {} in the store", name))
+	};
+	match command {
+		StoreCommand::Add { label } => {
+			let identity = opts
+				.identity
+				.as_deref()
+				.ok_or("Requires an identity (-i) to add")?;
+			let key =
+				EccKeyPrivP256::import_str(identity).map_err(|_| "Failed to read identity")?;
+			let entry = Entry::new(&key, opts.counter.unwrap_or(0), label);
+			store.put(&entry)?;
+			println!("UID: {} LEVEL: {}", entry.uid, entry.level);
+		}
+		StoreCommand::List => {
+			for entry in store.list()? {
+				println!(
+					"UID: {} LEVEL: {} LABEL: {}",
+					entry.uid,
+					entry.level,
+					entry.label.as_deref().unwrap_or("")
+				);
+			}
+		}
+		StoreCommand::Show { name } => {
+			let entry = get(&name)?;
+			println!("UID: {}", entry.uid);
+			if let Some(label) = &entry.label {
+				println!("LABEL: {}", label);
+			}
+			println!("LEVEL: {} OFFSET: {}", entry.level, entry.counter);
+			println!("CREATED: {}", entry.created);
+			println!("KEY: {}", entry.key);
+		}
+		StoreCommand::Remove { name } => {
+			if !store.remove(&name)? {
+				return Err(format!("No identity {} in the store", name));
+			}
+			println!("REMOVED: {}", name);
+		}
+		StoreCommand::Export { name } => {
+			let entry = get(&name)?;
+			let key = EccKeyPrivP256::import_str(&entry.key)
+				.map_err(|_| format!("The stored key of {} is invalid", entry.uid))?;
+			println!("KEY: {}V{}", entry.counter, key.to_ts_obfuscated());
+		}
+	}
+	Ok(Outcome::Success)
+}
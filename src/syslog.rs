@@ -0,0 +1,126 @@
+//! Sends status messages and results to syslog with '--syslog'.
+//!
+//! Messages go to the '/dev/log' socket, which journald also provides under systemd. Found
+//! identities are reported without their key unless '--syslog-keys' is given, keys in status
+//! messages are always left out.
+
+use std::os::unix::net::UnixDatagram;
+
+use log::Level;
+
+use crate::logfile::redact_keys;
+use crate::notify::{Event, Notifier};
+
+const IDENT: &str = "mahtsidentity";
+/// The 'user' facility.
+const FACILITY: u8 = 1;
+/// The severity of found identities and finished searches.
+const NOTICE: u8 = 5;
+
+#[derive(Debug)]
+pub struct Syslog {
+	socket: UnixDatagram,
+}
+
+impl Syslog {
+	pub fn connect() -> Result<Self, String> {
+		let socket = UnixDatagram::unbound()
+			.and_then(|s| s.connect("/dev/log").map(|_| s))
+			.map_err(|e| format!("Failed to connect to syslog at /dev/log: {}", e))?;
+		Ok(Self { socket })
+	}
+
+	/// Sends a message, errors are ignored so logging never stops the search.
+	pub fn send(&self, severity: u8, message: &str) {
+		let _ = self.socket.send(format_line(severity, message).as_bytes());
+	}
+
+	pub fn send_log(&self, level: Level, message: &str) {
+		let _ = self.socket.send(log_line(level, message).as_bytes());
+	}
+}
+
+fn format_line(severity: u8, message: &str) -> String {
+	format!(
+		"<{}>{}[{}]: {}",
+		FACILITY * 8 + severity,
+		IDENT,
+		std::process::id(),
+		message
+	)
+}
+
+/// A status message with its keys redacted.
+fn log_line(level: Level, message: &str) -> String {
+	let severity = match level {
+		Level::Error => 3,
+		Level::Warn => 4,
+		Level::Info => 6,
+		Level::Debug | Level::Trace => 7,
+	};
+	format_line(severity, &redact_keys(message))
+}
+
+fn event_message(event: &Event, keys: bool) -> String {
+	let mut message = event.describe();
+	if let (Event::Found(found), true) = (event, keys) {
+		message.push_str(&format!(", key {}", found.key));
+	}
+	message
+}
+
+/// Reports found identities and finished searches to syslog.
+pub struct SyslogNotifier {
+	pub syslog: Syslog,
+	/// Includes the private keys of found identities.
+	pub keys: bool,
+}
+
+impl Notifier for SyslogNotifier {
+	fn name(&self) -> String {
+		"syslog".to_string()
+	}
+
+	fn send(&self, event: &Event) -> Result<(), String> {
+		self.syslog.send(NOTICE, &event_message(event, self.keys));
+		Ok(())
+	}
+
+	fn retry(&self) -> bool {
+		false
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use tsproto_types::crypto::EccKeyPrivP256;
+
+	use super::*;
+	use crate::output::Found;
+
+	#[test]
+	fn status_messages_have_no_keys() {
+		let key = EccKeyPrivP256::create().to_ts();
+		let messages = [
+			format!("NEAR MISS: 3/5 Alice UID: abc= KEY: {}", key),
+			format!("  1. 30 bits of Alice UID: abc= KEY: {} ", key),
+			format!("KEY: {} KEY: {}", key, key),
+		];
+		for message in &messages {
+			let line = log_line(Level::Info, message);
+			assert!(!line.contains(&key), "{}", line);
+			assert!(line.contains("KEY: <redacted>"), "{}", line);
+		}
+	}
+
+	#[test]
+	fn found_identities_have_no_keys_by_default() {
+		let found = Found::new(&EccKeyPrivP256::create(), "Alice".to_string(), 1);
+		let event = Event::Found(found.clone());
+		let line = format_line(NOTICE, &event_message(&event, false));
+		assert!(line.contains(&found.uid));
+		assert!(!line.contains(&found.key));
+		assert!(!line.contains(&found.key_obfuscated));
+		assert!(event_message(&event, true).contains(&found.key));
+	}
+}
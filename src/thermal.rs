@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::control;
+
+/// How often the temperature is checked.
+const INTERVAL: Duration = Duration::from_secs(5);
+/// The workers run at full speed again once the temperature drops this far below the limit.
+const HYSTERESIS: f64 = 5.0;
+/// The cpu limit is never lowered below this percentage.
+const MIN_LIMIT: u32 = 10;
+/// The hwmon drivers of cpu sensors, other sensors like disks or gpus are ignored.
+#[cfg(target_os = "linux")]
+const CPU_SENSORS: &[&str] = &["coretemp", "k10temp", "zenpower", "cpu_thermal"];
+
+/// Starts a thread which lowers the cpu limit while the cpu is hotter than `max_temp` degrees
+/// Celsius and raises it again up to `max_limit` when it cooled down.
+pub fn spawn(max_temp: f64, max_limit: u32) {
+	if cpu_temperature().is_none() {
+		warn!("Found no temperature sensor, cannot throttle on temperature");
+		return;
+	}
+	std::thread::spawn(move || loop {
+		std::thread::sleep(INTERVAL);
+		let temp = match cpu_temperature() {
+			Some(temp) => temp,
+			None => continue,
+		};
+		let limit = control::cpu_limit();
+		if temp > max_temp && limit > MIN_LIMIT {
+			let new = (limit * 3 / 4).max(MIN_LIMIT);
+			info!("Cpu is at {:.0}°C, limiting cpu usage to {}%", temp, new);
+			control::set_cpu_limit(new);
+		} else if temp < max_temp - HYSTERESIS && limit < max_limit {
+			let new = (limit + 10).min(max_limit);
+			info!(
+				"Cpu cooled down to {:.0}°C, limiting cpu usage to {}%",
+				temp, new
+			);
+			control::set_cpu_limit(new);
+		}
+	});
+}
+
+/// Returns the highest temperature of all cpu sensors in degrees Celsius.
+#[cfg(target_os = "linux")]
+fn cpu_temperature() -> Option<f64> {
+	let mut max = None;
+	for entry in std::fs::read_dir("/sys/class/hwmon").ok()?.flatten() {
+		let driver = std::fs::read_to_string(entry.path().join("name")).unwrap_or_default();
+		if !CPU_SENSORS.contains(&driver.trim()) {
+			continue;
+		}
+		for file in std::fs::read_dir(entry.path())
+			.into_iter()
+			.flatten()
+			.flatten()
+		{
+			let name = file.file_name();
+			let name = name.to_string_lossy();
+			if !(name.starts_with("temp") && name.ends_with("_input")) {
+				continue;
+			}
+			// Values are in millidegrees
+			if let Some(temp) = std::fs::read_to_string(file.path())
+				.ok()
+				.and_then(|t| t.trim().parse::<f64>().ok())
+			{
+				let temp = temp / 1000.0;
+				if max.map_or(true, |max| temp > max) {
+					max = Some(temp);
+				}
+			}
+		}
+	}
+	max
+}
+
+#[cfg(not(target_os = "linux"))]
+fn cpu_temperature() -> Option<f64> {
+	None
+}
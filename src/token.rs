@@ -0,0 +1,48 @@
+//! The token which the HTTP interfaces require, so other web pages and users on the machine
+//! can not control a search or read its results.
+
+use rand_core::{OsRng, RngCore};
+use tiny_http::Request;
+
+#[derive(Debug)]
+pub struct Token(String);
+
+impl Token {
+	/// Takes the token from the environment variable `env`, or generates a random one.
+	pub fn from_env(env: &str) -> Self {
+		match std::env::var(env) {
+			Ok(token) if !token.is_empty() => Self(token),
+			_ => {
+				let mut bytes = [0; 24];
+				OsRng.fill_bytes(&mut bytes);
+				Self(base64::encode_config(bytes, base64::URL_SAFE_NO_PAD))
+			}
+		}
+	}
+
+	pub fn as_str(&self) -> &str {
+		&self.0
+	}
+
+	/// Checks the 'Authorization: Bearer <token>' header. Browsers do not send custom
+	/// headers to other sites without asking the server first, which tiny_http never allows.
+	pub fn accepts(&self, request: &Request) -> bool {
+		request
+			.headers()
+			.iter()
+			.filter(|h| h.field.equiv("Authorization"))
+			.any(|h| self.accepts_header(h.value.as_str()))
+	}
+
+	/// Checks the value of an authorization header, 'Bearer <token>'.
+	pub fn accepts_header(&self, value: &str) -> bool {
+		value.strip_prefix("Bearer ").map_or(false, |token| {
+			constant_time_eq(token.as_bytes(), self.0.as_bytes())
+		})
+	}
+}
+
+/// Compares secrets in a time which does not depend on where they differ.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+	a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
@@ -0,0 +1,226 @@
+//! The identities in the settings database of the local TeamSpeak 3 client.
+//!
+//! The client keeps all identities in the `Identities` table of its `settings.db` as one ini
+//! text:
+//!
+//! ```ini
+//! [Identities]
+//! 1/id=Name
+//! 1/identity="<counter>V<obfuscated key>"
+//! 1/nickname=Foo
+//! size=1
+//! ```
+
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{info, warn};
+use rusqlite::{params, Connection, OpenFlags, OptionalExtension};
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::{Opts, Outcome, Result};
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Client {
+	/// The TeamSpeak 3 client.
+	Ts3,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct InstallArgs {
+	#[clap(long, value_enum, default_value = "ts3")]
+	/// The client which gets the identity.
+	client: Client,
+
+	#[clap(long, value_name = "PATH")]
+	/// The settings database of the client, defaults to the one of the current user.
+	settings: Option<PathBuf>,
+
+	#[clap(long)]
+	/// The name of the identity in the client, defaults to '--nickname'. An identity with
+	/// this name is replaced.
+	name: Option<String>,
+}
+
+/// An identity of the client, the fields are kept as they are stored.
+#[derive(Clone, Debug, Default)]
+pub struct ClientIdentity {
+	pub fields: Vec<(String, String)>,
+}
+
+impl ClientIdentity {
+	pub fn get(&self, name: &str) -> Option<&str> {
+		self.fields
+			.iter()
+			.find(|(k, _)| k == name)
+			.map(|(_, v)| v.as_str())
+	}
+
+	fn set(&mut self, name: &str, value: String) {
+		match self.fields.iter_mut().find(|(k, _)| k == name) {
+			Some((_, v)) => *v = value,
+			None => self.fields.push((name.to_string(), value)),
+		}
+	}
+}
+
+/// The default location of the `settings.db` of the TeamSpeak 3 client.
+pub fn default_settings() -> Option<PathBuf> {
+	#[cfg(windows)]
+	let dir = dirs::config_dir()?.join("TS3Client");
+	#[cfg(target_os = "macos")]
+	let dir = dirs::data_dir()?.join("TeamSpeak 3");
+	#[cfg(all(unix, not(target_os = "macos")))]
+	let dir = dirs::home_dir()?.join(".ts3client");
+	Some(dir.join("settings.db"))
+}
+
+/// The settings file given on the command line or the default one.
+pub fn settings_path(path: Option<&Path>) -> std::result::Result<PathBuf, String> {
+	let path = match path {
+		Some(p) => p.to_path_buf(),
+		None => default_settings().ok_or("Found no TeamSpeak 3 settings directory")?,
+	};
+	if !path.exists() {
+		return Err(format!(
+			"{} does not exist, start the TeamSpeak 3 client once or pass '--settings'",
+			path.display()
+		));
+	}
+	Ok(path)
+}
+
+pub fn open(path: &Path) -> std::result::Result<Connection, String> {
+	Connection::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+/// Opens the database without changing it, also while the client is running.
+pub fn open_read_only(path: &Path) -> std::result::Result<Connection, String> {
+	Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)
+		.map_err(|e| format!("Failed to open {}: {}", path.display(), e))
+}
+
+/// Reads all identities from the settings database.
+pub fn read_identities(conn: &Connection) -> std::result::Result<Vec<ClientIdentity>, String> {
+	let value = conn
+		.query_row(
+			"SELECT value FROM Identities WHERE key = 'Identities'",
+			[],
+			|row| row.get::<_, String>(0),
+		)
+		.optional()
+		.map_err(|e| format!("Failed to read identities: {}", e))?;
+	Ok(value.as_deref().map(parse).unwrap_or_default())
+}
+
+fn parse(value: &str) -> Vec<ClientIdentity> {
+	let mut identities = Vec::<ClientIdentity>::new();
+	for line in value.lines().map(str::trim) {
+		let (key, value) = match line.split_once('=') {
+			Some(kv) => kv,
+			None => continue,
+		};
+		let (index, field) = match key.split_once('/') {
+			Some((i, f)) => match i.parse::<usize>() {
+				Ok(i) if i > 0 => (i, f),
+				_ => continue,
+			},
+			None => continue,
+		};
+		if identities.len() < index {
+			identities.resize_with(index, Default::default);
+		}
+		identities[index - 1].set(field, value.to_string());
+	}
+	identities.retain(|i| !i.fields.is_empty());
+	identities
+}
+
+fn format_identities(identities: &[ClientIdentity]) -> String {
+	let mut value = "[Identities]\n".to_string();
+	for (i, identity) in identities.iter().enumerate() {
+		for (field, v) in &identity.fields {
+			value.push_str(&format!("{}/{}={}\n", i + 1, field, v));
+		}
+	}
+	value.push_str(&format!("size={}\n", identities.len()));
+	value
+}
+
+/// Copies the database next to itself, so a broken client configuration can be restored.
+fn backup(path: &Path) -> std::result::Result<PathBuf, String> {
+	let secs = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or_default();
+	let mut name = path.as_os_str().to_owned();
+	name.push(format!(".{}.bak", secs));
+	let backup = PathBuf::from(name);
+	std::fs::copy(path, &backup)
+		.map_err(|e| format!("Failed to back up {}: {}", path.display(), e))?;
+	Ok(backup)
+}
+
+pub fn tool_install(args: InstallArgs, opts: &Opts) -> Result {
+	// The only client for now
+	let Client::Ts3 = args.client;
+	let identity = opts
+		.identity
+		.as_deref()
+		.ok_or("Requires an identity (-i) to install")?;
+	let key = EccKeyPrivP256::import_str(identity).map_err(|_| "Failed to read identity")?;
+	let name = args.name.unwrap_or_else(|| opts.nickname.clone());
+	let path = settings_path(args.settings.as_deref())?;
+	warn!("Close the TeamSpeak client first, it overwrites the identities when it exits");
+
+	let backup = backup(&path)?;
+	info!("Backed up {} to {}", path.display(), backup.display());
+	let conn = open(&path)?;
+	let mut identities = read_identities(&conn)?;
+	let index = match identities
+		.iter()
+		.position(|i| i.get("id") == Some(name.as_str()))
+	{
+		Some(i) => {
+			info!("Replacing the identity {}", name);
+			i
+		}
+		None => {
+			identities.push(ClientIdentity::default());
+			identities.len() - 1
+		}
+	};
+	let entry = &mut identities[index];
+	entry.set("id", name.clone());
+	entry.set(
+		"identity",
+		format!(
+			"\"{}V{}\"",
+			opts.counter.unwrap_or(0),
+			key.to_ts_obfuscated()
+		),
+	);
+	entry.set("nickname", opts.nickname.clone());
+
+	let timestamp = SystemTime::now()
+		.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs() as i64)
+		.unwrap_or_default();
+	conn.execute(
+		"UPDATE Identities SET value = ?1, timestamp = ?2 WHERE key = 'Identities'",
+		params![format_identities(&identities), timestamp],
+	)
+	.and_then(|changed| {
+		if changed == 0 {
+			conn.execute(
+				"INSERT INTO Identities (timestamp, key, value) VALUES (?2, 'Identities', ?1)",
+				params![format_identities(&identities), timestamp],
+			)
+		} else {
+			Ok(changed)
+		}
+	})
+	.map_err(|e| format!("Failed to write identities to {}: {}", path.display(), e))?;
+	println!("INSTALLED: {} UID: {}", name, key.to_pub().get_uid());
+	Ok(Outcome::Success)
+}
@@ -0,0 +1,256 @@
+//! Identities in the ini format of the TeamSpeak 3 client, used by its identity import and
+//! export.
+//!
+//! ```ini
+//! [Identity]
+//! id=Name
+//! identity="<counter>V<obfuscated key>"
+//! nickname=Foo
+//! ```
+
+use std::path::{Path, PathBuf};
+
+use log::info;
+use tsproto::algorithms::get_hash_cash_level;
+use tsproto_types::crypto::EccKeyPrivP256;
+use zeroize::Zeroizing;
+
+use crate::output;
+use crate::ts3client;
+use crate::{Outcome, Result};
+
+#[derive(clap::Args, Debug)]
+pub struct ImportArgs {
+	#[clap(long, value_name = "PATH", required_unless_present = "settings-db")]
+	/// An identity exported from the TeamSpeak 3 client.
+	ts3_ini: Option<PathBuf>,
+
+	#[clap(
+		long,
+		value_name = "PATH",
+		require_equals = true,
+		conflicts_with = "ts3-ini"
+	)]
+	/// Reads the identities stored in the settings database of the TeamSpeak 3 client, which
+	/// is only opened for reading. Lists them unless '--name' picks one. Defaults to the
+	/// database of the current user, another one is given like '--settings-db=PATH'.
+	settings_db: Option<Option<PathBuf>>,
+
+	#[clap(long, requires = "settings-db")]
+	/// The name of the identity to import from '--settings-db'.
+	name: Option<String>,
+}
+
+pub struct Ts3Identity {
+	pub id: Option<String>,
+	pub nickname: Option<String>,
+	pub key: EccKeyPrivP256,
+	/// The hashcash offset of the security level.
+	pub counter: u64,
+}
+
+/// Reads the identity from a TeamSpeak 3 ini file.
+pub fn read(path: &Path) -> std::result::Result<Ts3Identity, String> {
+	let content = std::fs::read_to_string(path)
+		.map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+	parse(&content).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Returns `true` if `content` looks like a TeamSpeak 3 identity ini file.
+pub fn is_ini(content: &str) -> bool {
+	content.lines().any(|l| l.trim() == "[Identity]")
+}
+
+pub fn parse(content: &str) -> std::result::Result<Ts3Identity, String> {
+	let mut in_identity = false;
+	let (mut id, mut nickname, mut identity) = (None, None, None);
+	for line in content.lines().map(str::trim) {
+		if line.starts_with('[') {
+			in_identity = line == "[Identity]";
+			continue;
+		}
+		let (key, value) = match line.split_once('=') {
+			Some((k, v)) if in_identity => (k.trim(), unquote(v.trim())),
+			_ => continue,
+		};
+		match key {
+			"id" => id = Some(value.to_string()),
+			"nickname" => nickname = Some(value.to_string()),
+			"identity" => identity = Some(value.to_string()),
+			_ => {}
+		}
+	}
+	let identity = identity.ok_or("No identity in the [Identity] section")?;
+	let (counter, key) = parse_identity(&identity)?;
+	Ok(Ts3Identity {
+		id: id.filter(|s| !s.is_empty()),
+		nickname: nickname.filter(|s| !s.is_empty()),
+		key,
+		counter,
+	})
+}
+
+/// Parses an identity string like `<counter>V<obfuscated key>`.
+pub fn parse_identity(identity: &str) -> std::result::Result<(u64, EccKeyPrivP256), String> {
+	let (counter, key) = identity
+		.split_once('V')
+		.ok_or("The identity has no security level counter")?;
+	let counter = counter
+		.parse()
+		.map_err(|_| format!("Invalid security level counter '{}'", counter))?;
+	// Deobfuscates the key
+	let key = EccKeyPrivP256::import_str(key).map_err(|_| "Failed to read identity")?;
+	Ok((counter, key))
+}
+
+fn unquote(value: &str) -> &str {
+	value
+		.strip_prefix('"')
+		.and_then(|v| v.strip_suffix('"'))
+		.unwrap_or(value)
+}
+
+pub fn tool_import(args: ImportArgs) -> Result {
+	let identity = match (&args.ts3_ini, &args.settings_db) {
+		(Some(path), _) => read(path)?,
+		(None, Some(path)) => match read_settings(path.as_deref(), args.name.as_deref())? {
+			Some(identity) => identity,
+			None => return Ok(Outcome::Success),
+		},
+		(None, None) => return Err("Requires '--ts3-ini' or '--settings-db'".to_string()),
+	};
+	let omega = identity.key.to_pub().to_ts();
+	if let Some(id) = &identity.id {
+		println!("ID: {}", id);
+	}
+	if let Some(nickname) = &identity.nickname {
+		println!("NICKNAME: {}", nickname);
+	}
+	let uid = identity.key.to_pub().get_uid().to_string();
+	println!("UID: {}", uid);
+	if let Some(art) = crate::randomart::render(&uid) {
+		println!("{}", art);
+	}
+	println!(
+		"LEVEL: {} OFFSET: {}",
+		get_hash_cash_level(&omega, identity.counter),
+		identity.counter
+	);
+	println!("KEY: {}", identity.key.to_ts());
+	Ok(Outcome::Success)
+}
+
+/// Reads the identity called `name` from the settings database, or lists all identities and
+/// returns `None` without a name.
+fn read_settings(
+	path: Option<&Path>,
+	name: Option<&str>,
+) -> std::result::Result<Option<Ts3Identity>, String> {
+	let path = ts3client::settings_path(path)?;
+	let conn = ts3client::open_read_only(&path)?;
+	let stored = ts3client::read_identities(&conn)?;
+	if stored.is_empty() {
+		return Err(format!("{} contains no identities", path.display()));
+	}
+	let parse_stored = |stored: &ts3client::ClientIdentity| {
+		let identity = stored
+			.get("identity")
+			.ok_or("The stored identity has no key")?;
+		parse_identity(unquote(identity))
+	};
+
+	let name = match name {
+		Some(name) => name,
+		None => {
+			for identity in &stored {
+				let id = unquote(identity.get("id").unwrap_or_default());
+				match parse_stored(identity) {
+					Ok((counter, key)) => {
+						let omega = key.to_pub().to_ts();
+						println!(
+							"NAME: {} UID: {} LEVEL: {}",
+							id,
+							key.to_pub().get_uid(),
+							get_hash_cash_level(&omega, counter)
+						);
+					}
+					Err(e) => println!("NAME: {} ERROR: {}", id, e),
+				}
+			}
+			info!("Pick an identity with '--name'");
+			return Ok(None);
+		}
+	};
+	let identity = stored
+		.iter()
+		.find(|i| i.get("id").map(unquote) == Some(name))
+		.ok_or_else(|| format!("{} has no identity called '{}'", path.display(), name))?;
+	let (counter, key) =
+		parse_stored(identity).map_err(|e| format!("Identity '{}': {}", name, e))?;
+	Ok(Some(Ts3Identity {
+		id: Some(name.to_string()),
+		nickname: identity
+			.get("nickname")
+			.map(|n| unquote(n).to_string())
+			.filter(|n| !n.is_empty()),
+		key,
+		counter,
+	}))
+}
+
+/// Formats an identity like the identity export of the TeamSpeak 3 client.
+pub fn to_ini(key: &EccKeyPrivP256, nickname: &str, counter: u64) -> String {
+	format!(
+		"[Identity]\nid={}\nidentity=\"{}V{}\"\nnickname={}\nphonetic_nickname=\n",
+		nickname,
+		counter,
+		key.to_ts_obfuscated(),
+		nickname
+	)
+}
+
+/// Writes an ini file which can be imported in the TeamSpeak 3 client.
+pub fn write(
+	path: &Path,
+	key: &EccKeyPrivP256,
+	nickname: &str,
+	counter: u64,
+) -> std::result::Result<(), String> {
+	let ini = Zeroizing::new(to_ini(key, nickname, counter));
+	output::write_private(path, ini.as_bytes())
+		.map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn ini_roundtrip() {
+		let key = EccKeyPrivP256::create();
+		let identity = parse(&to_ini(&key, "Foo", 42)).unwrap();
+		assert_eq!(identity.id.as_deref(), Some("Foo"));
+		assert_eq!(identity.nickname.as_deref(), Some("Foo"));
+		assert_eq!(identity.counter, 42);
+		assert_eq!(identity.key.to_ts(), key.to_ts());
+
+		let ini = to_ini(&identity.key, "Foo", identity.counter);
+		assert_eq!(ini, to_ini(&key, "Foo", 42));
+		assert!(is_ini(&ini));
+	}
+
+	#[test]
+	fn other_sections_are_ignored() {
+		let key = EccKeyPrivP256::create();
+		let ini = format!(
+			"[Other]\nidentity=\"1V{}\"\n[Identity]\nidentity=\"7V{}\"\nnickname=\n",
+			EccKeyPrivP256::create().to_ts_obfuscated(),
+			key.to_ts_obfuscated()
+		);
+		let identity = parse(&ini).unwrap();
+		assert_eq!(identity.counter, 7);
+		assert_eq!(identity.key.to_ts(), key.to_ts());
+		assert_eq!(identity.nickname, None);
+		assert!(parse("[Identity]\nid=Foo\n").is_err());
+	}
+}
@@ -0,0 +1,276 @@
+//! An interactive terminal dashboard for the pattern search.
+//!
+//! Keys: `p` pauses and resumes, `a` adds a pattern, `q` stops the search.
+
+use std::io::Stdout;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::execute;
+use crossterm::terminal::{
+	disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+};
+use ratatui::backend::CrosstermBackend;
+use ratatui::layout::{Constraint, Direction, Layout, Rect};
+use ratatui::style::{Color, Style};
+use ratatui::widgets::{Block, Borders, LineGauge, List, ListItem, Paragraph, Wrap};
+use ratatui::{Frame, Terminal};
+
+use crate::progress::{human_count, short_duration};
+use crate::{control, miss_log, FindPattern, RunData};
+
+type Backend = CrosstermBackend<Stdout>;
+
+const FRAME_TIME: Duration = Duration::from_millis(250);
+/// Number of found identities which are shown.
+const RECENT_MATCHES: usize = 10;
+
+struct App<'a> {
+	data: &'a RunData,
+	start: Instant,
+	/// Per thread attempts of the last frame, to compute the current rates.
+	last_threads: Vec<u64>,
+	last_frame: Instant,
+	thread_rates: Vec<f64>,
+	/// The pattern which is typed after pressing `a`.
+	input: Option<String>,
+	/// The result of the last key press, e.g. an invalid pattern.
+	message: String,
+}
+
+/// Shows the dashboard until the search is `done` or stopped with `q`.
+pub fn run(data: &RunData, done: &AtomicBool) -> Result<(), String> {
+	enable_raw_mode().map_err(|e| format!("Failed to set up the terminal: {}", e))?;
+	let mut stdout = std::io::stdout();
+	if let Err(e) = execute!(stdout, EnterAlternateScreen) {
+		let _ = disable_raw_mode();
+		return Err(format!("Failed to set up the terminal: {}", e));
+	}
+	let res = Terminal::new(CrosstermBackend::new(stdout))
+		.map_err(|e| format!("Failed to set up the terminal: {}", e))
+		.and_then(|mut terminal| {
+			let res = event_loop(&mut terminal, data, done);
+			let _ = terminal.show_cursor();
+			res
+		});
+	let _ = execute!(std::io::stdout(), LeaveAlternateScreen);
+	let _ = disable_raw_mode();
+	res
+}
+
+fn event_loop(
+	terminal: &mut Terminal<Backend>,
+	data: &RunData,
+	done: &AtomicBool,
+) -> Result<(), String> {
+	let mut app = App {
+		data,
+		start: Instant::now(),
+		last_threads: data.thread_attempts.values(),
+		last_frame: Instant::now(),
+		thread_rates: Vec::new(),
+		input: None,
+		message: String::new(),
+	};
+	while !done.load(Ordering::Relaxed) {
+		app.update_rates();
+		terminal
+			.draw(|f| app.draw(f))
+			.map_err(|e| format!("Failed to draw: {}", e))?;
+		let deadline = Instant::now() + FRAME_TIME;
+		while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+			if !event::poll(timeout).map_err(|e| format!("Failed to read input: {}", e))? {
+				break;
+			}
+			if let Event::Key(key) =
+				event::read().map_err(|e| format!("Failed to read input: {}", e))?
+			{
+				// Raw mode swallows ctrl-c, so there is no signal
+				if key.kind == KeyEventKind::Press
+					&& (key.code == KeyCode::Char('c')
+						&& key.modifiers.contains(KeyModifiers::CONTROL)
+						|| app.handle_key(key.code))
+				{
+					data.stop.store(true, Ordering::Relaxed);
+					return Ok(());
+				}
+			}
+		}
+	}
+	Ok(())
+}
+
+impl App<'_> {
+	fn update_rates(&mut self) {
+		let threads = self.data.thread_attempts.values();
+		let secs = self.last_frame.elapsed().as_secs_f64().max(0.001);
+		self.thread_rates = threads
+			.iter()
+			.zip(&self.last_threads)
+			.map(|(now, last)| (now - last) as f64 / secs)
+			.collect();
+		self.last_threads = threads;
+		self.last_frame = Instant::now();
+	}
+
+	/// Returns `true` if the search should be stopped.
+	fn handle_key(&mut self, code: KeyCode) -> bool {
+		if let Some(input) = &mut self.input {
+			match code {
+				KeyCode::Enter => {
+					let input = self.input.take().unwrap_or_default();
+					self.add_pattern(&input);
+				}
+				KeyCode::Esc => self.input = None,
+				KeyCode::Backspace => {
+					input.pop();
+				}
+				KeyCode::Char(c) => input.push(c),
+				_ => {}
+			}
+			return false;
+		}
+		match code {
+			KeyCode::Char('q') => return true,
+			KeyCode::Char('p') => {
+				let paused = !control::is_paused();
+				control::set_paused(paused);
+				self.message = if paused { "Paused" } else { "Resumed" }.to_string();
+			}
+			KeyCode::Char('a') => {
+				self.input = Some(String::new());
+				self.message.clear();
+			}
+			_ => {}
+		}
+		false
+	}
+
+	fn add_pattern(&mut self, input: &str) {
+		self.message = match FindPattern::parse(input) {
+			Ok(mut pattern) => {
+				pattern.since = self.data.attempts.load(Ordering::Relaxed);
+				if self.data.patterns.add(pattern) {
+					format!("Added pattern {}", input)
+				} else {
+					format!("Already searching for {}", input)
+				}
+			}
+			Err(e) => e,
+		};
+	}
+
+	fn draw(&self, f: &mut Frame<Backend>) {
+		let patterns = self.data.patterns.read();
+		let chunks = Layout::default()
+			.direction(Direction::Vertical)
+			.constraints([
+				Constraint::Length(3),
+				Constraint::Length(patterns.len() as u16 + 2),
+				Constraint::Length(4),
+				Constraint::Min(3),
+				Constraint::Length(3),
+			])
+			.split(f.size());
+
+		let elapsed = self.start.elapsed().as_secs_f64();
+		let attempts = self.data.attempts.load(Ordering::Relaxed);
+		let rate: f64 = self.thread_rates.iter().sum();
+		let mut summary = format!(
+			"{} keys, {} keys/s, {} found, running for {}",
+			human_count(attempts as f64),
+			human_count(rate),
+			self.data.matches.load(Ordering::Relaxed),
+			short_duration(elapsed)
+		);
+		if control::is_paused() {
+			summary.push_str(" (paused)");
+		}
+		f.render_widget(
+			Paragraph::new(summary).block(Block::default().borders(Borders::ALL).title("Search")),
+			chunks[0],
+		);
+
+		let block = Block::default()
+			.borders(Borders::ALL)
+			.title("Chance to have found a match by now");
+		let inner = block.inner(chunks[1]);
+		f.render_widget(block, chunks[1]);
+		for (i, p) in patterns.iter().enumerate() {
+			if i as u16 >= inner.height {
+				break;
+			}
+			let area = Rect {
+				y: inner.y + i as u16,
+				height: 1,
+				..inner
+			};
+			f.render_widget(pattern_gauge(p, attempts, rate), area);
+		}
+		drop(patterns);
+
+		let threads = self
+			.thread_rates
+			.iter()
+			.enumerate()
+			.map(|(i, r)| format!("#{} {}/s", i, human_count(*r)))
+			.collect::<Vec<_>>()
+			.join("  ");
+		f.render_widget(
+			Paragraph::new(threads)
+				.wrap(Wrap { trim: true })
+				.block(Block::default().borders(Borders::ALL).title("Threads")),
+			chunks[2],
+		);
+
+		let found = self.data.output.found();
+		let recent = found
+			.iter()
+			.rev()
+			.take(RECENT_MATCHES)
+			.map(|f| ListItem::new(format!("{}  {}  {}", f.timestamp, f.pattern, f.uid)))
+			.collect::<Vec<_>>();
+		f.render_widget(
+			List::new(recent).block(
+				Block::default()
+					.borders(Borders::ALL)
+					.title("Recent matches"),
+			),
+			chunks[3],
+		);
+
+		let footer = match &self.input {
+			Some(input) => format!("Pattern: {}_  (enter to add, esc to cancel)", input),
+			None if self.message.is_empty() => {
+				"p: pause/resume  a: add pattern  q: quit".to_string()
+			}
+			None => format!(
+				"{}  (p: pause/resume  a: add pattern  q: quit)",
+				self.message
+			),
+		};
+		f.render_widget(
+			Paragraph::new(footer).block(Block::default().borders(Borders::ALL)),
+			chunks[4],
+		);
+	}
+}
+
+/// The chance that the pattern matched at least once, with the expected time per match.
+fn pattern_gauge(p: &FindPattern, attempts: u64, rate: f64) -> LineGauge<'static> {
+	let tries = attempts.saturating_sub(p.since) as f64;
+	let chance = -(tries * miss_log(p.bits())).exp_m1();
+	let mut label = format!("{} ({} found", p.input, p.matches.load(Ordering::Relaxed));
+	if p.retired.load(Ordering::Relaxed) {
+		label.push_str(", done)");
+	} else {
+		// Every key is independent, so the expected time does not decrease
+		let eta = 2f64.powi(p.bits() as i32) / rate;
+		label.push_str(&format!(", ~{} per match)", short_duration(eta)));
+	}
+	LineGauge::default()
+		.gauge_style(Style::default().fg(Color::Green))
+		.ratio(chance.clamp(0.0, 1.0))
+		.label(label)
+}
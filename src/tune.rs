@@ -0,0 +1,110 @@
+//! Picks the thread count and batch size at startup by trying a few of them.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use log::{debug, info};
+use rayon::prelude::*;
+use tsproto_types::crypto::EccKeyPrivP256;
+
+use crate::bench;
+use crate::sha1x;
+
+/// How long every configuration is measured.
+const PROBE: Duration = Duration::from_millis(500);
+/// The level search batch sizes which are tried, in offsets.
+const BATCH_SIZES: [u64; 3] = [1 << 16, 1 << 19, 1 << 22];
+
+/// The fastest configuration found.
+#[derive(Clone, Copy, Debug)]
+pub struct Tuned {
+	pub threads: usize,
+	/// Only measured for the level search.
+	pub batch_size: Option<u64>,
+}
+
+/// Measures the pattern search, or the level search if `level` is set, with half to all of
+/// the logical cpus.
+pub fn autotune(level: bool) -> Result<Tuned, String> {
+	let cpus = std::thread::available_parallelism().map_or(1, |n| n.get());
+	let mut counts = vec![(cpus / 2).max(1), (cpus * 3 / 4).max(1), cpus];
+	counts.dedup();
+	info!(
+		"Tuning the thread count{}, this takes a few seconds",
+		if level { " and batch size" } else { "" }
+	);
+
+	let omega = EccKeyPrivP256::create().to_pub().to_ts();
+	let midstate = sha1x::Midstate::new(&omega);
+	let mut best = (0.0, cpus);
+	for threads in counts {
+		let pool = build_pool(threads)?;
+		let rate = if level {
+			bench::hashcash_rate(&pool, PROBE, &omega)
+		} else {
+			bench::measure(&pool, PROBE, |_| bench::candidate())
+		};
+		debug!("{} threads: {:.0} per second", threads, rate);
+		if rate > best.0 {
+			best = (rate, threads);
+		}
+	}
+	let threads = best.1;
+
+	let mut batch_size = None;
+	if level {
+		let pool = build_pool(threads)?;
+		let mut best = (0.0, BATCH_SIZES[0]);
+		for size in BATCH_SIZES {
+			let rate = batch_rate(&pool, &midstate, size);
+			debug!("Batches of {}: {:.0} offsets per second", size, rate);
+			if rate > best.0 {
+				best = (rate, size);
+			}
+		}
+		batch_size = Some(best.1);
+	}
+
+	match batch_size {
+		Some(size) => info!(
+			"Autotune picked {} threads and batches of {}",
+			threads, size
+		),
+		None => info!("Autotune picked {} threads", threads),
+	}
+	Ok(Tuned {
+		threads,
+		batch_size,
+	})
+}
+
+fn build_pool(threads: usize) -> Result<rayon::ThreadPool, String> {
+	rayon::ThreadPoolBuilder::new()
+		.num_threads(threads)
+		.build()
+		.map_err(|e| format!("Failed to create thread pool: {}", e))
+}
+
+/// Checked offsets per second, with batches split up like the level search does.
+fn batch_rate(pool: &rayon::ThreadPool, midstate: &sha1x::Midstate, size: u64) -> f64 {
+	let lanes = sha1x::LANES as u64;
+	let start = Instant::now();
+	let mut offsets = 0;
+	pool.install(|| {
+		while start.elapsed() < PROBE {
+			let first = offsets;
+			black_box(
+				(0..(size + lanes - 1) / lanes)
+					.into_par_iter()
+					.map(|chunk| {
+						let levels = midstate
+							.levels(&std::array::from_fn(|i| first + chunk * lanes + i as u64));
+						levels.into_iter().max()
+					})
+					.max(),
+			);
+			offsets += size;
+		}
+	});
+	offsets as f64 / start.elapsed().as_secs_f64()
+}
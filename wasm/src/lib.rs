@@ -0,0 +1,142 @@
+//! The pattern search for WebAssembly, so a browser page can generate vanity identities
+//! without the private key ever leaving the machine.
+//!
+//! This only uses pure Rust crypto and runs on one thread. Call `find` repeatedly with a small
+//! number of attempts, ideally in a web worker, to keep the page responsive:
+//!
+//! ```js
+//! import init, { find } from "./pkg/mah_ts_identity_wasm.js";
+//! await init();
+//! let identity;
+//! while (!(identity = find("Foo", 10000))) {}
+//! console.log(identity.uid, identity.key);
+//! ```
+
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use p256::SecretKey;
+use rand_core::OsRng;
+use sha1::{Digest, Sha1};
+use wasm_bindgen::prelude::*;
+
+/// Every uid character has 6 bits, 28 characters hold the 160 bits of the hash.
+const MAX_PATTERN_LEN: usize = 27;
+
+#[wasm_bindgen]
+pub struct Identity {
+	uid: String,
+	key: String,
+}
+
+#[wasm_bindgen]
+impl Identity {
+	/// The base64 uid.
+	#[wasm_bindgen(getter)]
+	pub fn uid(&self) -> String {
+		self.uid.clone()
+	}
+
+	/// The private key in the format of TeamSpeak, which `--identity` accepts.
+	#[wasm_bindgen(getter)]
+	pub fn key(&self) -> String {
+		self.key.clone()
+	}
+}
+
+/// Generates up to `attempts` keys and returns the first one whose uid starts with the
+/// pattern. Use an '_' as a wildcard.
+#[wasm_bindgen]
+pub fn find(pattern: &str, attempts: u32) -> Result<Option<Identity>, JsError> {
+	check_pattern(pattern)?;
+	for _ in 0..attempts {
+		let secret = SecretKey::random(&mut OsRng);
+		let point = secret.public_key().to_encoded_point(false);
+		let (x, y) = (
+			point.x().expect("Uncompressed point"),
+			point.y().expect("Uncompressed point"),
+		);
+		let omega = base64::encode(der_sequence(&[
+			der_bit_string(0x00),
+			der_integer(&[32]),
+			der_integer(x),
+			der_integer(y),
+		]));
+		let uid = base64::encode(Sha1::digest(omega.as_bytes()));
+		if matches(&uid, pattern) {
+			let key = base64::encode(der_sequence(&[
+				der_bit_string(0x80),
+				der_integer(&[32]),
+				der_integer(x),
+				der_integer(y),
+				der_integer(&secret.to_be_bytes()),
+			]));
+			return Ok(Some(Identity { uid, key }));
+		}
+	}
+	Ok(None)
+}
+
+/// The expected number of attempts to find the pattern.
+#[wasm_bindgen]
+pub fn expected_attempts(pattern: &str) -> Result<f64, JsError> {
+	check_pattern(pattern)?;
+	let chars = pattern.chars().filter(|c| *c != '_').count();
+	Ok(2f64.powi(6 * chars as i32))
+}
+
+fn check_pattern(pattern: &str) -> Result<(), JsError> {
+	if pattern.len() > MAX_PATTERN_LEN {
+		return Err(JsError::new("The pattern is too long"));
+	}
+	if let Some(c) = pattern
+		.chars()
+		.find(|c| !c.is_ascii_alphanumeric() && !"+/_".contains(*c))
+	{
+		return Err(JsError::new(&format!("'{}' can not be part of a uid", c)));
+	}
+	Ok(())
+}
+
+fn matches(uid: &str, pattern: &str) -> bool {
+	uid.bytes()
+		.zip(pattern.bytes())
+		.all(|(u, p)| p == b'_' || u == p)
+}
+
+// The key format of libtomcrypt, which TeamSpeak uses
+
+fn der(tag: u8, content: &[u8]) -> Vec<u8> {
+	let mut data = vec![tag];
+	if content.len() < 0x80 {
+		data.push(content.len() as u8);
+	} else {
+		data.push(0x81);
+		data.push(content.len() as u8);
+	}
+	data.extend_from_slice(content);
+	data
+}
+
+fn der_sequence(elements: &[Vec<u8>]) -> Vec<u8> {
+	der(0x30, &elements.concat())
+}
+
+/// A single byte of flags, the 0x80 bit marks a private key.
+fn der_bit_string(flags: u8) -> Vec<u8> {
+	// 7 unused bits
+	der(0x03, &[0x07, flags])
+}
+
+/// A positive big endian integer.
+fn der_integer(bytes: &[u8]) -> Vec<u8> {
+	let start = bytes
+		.iter()
+		.position(|b| *b != 0)
+		.unwrap_or(bytes.len() - 1);
+	let bytes = &bytes[start..];
+	let mut content = Vec::with_capacity(bytes.len() + 1);
+	if bytes[0] & 0x80 != 0 {
+		content.push(0);
+	}
+	content.extend_from_slice(bytes);
+	der(0x02, &content)
+}